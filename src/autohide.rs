@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+use crate::config::AutohideConfig;
+
+// Time between animation steps while sliding.
+const FRAME: Duration = Duration::from_millis(16);
+
+// How far the panel is pushed below the screen edge, and where it is heading. The offset is in device pixels:
+// 0 is fully shown, the `max` passed to `step` leaves only the trigger strip on screen.
+pub struct Autohide {
+    cfg: AutohideConfig,
+    offset: u16,
+    hidden: bool,
+    hide_at: Option<Instant>,
+    last_step: Instant,
+}
+
+impl Autohide {
+    // Starts shown, with a hide scheduled in case the pointer is not over the panel.
+    pub fn new(cfg: AutohideConfig) -> Self {
+        let now = Instant::now();
+        Self { cfg, offset: 0, hidden: false, hide_at: cfg.enabled.then(|| now + Duration::from_millis(cfg.delay)), last_step: now }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.cfg.enabled
+    }
+
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    // The pointer left the panel: hide after the delay unless it comes back first.
+    pub fn pointer_left(&mut self) {
+        if self.cfg.enabled && self.hide_at.is_none() && !self.hidden {
+            self.hide_at = Some(Instant::now() + Duration::from_millis(self.cfg.delay));
+        }
+    }
+
+    pub fn reveal(&mut self) {
+        self.hide_at = None;
+        if self.hidden {
+            self.hidden = false;
+            self.last_step = Instant::now();
+        }
+    }
+
+    // Whether the scheduled hide is due, so the caller can check what should keep the panel up first.
+    pub fn hide_due(&self, now: Instant) -> bool {
+        self.hide_at.is_some_and(|at| at <= now)
+    }
+
+    // Something keeps the panel up for now; look again after another delay.
+    pub fn postpone(&mut self) {
+        if self.cfg.enabled {
+            self.hide_at = Some(Instant::now() + Duration::from_millis(self.cfg.delay));
+        }
+    }
+
+    // How long the event loop may sleep before `step` has something to do.
+    pub fn timeout(&self, now: Instant, max: u16) -> Option<Duration> {
+        if self.moving(max) {
+            return Some(FRAME);
+        }
+        self.hide_at.map(|at| at.saturating_duration_since(now))
+    }
+
+    fn moving(&self, max: u16) -> bool {
+        let target = if self.hidden { max } else { 0 };
+        self.offset != target
+    }
+
+    // Starts a due hide and advances the slide; returns the new offset when the panel has to move.
+    pub fn step(&mut self, now: Instant, max: u16) -> Option<u16> {
+        if self.hide_due(now) {
+            self.hide_at = None;
+            self.hidden = true;
+            self.last_step = now;
+        }
+        if !self.moving(max) { return None; }
+
+        let target = if self.hidden { max } else { 0 };
+        let offset = if self.cfg.animate && self.cfg.duration > 0 {
+            let elapsed = now.saturating_duration_since(self.last_step).as_millis() as u64;
+            let distance = ((max as u64 * elapsed.max(1)) / self.cfg.duration).max(1).min(u16::MAX as u64) as u16;
+            if self.hidden { self.offset.saturating_add(distance).min(target) } else { self.offset.saturating_sub(distance) }
+        } else {
+            target
+        };
+        self.last_step = now;
+        self.offset = offset;
+        Some(offset)
+    }
+}
@@ -0,0 +1,118 @@
+use image::{imageops::FilterType, RgbaImage};
+use x11rb::connection::RequestConnection;
+use x11rb::protocol::xproto::*;
+use x11rb::rust_connection::RustConnection;
+
+use crate::color::{self, Color};
+use crate::config::{expand_home, BackgroundConfig, ImageMode};
+
+// The panel's backdrop as premultiplied BGRA: the configured image or the part of the wallpaper under the
+// panel, with the tint over it. None when there is no image, or "root" and no wallpaper is set.
+#[allow(clippy::too_many_arguments)]
+pub fn compose(
+    conn: &RustConnection, root: Window, cfg: &BackgroundConfig, tint: Color,
+    width: u16, height: u16, panel_y: i16, opaque: bool,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let (w, h) = (width as usize, height as usize);
+    let mut data = match cfg.image.as_deref() {
+        None => return Ok(None),
+        Some("root") => match wallpaper(conn, root, width, height, panel_y)? {
+            Some(data) => data,
+            None => return Ok(None),
+        },
+        Some(path) => {
+            let path = expand_home(path);
+            let img = image::open(&path).map_err(|e| format!("{}: {}", path, e))?.to_rgba8();
+            from_image(&img, cfg.mode, w, h)
+        }
+    };
+
+    let tint = tint.premultiplied();
+    for px in data.chunks_exact_mut(4) {
+        color::over(px, tint);
+        // Nothing shows through a window without alpha; keep what the tint and image make of it.
+        if opaque { px[3] = 255; }
+    }
+    Ok(Some(data))
+}
+
+// Uploads a premultiplied BGRA image in as few PutImage requests as fit the maximum request size.
+pub fn upload(
+    conn: &RustConnection, target: Drawable, gc: Gcontext, depth: u8, width: u16, data: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stride = width as usize * 4;
+    if stride == 0 { return Ok(()); }
+    let rows = ((conn.maximum_request_bytes() - 32) / stride).max(1);
+    for (i, chunk) in data.chunks(rows * stride).enumerate() {
+        let n = chunk.len() / stride;
+        conn.put_image(ImageFormat::Z_PIXMAP, target, gc, width, n as u16, 0, (i * rows) as i16, 0, depth, chunk)?;
+    }
+    Ok(())
+}
+
+fn from_image(img: &RgbaImage, mode: ImageMode, w: usize, h: usize) -> Vec<u8> {
+    let mut data = vec![0u8; w * h * 4];
+    let (src_w, src_h) = (img.width() as usize, img.height() as usize);
+    if src_w == 0 || src_h == 0 { return data; }
+    let resized;
+    let (img, (off_x, off_y)) = match mode {
+        ImageMode::Stretch => {
+            resized = image::imageops::resize(img, w as u32, h as u32, FilterType::Triangle);
+            (&resized, (0, 0))
+        }
+        ImageMode::Tile => (img, (0, 0)),
+        ImageMode::Center => (img, ((src_w as i64 - w as i64) / 2, (src_h as i64 - h as i64) / 2)),
+    };
+    let (img_w, img_h) = (img.width() as i64, img.height() as i64);
+    for y in 0..h {
+        for x in 0..w {
+            let (mut sx, mut sy) = (x as i64 + off_x, y as i64 + off_y);
+            if mode == ImageMode::Tile {
+                sx %= img_w;
+                sy %= img_h;
+            }
+            if sx < 0 || sy < 0 || sx >= img_w || sy >= img_h { continue; }
+            let [r, g, b, a] = img.get_pixel(sx as u32, sy as u32).0;
+            let [b, g, r, a] = Color(u32::from_be_bytes([a, r, g, b])).premultiplied();
+            data[(y * w + x) * 4..(y * w + x) * 4 + 4].copy_from_slice(&[b as u8, g as u8, r as u8, a as u8]);
+        }
+    }
+    data
+}
+
+// Reads the wallpaper pixmap that xsetroot-style setters (feh, nitrogen, hsetroot) publish on the root window,
+// tiling it when it is smaller than the screen.
+fn wallpaper(conn: &RustConnection, root: Window, width: u16, height: u16, panel_y: i16) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let mut pixmap = None;
+    for name in [&b"_XROOTPMAP_ID"[..], b"ESETROOT_PMAP_ID"] {
+        let atom = conn.intern_atom(true, name)?.reply()?.atom;
+        if atom == x11rb::NONE { continue; }
+        let reply = conn.get_property(false, root, atom, AtomEnum::PIXMAP, 0, 1)?.reply()?;
+        pixmap = reply.value32().and_then(|mut i| i.next()).filter(|&p| p != x11rb::NONE);
+        if pixmap.is_some() { break; }
+    }
+    let Some(pixmap) = pixmap else { return Ok(None) };
+    // The property can outlive its pixmap when the setter exits without cleaning up.
+    let Ok(geometry) = conn.get_geometry(pixmap)?.reply() else { return Ok(None) };
+    if !matches!(geometry.depth, 24 | 32) || geometry.width == 0 || geometry.height == 0 { return Ok(None); }
+
+    let (w, h) = (width as usize, height as usize);
+    let (pm_w, pm_h) = (geometry.width as usize, geometry.height as usize);
+    let covers = panel_y >= 0 && pm_w >= w && pm_h >= panel_y as usize + h;
+    // Only the strip under the panel when the pixmap spans the screen; a tile is read whole.
+    let (src_y, src_w, src_h) = if covers { (panel_y, width, height) } else { (0, geometry.width, geometry.height) };
+    let image = conn.get_image(ImageFormat::Z_PIXMAP, pixmap, 0, src_y, src_w, src_h, !0)?.reply()?;
+    let stride = src_w as usize * 4;
+    if image.data.len() < stride * src_h as usize { return Ok(None); }
+
+    let mut data = vec![0u8; w * h * 4];
+    for y in 0..h {
+        let sy = if covers { y } else { (y + panel_y.max(0) as usize) % pm_h };
+        for x in 0..w {
+            let sx = if covers { x } else { x % pm_w };
+            let src = &image.data[sy * stride + sx * 4..sy * stride + sx * 4 + 3];
+            data[(y * w + x) * 4..(y * w + x) * 4 + 4].copy_from_slice(&[src[0], src[1], src[2], 255]);
+        }
+    }
+    Ok(Some(data))
+}
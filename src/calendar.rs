@@ -0,0 +1,319 @@
+use std::collections::HashSet;
+
+use chrono::{Datelike, Months, NaiveDate, Weekday};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::*;
+use x11rb::rust_connection::RustConnection;
+use x11rb::CURRENT_TIME;
+
+use crate::color::Color;
+use crate::config::ColorsConfig;
+use crate::locale::Names;
+use crate::metrics::Metrics;
+use crate::render::software::draw_text_render;
+use crate::text::{calculate_text_width, Fonts};
+use crate::VisualInfo;
+
+const CELL_WIDTH: u16 = 30;
+const CELL_HEIGHT: u16 = 22;
+const PADDING: u16 = 8;
+const XK_ESCAPE: u32 = 0xff1b;
+
+pub enum CalendarAction {
+    None,
+    Redraw,
+    Close,
+    // A day was clicked, for its agenda.
+    Day(NaiveDate),
+}
+
+// A month view above the clock: a header with the month and arrows to page, weekday names, and up to six weeks
+// with their ISO week numbers. Days with events have a dot, and below the weeks goes the agenda of a clicked
+// day. It takes the keyboard focus so that Escape and focusing another window close it.
+pub struct Calendar {
+    win: Window,
+    pixmap: Pixmap,
+    gc: Gcontext,
+    visual: VisualInfo,
+    colors: ColorsConfig,
+    metrics: Metrics,
+    // The first of the month shown.
+    month: NaiveDate,
+    today: NaiveDate,
+    first_weekday: Weekday,
+    week_numbers: bool,
+    marks: HashSet<NaiveDate>,
+    // The clicked day and its lines, the first being the day itself.
+    selected: Option<NaiveDate>,
+    agenda: Vec<String>,
+    escape: Option<Keycode>,
+    // Whoever had the focus before, to give it back when closed with Escape.
+    previous_focus: Window,
+    // Where the bottom edge is centered, and the screen width, for keeping it there when the agenda resizes it.
+    anchor_x: i16,
+    anchor_y: i16,
+    screen_width: u16,
+    width: u16,
+    height: u16,
+}
+
+impl Calendar {
+    #[allow(clippy::too_many_arguments)]
+    pub fn open(
+        conn: &RustConnection, screen_num: usize, visual: VisualInfo, colors: ColorsConfig, metrics: Metrics,
+        today: NaiveDate, first_weekday: Weekday, week_numbers: bool, anchor_x: i16, anchor_y: i16,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let screen = &conn.setup().roots[screen_num];
+        let win = conn.generate_id()?;
+        let pixmap = conn.generate_id()?;
+        let gc = conn.generate_id()?;
+
+        let (width, height) = grid_size(metrics, week_numbers);
+        let (x, y) = position(anchor_x, anchor_y, screen.width_in_pixels, width, height);
+
+        let values = CreateWindowAux::new()
+            .background_pixel(colors.background.pixel())
+            .border_pixel(colors.date.pixel())
+            .colormap(visual.colormap)
+            .override_redirect(1)
+            .event_mask(EventMask::EXPOSURE | EventMask::BUTTON_PRESS | EventMask::KEY_PRESS | EventMask::FOCUS_CHANGE);
+        conn.create_window(
+            visual.depth, win, screen.root,
+            x, y, width, height, 1,
+            WindowClass::INPUT_OUTPUT, visual.visual, &values,
+        )?;
+        conn.create_pixmap(visual.depth, pixmap, win, width, height)?;
+        conn.create_gc(gc, win, &CreateGCAux::new().foreground(colors.text.pixel()).background(colors.background.pixel()))?;
+
+        let previous_focus = conn.get_input_focus()?.reply()?.focus;
+        conn.map_window(win)?;
+        conn.set_input_focus(InputFocus::PARENT, win, CURRENT_TIME)?;
+
+        Ok(Self {
+            win, pixmap, gc, visual, colors, metrics,
+            month: today.with_day(1).unwrap_or(today), today, first_weekday, week_numbers,
+            marks: HashSet::new(), selected: None, agenda: Vec::new(),
+            escape: keycode(conn, XK_ESCAPE)?,
+            previous_focus,
+            anchor_x, anchor_y, screen_width: screen.width_in_pixels,
+            width, height,
+        })
+    }
+
+    pub fn window(&self) -> Window {
+        self.win
+    }
+
+    // The first of the month shown.
+    pub fn month(&self) -> NaiveDate {
+        self.month
+    }
+
+    pub fn selected(&self) -> Option<NaiveDate> {
+        self.selected
+    }
+
+    // The day whose agenda to show, applied by the next `set_agenda`.
+    pub fn select(&mut self, day: Option<NaiveDate>) {
+        self.selected = day;
+    }
+
+    // Days to put a dot under.
+    pub fn set_marks(&mut self, marks: HashSet<NaiveDate>) {
+        self.marks = marks;
+    }
+
+    // Shows `lines` below the weeks for the selected day, resizing the window to fit.
+    pub fn set_agenda(&mut self, conn: &RustConnection, font: &Fonts, lines: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+        let m = self.metrics;
+        let (grid_width, grid_height) = grid_size(m, self.week_numbers);
+        let text_width = lines.iter().map(|l| calculate_text_width(font, m.font_date, l) as u16).max().unwrap_or(0);
+        let width = grid_width.max(text_width + 2 * m.px(PADDING));
+        let height = grid_height + lines.len() as u16 * m.px(CELL_HEIGHT);
+        self.agenda = lines;
+        if (width, height) == (self.width, self.height) { return Ok(()); }
+
+        let (x, y) = position(self.anchor_x, self.anchor_y, self.screen_width, width, height);
+        conn.free_pixmap(self.pixmap)?;
+        conn.create_pixmap(self.visual.depth, self.pixmap, self.win, width, height)?;
+        conn.configure_window(self.win, &ConfigureWindowAux::new().x(x as i32).y(y as i32).width(width as u32).height(height as u32))?;
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    // Pages by whole months; 0 goes back to the current one.
+    pub fn page(&mut self, months: i32) {
+        self.month = match months {
+            0 => self.today.with_day(1).unwrap_or(self.today),
+            m if m > 0 => self.month.checked_add_months(Months::new(m as u32)).unwrap_or(self.month),
+            m => self.month.checked_sub_months(Months::new(m.unsigned_abs())).unwrap_or(self.month),
+        };
+    }
+
+    // Returns true when the day changed while open.
+    pub fn set_today(&mut self, today: NaiveDate) -> bool {
+        let changed = today != self.today;
+        self.today = today;
+        changed
+    }
+
+    pub fn draw(&self, conn: &RustConnection, font: &Fonts, names: Option<&Names>, render_buf: &mut Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        let (colors, m) = (&self.colors, self.metrics);
+        conn.change_gc(self.gc, &ChangeGCAux::new().foreground(colors.background.pixel()))?;
+        conn.poly_fill_rectangle(self.pixmap, self.gc, &[Rectangle { x: 0, y: 0, width: self.width, height: self.height }])?;
+
+        let (cell_w, cell_h, pad) = (m.px(CELL_WIDTH) as i16, m.px(CELL_HEIGHT) as i16, m.px(PADDING) as i16);
+        let first_col = self.week_numbers as i16;
+        // Text boxes are the font size plus 8 high.
+        let top = |size: f32| (cell_h - size.ceil() as i16 - 8) / 2;
+
+        let month_name = names.map_or_else(
+            || chrono::Month::try_from(self.month.month() as u8).map_or(String::new(), |m| m.name().to_string()),
+            |n| n.month(self.month.month0() as usize).to_string(),
+        );
+        let title = format!("{} {}", month_name, self.month.year());
+        let title_w = calculate_text_width(font, m.font_main, &title) as i16;
+        let title_color = if self.month == self.today.with_day(1).unwrap_or(self.today) { colors.text } else { colors.date };
+        let title_x = (self.width as i16 - title_w) / 2;
+        draw_text_render(conn, self.pixmap, self.gc, self.visual.depth, font, render_buf, &title, m.font_main, title_x, pad + top(m.font_main), colors.background, title_color)?;
+
+        // Text centered in the cell at column `col` (0 is the week number column) and row `row`.
+        let size = m.font_date;
+        let mut cell = |text: &str, col: i16, row: i16, bg: Color, fg: Color| {
+            let w = calculate_text_width(font, size, text) as i16;
+            let x = pad + col * cell_w + (cell_w - w) / 2;
+            draw_text_render(conn, self.pixmap, self.gc, self.visual.depth, font, render_buf, text, size, x, pad + row * cell_h + top(size), bg, fg)
+        };
+        cell("‹", first_col, 0, colors.background, colors.text)?;
+        cell("›", first_col + 6, 0, colors.background, colors.text)?;
+
+        for i in 0..7 {
+            let weekday = self.first_weekday.days_since(Weekday::Mon) as usize + i;
+            let name = names.map_or_else(
+                || Weekday::try_from((weekday % 7) as u8).map_or(String::new(), |d| d.to_string()),
+                |n| n.weekday_abbr(weekday % 7).to_string(),
+            );
+            cell(&name, first_col + i as i16, 1, colors.background, colors.date)?;
+        }
+
+        // Back to the first weekday on or before the first of the month.
+        let offset = self.month.weekday().days_since(self.first_weekday);
+        let mut day = self.month - chrono::Duration::days(offset as i64);
+        for week in 0..6 {
+            if week > 0 && day.month() != self.month.month() { break; }
+            if self.week_numbers {
+                // The middle day decides, which for weeks starting on Monday is the ISO rule.
+                let middle = day + chrono::Duration::days(3);
+                cell(&middle.iso_week().week().to_string(), 0, 2 + week, colors.background, colors.date)?;
+            }
+            for col in 0..7 {
+                if day.month() == self.month.month() {
+                    let (x, y) = (pad + (first_col + col) * cell_w, pad + (2 + week) * cell_h);
+                    let bg = if day == self.today {
+                        colors.active_background
+                    } else if Some(day) == self.selected {
+                        colors.hover_background
+                    } else {
+                        colors.background
+                    };
+                    if bg != colors.background {
+                        conn.change_gc(self.gc, &ChangeGCAux::new().foreground(bg.pixel()))?;
+                        conn.poly_fill_rectangle(self.pixmap, self.gc, &[Rectangle { x, y, width: cell_w as u16, height: cell_h as u16 }])?;
+                    }
+                    cell(&day.day().to_string(), first_col + col, 2 + week, bg, colors.text)?;
+                    if self.marks.contains(&day) {
+                        let (w, h) = (m.px(4), m.px(2));
+                        conn.change_gc(self.gc, &ChangeGCAux::new().foreground(colors.underline.pixel()))?;
+                        let dot = Rectangle { x: x + (cell_w - w as i16) / 2, y: y + cell_h - 2 * h as i16, width: w, height: h };
+                        conn.poly_fill_rectangle(self.pixmap, self.gc, &[dot])?;
+                    }
+                }
+                day = day.succ_opt().unwrap_or(day);
+            }
+        }
+
+        for (i, line) in self.agenda.iter().enumerate() {
+            let fg = if i == 0 { colors.date } else { colors.text };
+            let y = pad + (8 + i as i16) * cell_h + top(m.font_date);
+            draw_text_render(conn, self.pixmap, self.gc, self.visual.depth, font, render_buf, line, m.font_date, pad, y, colors.background, fg)?;
+        }
+
+        conn.copy_area(self.pixmap, self.win, self.gc, 0, 0, 0, 0, self.width, self.height)?;
+        Ok(())
+    }
+
+    pub fn handle_click(&mut self, e: &ButtonPressEvent) -> CalendarAction {
+        let m = self.metrics;
+        let (cell_w, cell_h, pad) = (m.px(CELL_WIDTH) as i16, m.px(CELL_HEIGHT) as i16, m.px(PADDING) as i16);
+        match e.detail {
+            4 => self.page(-1),
+            5 => self.page(1),
+            1 if e.event_y >= pad && e.event_y < pad + cell_h => {
+                let first_col = self.week_numbers as i16;
+                let col = (e.event_x - pad).div_euclid(cell_w);
+                if col == first_col {
+                    self.page(-1);
+                } else if col == first_col + 6 {
+                    self.page(1);
+                } else {
+                    // The title goes back to this month.
+                    self.page(0);
+                }
+            }
+            1 if e.event_y >= pad + 2 * cell_h && e.event_y < pad + 8 * cell_h => {
+                let col = (e.event_x - pad).div_euclid(cell_w) - self.week_numbers as i16;
+                let row = (e.event_y - pad) / cell_h - 2;
+                let offset = self.month.weekday().days_since(self.first_weekday) as i64;
+                let day = self.month + chrono::Duration::days(row as i64 * 7 + col as i64 - offset);
+                if !(0..7).contains(&col) || day.month() != self.month.month() { return CalendarAction::None; }
+                return CalendarAction::Day(day);
+            }
+            _ => return CalendarAction::None,
+        }
+        CalendarAction::Redraw
+    }
+
+    pub fn handle_key(&self, e: &KeyPressEvent) -> CalendarAction {
+        if Some(e.detail) == self.escape { CalendarAction::Close } else { CalendarAction::None }
+    }
+
+    // Focus moving to another window closes the calendar; focus changes from grabs and within it don't count.
+    pub fn handle_focus_out(&self, e: &FocusOutEvent) -> CalendarAction {
+        let real = e.mode == NotifyMode::NORMAL && !matches!(e.detail, NotifyDetail::INFERIOR | NotifyDetail::POINTER);
+        if real { CalendarAction::Close } else { CalendarAction::None }
+    }
+
+    // `restore_focus` gives the focus back to where it was, for closing from the keyboard.
+    pub fn close(self, conn: &RustConnection, restore_focus: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if restore_focus && self.previous_focus != x11rb::NONE {
+            conn.set_input_focus(InputFocus::PARENT, self.previous_focus, CURRENT_TIME)?;
+        }
+        conn.destroy_window(self.win)?;
+        conn.free_pixmap(self.pixmap)?;
+        conn.free_gc(self.gc)?;
+        Ok(())
+    }
+}
+
+// The keycode producing `keysym` without modifiers.
+fn keycode(conn: &RustConnection, keysym: u32) -> Result<Option<Keycode>, Box<dyn std::error::Error>> {
+    let setup = conn.setup();
+    let (min, max) = (setup.min_keycode, setup.max_keycode);
+    let mapping = conn.get_keyboard_mapping(min, max - min + 1)?.reply()?;
+    let per = mapping.keysyms_per_keycode.max(1) as usize;
+    Ok(mapping.keysyms.chunks(per).position(|syms| syms.first() == Some(&keysym)).map(|i| min + i as u8))
+}
+
+// Header, weekday names and six weeks, with the week number column.
+fn grid_size(metrics: Metrics, week_numbers: bool) -> (u16, u16) {
+    let columns = 7 + week_numbers as u16;
+    (columns * metrics.px(CELL_WIDTH) + 2 * metrics.px(PADDING), 8 * metrics.px(CELL_HEIGHT) + 2 * metrics.px(PADDING))
+}
+
+// Centered over `anchor_x` and above `anchor_y`, within the screen.
+fn position(anchor_x: i16, anchor_y: i16, screen_width: u16, width: u16, height: u16) -> (i16, i16) {
+    let x = (anchor_x as i32 - width as i32 / 2).clamp(0, (screen_width as i32 - width as i32 - 2).max(0));
+    let y = (anchor_y as i32 - height as i32 - 2).max(0);
+    (x as i16, y as i16)
+}
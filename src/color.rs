@@ -0,0 +1,92 @@
+use serde::{Deserialize, Deserializer};
+
+// Straight-alpha 0xAARRGGBB, written in config as "#rrggbb" or "#rrggbbaa".
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub u32);
+
+impl Color {
+    pub const fn rgb(rgb: u32) -> Self {
+        Self(0xFF00_0000 | rgb)
+    }
+
+    pub fn alpha(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    pub fn opaque(self) -> Self {
+        Self(self.0 | 0xFF00_0000)
+    }
+
+    // Premultiplied [b, g, r, a], the byte order of a 32bpp ZPixmap.
+    pub fn premultiplied(self) -> [u16; 4] {
+        let a = self.alpha() as u16;
+        let mul = |shift: u32| (((self.0 >> shift) & 0xFF) as u16 * a + 127) / 255;
+        [mul(0), mul(8), mul(16), a]
+    }
+
+    // Pixel value for GCs and window backgrounds; the alpha byte is ignored by 24-bit visuals.
+    pub fn pixel(self) -> u32 {
+        let [b, g, r, a] = self.premultiplied();
+        ((a as u32) << 24) | ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+    }
+}
+
+// Solid, or a linear gradient from top to bottom (left to right when `horizontal`).
+// Written in config as "#rrggbb" or { from = "#rrggbb", to = "#rrggbb", horizontal = false }.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Fill {
+    Solid(Color),
+    Gradient {
+        from: Color,
+        to: Color,
+        #[serde(default)]
+        horizontal: bool,
+    },
+}
+
+impl Fill {
+    // The color at `t` (0 to 1) along the gradient.
+    pub fn at(self, t: f32) -> Color {
+        match self {
+            Fill::Solid(color) => color,
+            Fill::Gradient { from, to, .. } => {
+                let t = t.clamp(0.0, 1.0);
+                let mix = |shift: u32| {
+                    let (a, b) = (((from.0 >> shift) & 0xFF) as f32, ((to.0 >> shift) & 0xFF) as f32);
+                    ((a + (b - a) * t).round() as u32) << shift
+                };
+                Color(mix(0) | mix(8) | mix(16) | mix(24))
+            }
+        }
+    }
+
+    pub fn opaque(self) -> Self {
+        match self {
+            Fill::Solid(color) => Fill::Solid(color.opaque()),
+            Fill::Gradient { from, to, horizontal } => Fill::Gradient { from: from.opaque(), to: to.opaque(), horizontal },
+        }
+    }
+}
+
+// Composites a premultiplied `src` over a premultiplied BGRA pixel.
+#[inline]
+pub fn over(dst: &mut [u8], src: [u16; 4]) {
+    let inv = 255 - src[3];
+    for i in 0..4 {
+        dst[i] = (src[i] + (dst[i] as u16 * inv + 127) / 255) as u8;
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        let hex = s.strip_prefix('#').unwrap_or(&s);
+        let value = u32::from_str_radix(hex, 16).ok().filter(|_| hex.is_ascii());
+        match (hex.len(), value) {
+            (6, Some(v)) => Ok(Self::rgb(v)),
+            (8, Some(v)) => Ok(Self((v >> 8) | ((v & 0xFF) << 24))),
+            _ => Err(serde::de::Error::custom(format!("invalid color \"{}\", expected #rrggbb or #rrggbbaa", s))),
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use std::process::{Command, Stdio};
+
+// Runs a configured shell command line in the background with `args` as $1 and on. A thread waits for it so
+// that it doesn't linger as a zombie. One that can't be started is skipped: there's nowhere to report it.
+pub fn spawn(command: &str, args: &[&str]) {
+    let child = Command::new("sh").arg("-c").arg(command).arg("sh").args(args)
+        .stdin(Stdio::null())
+        .spawn();
+    if let Ok(mut child) = child {
+        std::thread::spawn(move || child.wait());
+    }
+}
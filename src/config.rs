@@ -0,0 +1,657 @@
+use std::path::PathBuf;
+use serde::Deserialize;
+
+use crate::color::{Color, Fill};
+use crate::layout::Section;
+
+// Everything is optional: a missing file or section falls back to the built-in defaults.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub panel: PanelConfig,
+    pub autohide: AutohideConfig,
+    pub layout: LayoutConfig,
+    pub clock: ClockConfig,
+    pub world_clock: WorldClockConfig,
+    pub timer: TimerConfig,
+    pub battery: BatteryConfig,
+    pub cpu: CpuConfig,
+    pub memory: MemoryConfig,
+    pub network: NetworkConfig,
+    pub font: FontConfig,
+    pub colors: ColorsConfig,
+    pub background: BackgroundConfig,
+    pub theme: ThemeConfig,
+    pub tray: TrayConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PanelConfig {
+    // Use a 32-bit ARGB visual when a compositor is running, so color alpha takes effect.
+    pub transparent: bool,
+    // "software" or "xrender"; xrender falls back to software when the extension is missing.
+    pub renderer: crate::render::Backend,
+    // HiDPI scale factor, clamped to 0.5-4; by default taken from Xft.dpi (dpi / 96) and followed when it changes.
+    pub scale: Option<f32>,
+    // What to do while the active window is fullscreen over the panel: "unmap", "lower" or "ignore".
+    pub fullscreen: FullscreenMode,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FullscreenMode {
+    #[default]
+    Unmap,
+    Lower,
+    Ignore,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutohideConfig {
+    pub enabled: bool,
+    // Milliseconds between the pointer leaving the panel and the panel hiding.
+    pub delay: u64,
+    // Height left on screen while hidden, at 96 DPI; the pointer reaching it brings the panel back.
+    pub strip: u16,
+    // Slide over `duration` milliseconds instead of jumping.
+    pub animate: bool,
+    pub duration: u64,
+    // Show the panel while a window demands attention.
+    pub reveal_on_urgent: bool,
+}
+
+impl Default for AutohideConfig {
+    fn default() -> Self {
+        Self { enabled: false, delay: 800, strip: 2, animate: true, duration: 150, reveal_on_urgent: true }
+    }
+}
+
+// Which widgets go where, by name, in order from left to right within each section.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    pub left: Vec<String>,
+    pub center: Vec<String>,
+    pub right: Vec<String>,
+    // Gap between neighbouring widgets, at 96 DPI.
+    pub spacing: u16,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self { left: vec!["taskbar".to_string()], center: Vec::new(), right: vec!["tray".to_string(), "clock".to_string()], spacing: 10 }
+    }
+}
+
+impl LayoutConfig {
+    // Every widget with its section; each may appear only once, since widgets own windows and selections.
+    pub fn widgets(&self) -> Result<Vec<(&str, Section)>, Box<dyn std::error::Error>> {
+        let sections = [(&self.left, Section::Left), (&self.center, Section::Center), (&self.right, Section::Right)];
+        let mut widgets: Vec<(&str, Section)> = Vec::new();
+        for (names, section) in sections {
+            for name in names {
+                if widgets.iter().any(|(n, _)| n == name) {
+                    return Err(format!("widget \"{}\" appears more than once in [layout]", name).into());
+                }
+                widgets.push((name, section));
+            }
+        }
+        Ok(widgets)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockConfig {
+    // strftime formats for the upper and lower line; seconds in either make the clock tick every second.
+    pub format: String,
+    pub date_format: String,
+    // Only `format`, centered vertically in the main font.
+    pub single_line: bool,
+    // An IANA name like "Europe/Berlin"; by default $TZ or /etc/localtime.
+    pub timezone: Option<String>,
+    // For day and month names, e.g. "de_DE.UTF-8"; by default LC_ALL, LC_TIME or LANG.
+    pub locale: Option<String>,
+    // The calendar opened by clicking the clock.
+    pub first_weekday: FirstWeekday,
+    pub week_numbers: bool,
+    // .ics files or directories of them (e.g. synced by vdirsyncer) whose events the calendar and tooltip show.
+    pub events: Vec<String>,
+    // Minutes before an event to show it beside the clock, 0 for never; the clock turns `reminder_color`
+    // (by default the underline color) meanwhile.
+    pub reminder: u32,
+    pub reminder_color: Option<Color>,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            format: "%H:%M".to_string(), date_format: "%Y-%m-%d".to_string(), single_line: false, timezone: None, locale: None,
+            first_weekday: FirstWeekday::Monday, week_numbers: true, events: Vec::new(), reminder: 0, reminder_color: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FirstWeekday {
+    #[default]
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl FirstWeekday {
+    pub fn weekday(self) -> chrono::Weekday {
+        chrono::Weekday::try_from(self as u8).unwrap_or(chrono::Weekday::Mon)
+    }
+}
+
+// Extra clocks in other time zones, named by short labels; the [clock] locale applies to them too.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldClockConfig {
+    pub zones: Vec<WorldZone>,
+    pub format: String,
+    // For each zone's line in the tooltip, followed by its UTC offset.
+    pub tooltip_format: String,
+}
+
+impl Default for WorldClockConfig {
+    fn default() -> Self {
+        Self { zones: Vec::new(), format: "%H:%M".to_string(), tooltip_format: "%a %d %b %H:%M".to_string() }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorldZone {
+    pub label: String,
+    pub timezone: String,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimerConfig {
+    // Seconds one scroll step adds to or takes off the countdown.
+    pub step: u64,
+    // What the widget starts as, "timer" or "stopwatch"; the middle button switches.
+    pub mode: TimerMode,
+    // Run through `sh -c` when the countdown ends or an alarm goes off, with its label as $1.
+    pub command: Option<String>,
+    // Seconds the widget flashes for, unless clicked.
+    pub flash: u64,
+    // Daily, in the clock's time zone.
+    pub alarms: Vec<Alarm>,
+}
+
+impl Default for TimerConfig {
+    fn default() -> Self {
+        Self { step: 60, mode: TimerMode::Timer, command: None, flash: 30, alarms: Vec::new() }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimerMode {
+    #[default]
+    Timer,
+    Stopwatch,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Alarm {
+    // "07:30".
+    pub time: String,
+    #[serde(default = "alarm_label")]
+    pub label: String,
+}
+
+fn alarm_label() -> String {
+    "Alarm".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatteryConfig {
+    // Where the power supplies are; a directory laid out the same way can stand in for it.
+    pub root: String,
+    // Names like "BAT0" to show, combined; by default every system battery.
+    pub batteries: Vec<String>,
+    // Seconds between readings.
+    pub interval: f64,
+    // Percentages at or below which the widget turns `warning_color` or `critical_color` while discharging.
+    pub warning: u8,
+    pub critical: u8,
+    pub warning_color: Color,
+    pub critical_color: Color,
+    // Run through `sh -c` once each time the charge falls to `critical` while discharging, with the
+    // percentage as $1.
+    pub critical_command: Option<String>,
+    // The estimated time until empty or full beside the percentage.
+    pub show_time: bool,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            root: "/sys/class/power_supply".to_string(), batteries: Vec::new(), interval: 10.0,
+            warning: 20, critical: 10, warning_color: Color::rgb(0xFFAF5F), critical_color: Color::rgb(0xFF5F5F),
+            critical_command: None, show_time: true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuConfig {
+    // Where /proc is; a directory laid out the same way can stand in for it.
+    pub root: String,
+    // Seconds between samples.
+    pub interval: f64,
+    pub display: MeterDisplay,
+    // Each core's usage too: as numbers in the text, and as a bar per core beside the graph or bar.
+    pub per_core: bool,
+    // The width of the graph or bar at 96 DPI, and how many samples the graph spans.
+    pub graph_width: u16,
+    pub history: usize,
+    // The graph and bars; by default the underline color.
+    pub color: Option<Color>,
+    // Behind the graph and bars; by default the panel's background.
+    pub graph_background: Option<Color>,
+    // Usage in percent from which the text turns `high_color`.
+    pub high: u8,
+    pub high_color: Color,
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        Self {
+            root: "/proc".to_string(), interval: 2.0, display: MeterDisplay::Text, per_core: false,
+            graph_width: 40, history: 40, color: None, graph_background: None, high: 80, high_color: Color::rgb(0xFF5F5F),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+    // Where /proc is; a directory laid out the same way can stand in for it.
+    pub root: String,
+    // Seconds between samples.
+    pub interval: f64,
+    pub display: MeterDisplay,
+    // Swap usage too, when there is swap.
+    pub swap: bool,
+    // The width of the graph or bar at 96 DPI, and how many samples the graph spans.
+    pub graph_width: u16,
+    pub history: usize,
+    // Memory by default in the underline color, swap in the date color.
+    pub color: Option<Color>,
+    pub swap_color: Option<Color>,
+    pub graph_background: Option<Color>,
+    // Percentages of memory in use from which the text and bar turn `warning_color` or `critical_color`.
+    pub warning: u8,
+    pub critical: u8,
+    pub warning_color: Color,
+    pub critical_color: Color,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            root: "/proc".to_string(), interval: 5.0, display: MeterDisplay::Text, swap: true,
+            graph_width: 40, history: 40, color: None, swap_color: None, graph_background: None,
+            warning: 80, critical: 90, warning_color: Color::rgb(0xFFAF5F), critical_color: Color::rgb(0xFF5F5F),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    // Where /proc and the interfaces in sysfs are; directories laid out the same way can stand in for them.
+    pub root: String,
+    pub sys_root: String,
+    // Names like "wlan0" whose traffic is added up; by default those with a default route, else every one
+    // that is up.
+    pub interfaces: Vec<String>,
+    // Seconds between samples.
+    pub interval: f64,
+    pub display: MeterDisplay,
+    // The width of the graph or bars at 96 DPI, and how many samples the graph spans.
+    pub graph_width: u16,
+    pub history: usize,
+    // Download by default in the underline color, upload in the date color.
+    pub download_color: Option<Color>,
+    pub upload_color: Option<Color>,
+    pub graph_background: Option<Color>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            root: "/proc".to_string(), sys_root: "/sys/class/net".to_string(), interfaces: Vec::new(), interval: 2.0,
+            display: MeterDisplay::Text, graph_width: 40, history: 40,
+            download_color: None, upload_color: None, graph_background: None,
+        }
+    }
+}
+
+// How a polling widget shows its value: as text, a bar filled to the current value, a graph of the recent
+// history, or the graph and the text.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MeterDisplay {
+    #[default]
+    Text,
+    Bar,
+    Graph,
+    Both,
+}
+
+impl MeterDisplay {
+    pub fn text(self) -> bool {
+        matches!(self, MeterDisplay::Text | MeterDisplay::Both)
+    }
+
+    pub fn bar(self) -> bool {
+        self == MeterDisplay::Bar
+    }
+
+    pub fn graph(self) -> bool {
+        matches!(self, MeterDisplay::Graph | MeterDisplay::Both)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FontConfig {
+    // A fontconfig family name or alias, e.g. "DejaVu Sans" or "sans-serif".
+    pub family: String,
+    // Tried in order for characters the main family doesn't have.
+    pub fallback: Vec<String>,
+}
+
+impl Default for FontConfig {
+    fn default() -> Self {
+        Self {
+            family: "sans-serif".to_string(),
+            fallback: vec!["Noto Sans CJK SC".to_string(), "Noto Color Emoji".to_string(), "Symbola".to_string()],
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorsConfig {
+    pub background: Color,
+    pub active_background: Color,
+    pub hover_background: Color,
+    pub text: Color,
+    pub date: Color,
+    pub underline: Color,
+}
+
+impl Default for ColorsConfig {
+    fn default() -> Self {
+        Self {
+            background: Color::rgb(0x1d1f21),
+            active_background: Color::rgb(0x373b41),
+            hover_background: Color::rgb(0x282a2e),
+            text: Color::rgb(0xe0e0e0),
+            date: Color::rgb(0x969896),
+            underline: Color::rgb(0x5FAFAF),
+        }
+    }
+}
+
+impl ColorsConfig {
+    pub fn opaque(self) -> Self {
+        Self {
+            background: self.background.opaque(),
+            active_background: self.active_background.opaque(),
+            hover_background: self.hover_background.opaque(),
+            text: self.text.opaque(),
+            date: self.date.opaque(),
+            underline: self.underline.opaque(),
+        }
+    }
+}
+
+// An image under everything drawn on the panel, with a tint blended over it.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackgroundConfig {
+    // A PNG or JPEG file, or "root" for the wallpaper on the root window (pseudo-transparency without a compositor).
+    pub image: Option<String>,
+    pub mode: ImageMode,
+    // Defaults to the [colors] background; its alpha is kept even when there is no compositor.
+    pub tint: Option<Color>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageMode {
+    #[default]
+    Stretch,
+    Tile,
+    Center,
+}
+
+// Task button styles per window state. Keys left out keep the built-in look, which follows [colors].
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub normal: StyleConfig,
+    pub hover: StyleConfig,
+    pub active: StyleConfig,
+    pub urgent: StyleConfig,
+    pub minimized: StyleConfig,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleConfig {
+    pub background: Option<Fill>,
+    pub text: Option<Color>,
+    pub radius: Option<u16>,
+    pub border_width: Option<u16>,
+    pub border_color: Option<Color>,
+    // Space between the button edges and the icon and title.
+    pub padding: Option<u16>,
+    pub indicator: Option<Indicator>,
+    pub indicator_color: Option<Color>,
+    pub indicator_size: Option<u16>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Indicator {
+    None,
+    Top,
+    Bottom,
+    Left,
+}
+
+// A fully resolved StyleConfig; sizes are at 96 DPI.
+#[derive(Clone, Copy)]
+pub struct ButtonStyle {
+    pub background: Option<Fill>,
+    pub text: Color,
+    pub radius: u16,
+    pub border_width: u16,
+    pub border_color: Color,
+    pub padding: u16,
+    pub indicator: Indicator,
+    pub indicator_color: Color,
+    pub indicator_size: u16,
+}
+
+impl ButtonStyle {
+    fn with(self, cfg: &StyleConfig) -> Self {
+        Self {
+            background: cfg.background.or(self.background),
+            text: cfg.text.unwrap_or(self.text),
+            radius: cfg.radius.unwrap_or(self.radius),
+            border_width: cfg.border_width.unwrap_or(self.border_width),
+            border_color: cfg.border_color.unwrap_or(self.border_color),
+            padding: cfg.padding.unwrap_or(self.padding),
+            indicator: cfg.indicator.unwrap_or(self.indicator),
+            indicator_color: cfg.indicator_color.unwrap_or(self.indicator_color),
+            indicator_size: cfg.indicator_size.unwrap_or(self.indicator_size),
+        }
+    }
+
+    fn opaque(self) -> Self {
+        Self {
+            background: self.background.map(Fill::opaque),
+            text: self.text.opaque(),
+            border_color: self.border_color.opaque(),
+            indicator_color: self.indicator_color.opaque(),
+            ..self
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ButtonState {
+    Normal,
+    Hover,
+    Active,
+    Urgent,
+    Minimized,
+}
+
+#[derive(Clone, Copy)]
+pub struct Theme {
+    normal: ButtonStyle,
+    hover: ButtonStyle,
+    active: ButtonStyle,
+    urgent: ButtonStyle,
+    minimized: ButtonStyle,
+}
+
+impl Theme {
+    pub fn style(&self, state: ButtonState) -> &ButtonStyle {
+        match state {
+            ButtonState::Normal => &self.normal,
+            ButtonState::Hover => &self.hover,
+            ButtonState::Active => &self.active,
+            ButtonState::Urgent => &self.urgent,
+            ButtonState::Minimized => &self.minimized,
+        }
+    }
+
+    pub fn opaque(self) -> Self {
+        Self {
+            normal: self.normal.opaque(),
+            hover: self.hover.opaque(),
+            active: self.active.opaque(),
+            urgent: self.urgent.opaque(),
+            minimized: self.minimized.opaque(),
+        }
+    }
+}
+
+impl ThemeConfig {
+    // Every state starts from the normal style; the defaults are the flat look with the active underline.
+    pub fn resolve(&self, colors: &ColorsConfig) -> Theme {
+        let base = ButtonStyle {
+            background: None,
+            text: colors.text,
+            radius: 0,
+            border_width: 0,
+            border_color: colors.underline,
+            padding: 6,
+            indicator: Indicator::None,
+            indicator_color: colors.underline,
+            indicator_size: 2,
+        };
+        let normal = base.with(&self.normal);
+        Theme {
+            normal,
+            hover: ButtonStyle { background: Some(Fill::Solid(colors.hover_background)), ..normal }.with(&self.hover),
+            active: ButtonStyle {
+                background: Some(Fill::Solid(colors.active_background)),
+                indicator: Indicator::Bottom,
+                ..normal
+            }.with(&self.active),
+            urgent: ButtonStyle {
+                background: Some(Fill::Solid(Color::rgb(0x7a3b3b))),
+                indicator: Indicator::Bottom,
+                indicator_color: Color::rgb(0xcc6666),
+                ..normal
+            }.with(&self.urgent),
+            minimized: ButtonStyle { text: colors.date, ..normal }.with(&self.minimized),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrayConfig {
+    pub icon_size: u16,
+    pub spacing: u16,
+    // WM_CLASS names (instance or class, case-insensitive) in the order they should appear.
+    pub order: Vec<String>,
+    pub hidden: Vec<String>,
+    // Icons not listed in `order` are collapsed behind an arrow and shown in a popup.
+    pub overflow: bool,
+    // Host StatusNotifierItem (D-Bus) icons next to the XEMBED ones.
+    pub status_notifier: bool,
+    pub icon_theme: String,
+}
+
+impl Default for TrayConfig {
+    fn default() -> Self {
+        Self {
+            icon_size: 24, spacing: 8,
+            order: Vec::new(), hidden: Vec::new(), overflow: false,
+            status_notifier: true, icon_theme: "hicolor".to_string(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let path = config_path();
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                let config: Self = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+                if config.panel.scale.is_some_and(|scale| !scale.is_finite() || scale <= 0.0) {
+                    return Err(format!("{}: [panel] scale must be a positive number", path.display()).into());
+                }
+                Ok(config)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+        }
+    }
+}
+
+fn config_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("PSA_XPANEL_CONFIG") {
+        return PathBuf::from(path);
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .unwrap_or_default();
+    base.join("psa-xpanel").join("config.toml")
+}
+
+// Paths in the config may start with ~/ for the home directory.
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
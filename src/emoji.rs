@@ -0,0 +1,288 @@
+use ab_glyph::{Font, FontRef, GlyphId, GlyphImageFormat, PxScale, ScaleFont};
+use ab_glyph_rasterizer::{point, Point, Rasterizer};
+use image::imageops::FilterType;
+use image::RgbaImage;
+use rustybuzz::ttf_parser::{self, colr, RgbaColor, Transform};
+
+use crate::text::GlyphBitmap;
+
+// Color glyphs come as straight-alpha RGBA, positioned like outlines: relative to the pen on the baseline.
+
+// An embedded bitmap (CBDT or sbix) from the strike closest to the requested size, scaled to it.
+pub fn bitmap(font: &FontRef, id: GlyphId, scale: PxScale) -> Option<GlyphBitmap> {
+    let ppem = font.as_scaled(scale).scale_factor().vertical * font.units_per_em()?;
+    let image = font.glyph_raster_image2(id, ppem.round() as u16)?;
+    let strike = match image.format {
+        GlyphImageFormat::Png => image::load_from_memory_with_format(image.data, image::ImageFormat::Png).ok()?.to_rgba8(),
+        GlyphImageFormat::BitmapPremulBgra32 => {
+            let pixels = image.data.chunks_exact(4).flat_map(|px| {
+                let unpremultiply = |v: u8| if px[3] == 0 { 0 } else { (v as u32 * 255 / px[3] as u32).min(255) as u8 };
+                [unpremultiply(px[2]), unpremultiply(px[1]), unpremultiply(px[0]), px[3]]
+            }).collect();
+            RgbaImage::from_raw(image.width as u32, image.height as u32, pixels)?
+        }
+        // Monochrome and grayscale strikes are hinted copies of the outlines, which look better.
+        _ => return None,
+    };
+
+    let s = ppem / image.pixels_per_em as f32;
+    let width = ((strike.width() as f32 * s).round() as u32).max(1);
+    let height = ((strike.height() as f32 * s).round() as u32).max(1);
+    let scaled = image::imageops::resize(&strike, width, height, FilterType::Lanczos3);
+    // The strike's origin is its bottom-left corner, in strike pixels above the baseline.
+    Some(GlyphBitmap {
+        left: (image.origin.x * s).round() as i32,
+        top: -((image.origin.y + strike.height() as f32) * s).round() as i32,
+        width: width as usize,
+        coverage: Vec::new(),
+        color: Some(scaled.into_raw()),
+    })
+}
+
+// A COLR glyph, painted layer by layer into a box one advance wide spanning the font's ascent and descent.
+pub fn paint(face: &ttf_parser::Face, font: &FontRef, id: GlyphId, scale: PxScale) -> Option<GlyphBitmap> {
+    let glyph_id = ttf_parser::GlyphId(id.0);
+    if !face.is_color_glyph(glyph_id) { return None; }
+
+    let scaled = font.as_scaled(scale);
+    let factor = scaled.scale_factor();
+    let width = (scaled.h_advance(id).ceil() as usize).max(1);
+    let height = ((scaled.ascent() - scaled.descent()).ceil() as usize).max(1);
+    // Font units, y up, to box pixels, y down.
+    let base = Transform::new(factor.horizontal, 0.0, 0.0, -factor.vertical, 0.0, scaled.ascent());
+
+    let mut painter = Painter {
+        face, width, height,
+        transforms: vec![base],
+        outline: None,
+        clips: Vec::new(),
+        pixels: vec![[0.0; 4]; width * height],
+    };
+    face.paint_color_glyph(glyph_id, 0, RgbaColor::new(0, 0, 0, 255), &mut painter)?;
+
+    let color = painter.pixels.iter().flat_map(|&[r, g, b, a]| {
+        let straight = |v: f32| if a > 0.0 { (v / a * 255.0).round().clamp(0.0, 255.0) as u8 } else { 0 };
+        [straight(r), straight(g), straight(b), (a * 255.0).round().clamp(0.0, 255.0) as u8]
+    }).collect();
+    Some(GlyphBitmap { left: 0, top: -scaled.ascent().round() as i32, width, coverage: Vec::new(), color: Some(color) })
+}
+
+struct Painter<'f, 'a> {
+    face: &'f ttf_parser::Face<'a>,
+    width: usize,
+    height: usize,
+    transforms: Vec<Transform>,
+    outline: Option<ttf_parser::GlyphId>,
+    // Coverage masks, each already intersected with the ones below it.
+    clips: Vec<Vec<f32>>,
+    // Premultiplied RGBA in 0..1.
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Painter<'_, '_> {
+    fn transform(&self) -> Transform {
+        *self.transforms.last().unwrap()
+    }
+
+    fn rasterize(&self, draw: impl FnOnce(&mut PathBuilder)) -> Vec<f32> {
+        let mut builder = PathBuilder {
+            transform: self.transform(),
+            rasterizer: Rasterizer::new(self.width, self.height),
+            start: point(0.0, 0.0),
+            last: point(0.0, 0.0),
+        };
+        draw(&mut builder);
+        let mut coverage = vec![0.0; self.width * self.height];
+        builder.rasterizer.for_each_pixel(|i, c| coverage[i] = c.clamp(0.0, 1.0));
+        if let Some(clip) = self.clips.last() {
+            coverage.iter_mut().zip(clip).for_each(|(c, m)| *c *= m);
+        }
+        coverage
+    }
+
+    fn outline_mask(&self) -> Vec<f32> {
+        let (face, outline) = (self.face, self.outline);
+        self.rasterize(|builder| {
+            if let Some(id) = outline { face.outline_glyph(id, builder); }
+        })
+    }
+}
+
+impl<'a> colr::Painter<'a> for Painter<'_, 'a> {
+    fn outline_glyph(&mut self, glyph_id: ttf_parser::GlyphId) {
+        self.outline = Some(glyph_id);
+    }
+
+    fn paint(&mut self, paint: colr::Paint<'a>) {
+        let mask = self.outline_mask();
+        let shader = Shader::new(&paint, self.transform());
+        for (i, (px, &coverage)) in self.pixels.iter_mut().zip(&mask).enumerate() {
+            if coverage <= 0.0 { continue; }
+            let [r, g, b, a] = shader.at((i % self.width) as f32 + 0.5, (i / self.width) as f32 + 0.5);
+            let alpha = a * coverage;
+            for (dst, src) in px.iter_mut().zip([r * alpha, g * alpha, b * alpha, alpha]) {
+                *dst = src + *dst * (1.0 - alpha);
+            }
+        }
+    }
+
+    fn push_clip(&mut self) {
+        let mask = self.outline_mask();
+        self.clips.push(mask);
+    }
+
+    fn push_clip_box(&mut self, clipbox: colr::ClipBox) {
+        let mask = self.rasterize(|builder| {
+            use ttf_parser::OutlineBuilder;
+            builder.move_to(clipbox.x_min, clipbox.y_min);
+            builder.line_to(clipbox.x_max, clipbox.y_min);
+            builder.line_to(clipbox.x_max, clipbox.y_max);
+            builder.line_to(clipbox.x_min, clipbox.y_max);
+            builder.close();
+        });
+        self.clips.push(mask);
+    }
+
+    fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+
+    // Layers only matter for composite modes other than source-over, which emoji fonts hardly use.
+    fn push_layer(&mut self, _mode: colr::CompositeMode) {}
+
+    fn pop_layer(&mut self) {}
+
+    fn push_transform(&mut self, transform: Transform) {
+        let combined = Transform::combine(self.transform(), transform);
+        self.transforms.push(combined);
+    }
+
+    fn pop_transform(&mut self) {
+        if self.transforms.len() > 1 { self.transforms.pop(); }
+    }
+}
+
+struct PathBuilder {
+    transform: Transform,
+    rasterizer: Rasterizer,
+    start: Point,
+    last: Point,
+}
+
+impl PathBuilder {
+    fn map(&self, x: f32, y: f32) -> Point {
+        let t = &self.transform;
+        point(t.a * x + t.c * y + t.e, t.b * x + t.d * y + t.f)
+    }
+}
+
+impl ttf_parser::OutlineBuilder for PathBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.start = self.map(x, y);
+        self.last = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.map(x, y);
+        self.rasterizer.draw_line(self.last, p);
+        self.last = p;
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p1, p) = (self.map(x1, y1), self.map(x, y));
+        self.rasterizer.draw_quad(self.last, p1, p);
+        self.last = p;
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p1, p2, p) = (self.map(x1, y1), self.map(x2, y2), self.map(x, y));
+        self.rasterizer.draw_cubic(self.last, p1, p2, p);
+        self.last = p;
+    }
+
+    fn close(&mut self) {
+        if self.last != self.start { self.rasterizer.draw_line(self.last, self.start); }
+        self.last = self.start;
+    }
+}
+
+// Colors a paint at box pixels. Gradients are evaluated in glyph space through the inverse transform; radial
+// and sweep gradients are approximated by their distance and angle from the end circle's center.
+enum Shader {
+    Solid([f32; 4]),
+    Gradient { stops: Vec<(f32, [f32; 4])>, inverse: Transform, kind: GradientKind },
+}
+
+enum GradientKind {
+    Linear { x0: f32, y0: f32, dx: f32, dy: f32 },
+    Radial { x: f32, y: f32, r0: f32, r1: f32 },
+    Sweep { x: f32, y: f32, start: f32, end: f32 },
+}
+
+fn rgba(color: RgbaColor) -> [f32; 4] {
+    [color.red, color.green, color.blue, color.alpha].map(|v| v as f32 / 255.0)
+}
+
+impl Shader {
+    fn new(paint: &colr::Paint, transform: Transform) -> Self {
+        let (stops, kind) = match paint {
+            colr::Paint::Solid(color) => return Shader::Solid(rgba(*color)),
+            colr::Paint::LinearGradient(g) => {
+                // The gradient runs along p0→p1, projected onto the normal of p0→p2.
+                let (nx, ny) = (g.y2 - g.y0, -(g.x2 - g.x0));
+                let (px, py) = (g.x1 - g.x0, g.y1 - g.y0);
+                let len = nx * nx + ny * ny;
+                let (dx, dy) = if len > 0.0 { let k = (px * nx + py * ny) / len; (nx * k, ny * k) } else { (px, py) };
+                (g.stops(0, &[]).collect::<Vec<_>>(), GradientKind::Linear { x0: g.x0, y0: g.y0, dx, dy })
+            }
+            colr::Paint::RadialGradient(g) => (g.stops(0, &[]).collect(), GradientKind::Radial { x: g.x1, y: g.y1, r0: g.r0, r1: g.r1 }),
+            colr::Paint::SweepGradient(g) => (g.stops(0, &[]).collect(), GradientKind::Sweep {
+                x: g.center_x, y: g.center_y, start: g.start_angle, end: g.end_angle,
+            }),
+        };
+        let mut stops: Vec<(f32, [f32; 4])> = stops.iter().map(|s| (s.stop_offset, rgba(s.color))).collect();
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        match stops.len() {
+            0 => Shader::Solid([0.0; 4]),
+            1 => Shader::Solid(stops[0].1),
+            _ => Shader::Gradient { stops, inverse: invert(transform), kind },
+        }
+    }
+
+    fn at(&self, x: f32, y: f32) -> [f32; 4] {
+        let (stops, inverse, kind) = match self {
+            Shader::Solid(color) => return *color,
+            Shader::Gradient { stops, inverse, kind } => (stops, inverse, kind),
+        };
+        let (gx, gy) = (inverse.a * x + inverse.c * y + inverse.e, inverse.b * x + inverse.d * y + inverse.f);
+        let t = match *kind {
+            GradientKind::Linear { x0, y0, dx, dy } => {
+                let len = dx * dx + dy * dy;
+                if len > 0.0 { ((gx - x0) * dx + (gy - y0) * dy) / len } else { 0.0 }
+            }
+            GradientKind::Radial { x, y, r0, r1 } => {
+                let d = ((gx - x).powi(2) + (gy - y).powi(2)).sqrt();
+                if r1 != r0 { (d - r0) / (r1 - r0) } else { 0.0 }
+            }
+            GradientKind::Sweep { x, y, start, end } => {
+                // Angles are in half turns, counterclockwise.
+                let angle = (gy - y).atan2(gx - x).to_degrees().rem_euclid(360.0) / 180.0;
+                if end != start { (angle - start) / (end - start) } else { 0.0 }
+            }
+        };
+
+        let i = stops.partition_point(|&(offset, _)| offset <= t);
+        if i == 0 { return stops[0].1; }
+        if i == stops.len() { return stops[i - 1].1; }
+        let ((o0, c0), (o1, c1)) = (stops[i - 1], stops[i]);
+        let k = if o1 > o0 { (t - o0) / (o1 - o0) } else { 0.0 };
+        [0, 1, 2, 3].map(|j| c0[j] + (c1[j] - c0[j]) * k)
+    }
+}
+
+fn invert(t: Transform) -> Transform {
+    let det = t.a * t.d - t.b * t.c;
+    if det == 0.0 { return Transform::default(); }
+    let (a, b, c, d) = (t.d / det, -t.b / det, -t.c / det, t.a / det);
+    Transform::new(a, b, c, d, -(a * t.e + c * t.f), -(b * t.e + d * t.f))
+}
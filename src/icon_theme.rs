@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use resvg::{tiny_skia, usvg};
+
+// A small subset of the freedesktop icon theme spec: index.theme directories, Inherits and hicolor fallback.
+struct ThemeDir {
+    path: String,
+    size: u32,
+    min_size: u32,
+    max_size: u32,
+}
+
+impl ThemeDir {
+    fn distance(&self, size: u32) -> u32 {
+        if size < self.min_size { self.min_size - size } else { size.saturating_sub(self.max_size) }
+    }
+}
+
+struct Theme {
+    name: String,
+    dirs: Vec<ThemeDir>,
+    inherits: Vec<String>,
+}
+
+pub struct IconLookup {
+    base_dirs: Vec<PathBuf>,
+    themes: HashMap<String, Option<Theme>>,
+    theme: String,
+}
+
+impl IconLookup {
+    pub fn new(theme: &str) -> Self {
+        let mut base_dirs = Vec::new();
+        if let Some(home) = std::env::var_os("HOME") {
+            base_dirs.push(PathBuf::from(&home).join(".icons"));
+            let data_home = std::env::var_os("XDG_DATA_HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(&home).join(".local/share"));
+            base_dirs.push(data_home.join("icons"));
+        }
+        let data_dirs = std::env::var("XDG_DATA_DIRS").unwrap_or_else(|_| "/usr/local/share:/usr/share".to_string());
+        for dir in data_dirs.split(':').filter(|d| !d.is_empty()) {
+            base_dirs.push(Path::new(dir).join("icons"));
+        }
+        Self { base_dirs, themes: HashMap::new(), theme: theme.to_string() }
+    }
+
+    // `extra_dirs` are searched first, both as flat directories and as theme roots (SNI IconThemePath).
+    pub fn find(&mut self, name: &str, size: u32, extra_dirs: &[PathBuf]) -> Option<PathBuf> {
+        if name.is_empty() { return None; }
+        if name.starts_with('/') {
+            return Some(PathBuf::from(name)).filter(|p| p.exists());
+        }
+
+        for dir in extra_dirs {
+            if let Some(path) = find_in_dir(dir, name) { return Some(path); }
+        }
+
+        let mut chain = vec![self.theme.clone()];
+        let mut i = 0;
+        while i < chain.len() {
+            let theme_name = chain[i].clone();
+            if let Some(theme) = self.load_theme(&theme_name) {
+                for parent in &theme.inherits {
+                    if !chain.contains(parent) { chain.push(parent.clone()); }
+                }
+            }
+            i += 1;
+        }
+        if !chain.iter().any(|t| t == "hicolor") { chain.push("hicolor".to_string()); }
+
+        let mut roots: Vec<PathBuf> = extra_dirs.to_vec();
+        roots.extend(self.base_dirs.iter().cloned());
+        for theme_name in &chain {
+            let Some(theme) = self.load_theme(theme_name) else { continue };
+            let mut dirs: Vec<&ThemeDir> = theme.dirs.iter().collect();
+            dirs.sort_by_key(|d| (d.distance(size), d.size.abs_diff(size)));
+            for dir in dirs {
+                for root in &roots {
+                    if let Some(path) = find_in_dir(&root.join(&theme.name).join(&dir.path), name) {
+                        return Some(path);
+                    }
+                }
+            }
+        }
+
+        find_in_dir(Path::new("/usr/share/pixmaps"), name)
+    }
+
+    fn load_theme(&mut self, name: &str) -> Option<&Theme> {
+        if !self.themes.contains_key(name) {
+            let theme = self.base_dirs.iter()
+                .find_map(|dir| std::fs::read_to_string(dir.join(name).join("index.theme")).ok())
+                .map(|text| parse_index(name, &text));
+            self.themes.insert(name.to_string(), theme);
+        }
+        self.themes.get(name).and_then(|t| t.as_ref())
+    }
+}
+
+fn find_in_dir(dir: &Path, name: &str) -> Option<PathBuf> {
+    ["png", "svg"].iter()
+        .map(|ext| dir.join(format!("{}.{}", name, ext)))
+        .find(|p| p.is_file())
+}
+
+fn parse_index(name: &str, text: &str) -> Theme {
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current = String::new();
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            current = line[1..line.len() - 1].to_string();
+        } else if let Some((key, value)) = line.split_once('=') {
+            sections.entry(current.clone()).or_default().insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    let list = |key: &str| -> Vec<String> {
+        sections.get("Icon Theme").and_then(|s| s.get(key))
+            .map(|v| v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default()
+    };
+
+    let dirs = list("Directories").into_iter().filter_map(|path| {
+        let section = sections.get(&path)?;
+        let num = |key: &str| section.get(key).and_then(|v| v.parse::<u32>().ok());
+        let size = num("Size")?;
+        let (min_size, max_size) = match section.get("Type").map(String::as_str) {
+            Some("Scalable") => (num("MinSize").unwrap_or(size), num("MaxSize").unwrap_or(size)),
+            Some("Fixed") => (size, size),
+            _ => {
+                let threshold = num("Threshold").unwrap_or(2);
+                (size.saturating_sub(threshold), size + threshold)
+            }
+        };
+        Some(ThemeDir { path, size, min_size, max_size })
+    }).collect();
+
+    Theme { name: name.to_string(), dirs, inherits: list("Inherits") }
+}
+
+// Loads a PNG/JPEG/SVG file into a straight-alpha RGBA buffer of `size` x `size`.
+pub fn load_icon(path: &Path, size: u32) -> Option<Vec<u8>> {
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("svg")) {
+        let data = std::fs::read(path).ok()?;
+        let tree = usvg::Tree::from_data(&data, &usvg::Options::default()).ok()?;
+        let mut pixmap = tiny_skia::Pixmap::new(size, size)?;
+        let scale_x = size as f32 / tree.size().width();
+        let scale_y = size as f32 / tree.size().height();
+        resvg::render(&tree, tiny_skia::Transform::from_scale(scale_x, scale_y), &mut pixmap.as_mut());
+        return Some(pixmap.pixels().iter().flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        }).collect());
+    }
+
+    let img = image::open(path).ok()?.into_rgba8();
+    Some(image::imageops::resize(&img, size, size, FilterType::Lanczos3).into_raw())
+}
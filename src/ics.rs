@@ -0,0 +1,739 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime};
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, Utc, Weekday};
+
+use crate::config::expand_home;
+use crate::tz::{self, Zone};
+
+// How often the files are checked for changes.
+const RECHECK: std::time::Duration = std::time::Duration::from_secs(60);
+// Periods a recurrence rule is followed for at most, against rules that never produce anything.
+const MAX_PERIODS: u32 = 100_000;
+
+// Events from local iCalendar files, e.g. what vdirsyncer keeps in sync: VEVENTs with their recurrence rules,
+// exceptions and time zones. VTIMEZONE definitions are ignored in favour of the system's zoneinfo by TZID.
+pub struct Events {
+    sources: Vec<PathBuf>,
+    // Every file read and when it was modified, to notice edits, new and removed files.
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    checked: Instant,
+    zones: Vec<Zone>,
+    events: Vec<Event>,
+}
+
+pub struct Occurrence {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub all_day: bool,
+    pub summary: String,
+}
+
+// A DTSTART-like value: wall time in its zone.
+#[derive(Clone, Copy)]
+struct Time {
+    local: NaiveDateTime,
+    zone: TimeZone,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TimeZone {
+    Utc,
+    // No zone given: the panel's local time, as are dates.
+    Floating,
+    Zone(usize),
+}
+
+struct Event {
+    uid: String,
+    summary: String,
+    start: Time,
+    all_day: bool,
+    // DTEND of a timed event, whose zone may differ from DTSTART's; without it `duration` is the length.
+    end: Option<Time>,
+    duration: Duration,
+    rule: Option<Rule>,
+    exdates: Vec<Time>,
+    // For an override of one occurrence of a recurring event: the start it replaces.
+    recurrence_id: Option<Time>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+enum Until {
+    Utc(DateTime<Utc>),
+    Local(NaiveDateTime),
+}
+
+struct Rule {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<Until>,
+    // Weekdays with an optional ordinal ("2MO", "-1FR") within the month or year.
+    by_day: Vec<(Option<i32>, Weekday)>,
+    by_month_day: Vec<i32>,
+    by_month: Vec<u32>,
+    by_set_pos: Vec<i32>,
+    week_start: Weekday,
+}
+
+impl Events {
+    // `paths` are .ics files or directories of them; "~/" is the home directory.
+    pub fn load(paths: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let sources: Vec<PathBuf> = paths.iter().map(|p| PathBuf::from(expand_home(p))).collect();
+        for source in &sources {
+            std::fs::metadata(source).map_err(|e| format!("{}: {}", source.display(), e))?;
+        }
+        let mut events = Self {
+            sources, files: Vec::new(), checked: Instant::now(),
+            zones: Vec::new(), events: Vec::new(),
+        };
+        events.read();
+        Ok(events)
+    }
+
+    // Rereads everything when a file changed since the last look, at most once a minute. Returns whether the
+    // events changed.
+    pub fn refresh(&mut self) -> bool {
+        if self.checked.elapsed() < RECHECK { return false; }
+        self.checked = Instant::now();
+        if self.scan() == self.files { return false; }
+        self.read();
+        true
+    }
+
+    fn scan(&self) -> Vec<(PathBuf, Option<SystemTime>)> {
+        let mut files = Vec::new();
+        for source in &self.sources {
+            match std::fs::read_dir(source) {
+                Ok(dir) => {
+                    let mut paths: Vec<PathBuf> = dir.flatten().map(|e| e.path())
+                        .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("ics")))
+                        .collect();
+                    paths.sort();
+                    files.extend(paths);
+                }
+                Err(_) => files.push(source.clone()),
+            }
+        }
+        files.into_iter().map(|p| {
+            let modified = std::fs::metadata(&p).and_then(|m| m.modified()).ok();
+            (p, modified)
+        }).collect()
+    }
+
+    // Files that can't be read (yet) are skipped, a sync may be writing them.
+    fn read(&mut self) {
+        self.files = self.scan();
+        self.events.clear();
+        self.zones.clear();
+        let mut zone_ids = HashMap::new();
+        for (path, _) in &self.files {
+            let Ok(text) = std::fs::read_to_string(path) else { continue };
+            parse(&text, &mut |props| self.events.extend(event(&props, &mut self.zones, &mut zone_ids)));
+        }
+    }
+
+    // Occurrences overlapping `from`..`to`, by start time. Dates and floating times are in `local`.
+    pub fn occurrences(&self, from: DateTime<Utc>, to: DateTime<Utc>, local: Option<&Zone>) -> Vec<Occurrence> {
+        let mut found = Vec::new();
+        let overrides: Vec<(&str, DateTime<Utc>)> = self.events.iter()
+            .filter_map(|e| Some((e.uid.as_str(), resolve(&self.zones, e.recurrence_id?, local))))
+            .collect();
+        for event in &self.events {
+            let mut excluded: Vec<DateTime<Utc>> = event.exdates.iter().map(|t| resolve(&self.zones, *t, local)).collect();
+            if event.rule.is_some() {
+                excluded.extend(overrides.iter().filter(|(uid, _)| *uid == event.uid).map(|(_, start)| *start));
+            }
+            // Both ends in UTC, each through its own zone.
+            let duration = match event.end {
+                Some(end) => (resolve(&self.zones, end, local) - resolve(&self.zones, event.start, local)).max(Duration::zero()),
+                None => event.duration,
+            };
+            let mut add = |local_start: NaiveDateTime| {
+                let start = resolve(&self.zones, Time { local: local_start, ..event.start }, local);
+                let end = if event.all_day {
+                    resolve(&self.zones, Time { local: local_start + duration, ..event.start }, local)
+                } else {
+                    start + duration
+                };
+                // Zero-length events still show at their start.
+                let overlaps = start < to && (end > from || (end == start && start >= from));
+                if overlaps && !excluded.contains(&start) {
+                    found.push(Occurrence { start, end, all_day: event.all_day, summary: event.summary.clone() });
+                }
+                start < to
+            };
+            match &event.rule {
+                // The non-recurring kind, and overrides of single occurrences.
+                None => { add(event.start.local); }
+                Some(rule) => {
+                    let zones = &self.zones;
+                    expand(rule, event.start.local, |t| {
+                        if let Some(until) = &rule.until {
+                            let past = match until {
+                                Until::Utc(until) => resolve(zones, Time { local: t, ..event.start }, local) > *until,
+                                Until::Local(until) => t > *until,
+                            };
+                            if past { return false; }
+                        }
+                        add(t)
+                    });
+                }
+            }
+        }
+        found.sort_by_key(|o| (o.start, !o.all_day));
+        found
+    }
+}
+
+fn resolve(zones: &[Zone], time: Time, local: Option<&Zone>) -> DateTime<Utc> {
+    match time.zone {
+        TimeZone::Utc => time.local.and_utc(),
+        TimeZone::Floating => tz::resolve(local, time.local),
+        TimeZone::Zone(i) => tz::resolve(zones.get(i), time.local),
+    }
+}
+
+// Calls `f` with the properties of every VEVENT, skipping nested components like VALARM.
+fn parse(text: &str, f: &mut dyn FnMut(Vec<Property>)) {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    let mut props = Vec::new();
+    let mut depth = 0;
+    for line in &lines {
+        let Some(prop) = Property::parse(line) else { continue };
+        match (prop.name.as_str(), prop.value.to_ascii_uppercase().as_str(), depth) {
+            ("BEGIN", "VEVENT", 0) => { props.clear(); depth = 1; }
+            ("BEGIN", _, d) if d > 0 => depth += 1,
+            ("END", "VEVENT", 1) => { f(std::mem::take(&mut props)); depth = 0; }
+            ("END", _, d) if d > 0 => depth -= 1,
+            (_, _, 1) => props.push(prop),
+            _ => {}
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    // "DTSTART;TZID=Europe/Berlin:20240102T090000"; quoted parameter values may contain ':' and ';'.
+    fn parse(line: &str) -> Option<Self> {
+        let mut quoted = false;
+        let colon = line.char_indices().find(|&(_, c)| {
+            if c == '"' { quoted = !quoted; }
+            c == ':' && !quoted
+        })?.0;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+        let mut quoted = false;
+        let mut parts = head.split(|c| {
+            if c == '"' { quoted = !quoted; }
+            c == ';' && !quoted
+        });
+        let name = parts.next()?.to_ascii_uppercase();
+        let params = parts.filter_map(|p| {
+            let (key, value) = p.split_once('=')?;
+            Some((key.to_ascii_uppercase(), value.trim_matches('"').to_string()))
+        }).collect();
+        Some(Self { name, params, value: value.to_string() })
+    }
+
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+fn event(props: &[Property], zones: &mut Vec<Zone>, zone_ids: &mut HashMap<String, Option<usize>>) -> Option<Event> {
+    let get = |name: &str| props.iter().find(|p| p.name == name);
+    if get("STATUS").is_some_and(|p| p.value.eq_ignore_ascii_case("CANCELLED")) { return None; }
+    let mut time = |p: &Property| parse_time(p, &p.value, zones, zone_ids);
+    let dtstart = get("DTSTART")?;
+    let (start, all_day) = time(dtstart)?;
+    let end = get("DTEND").and_then(&mut time).map(|(t, _)| t);
+    let duration = match (end, get("DURATION")) {
+        // Dates have no zone.
+        (Some(end), _) if all_day => Some(end.local - start.local),
+        (None, Some(d)) => parse_duration(&d.value),
+        _ => None,
+    }.unwrap_or(if all_day { Duration::days(1) } else { Duration::zero() }).max(Duration::zero());
+    let end = end.filter(|_| !all_day);
+    let recurrence_id = get("RECURRENCE-ID").and_then(&mut time).map(|(t, _)| t);
+    let rule = match recurrence_id {
+        Some(_) => None,
+        None => get("RRULE").and_then(|p| parse_rule(&p.value, all_day)),
+    };
+    let exdates = props.iter().filter(|p| p.name == "EXDATE")
+        .flat_map(|p| p.value.split(',').filter_map(|v| parse_time(p, v, zones, zone_ids)).map(|(t, _)| t).collect::<Vec<_>>())
+        .collect();
+    let summary = get("SUMMARY").map_or_else(String::new, |p| unescape(&p.value));
+    let uid = get("UID").map_or_else(String::new, |p| p.value.clone());
+    Some(Event { uid, summary, start, all_day, end, duration, rule, exdates, recurrence_id })
+}
+
+// A DATE or DATE-TIME value, and whether it is a date.
+fn parse_time(prop: &Property, value: &str, zones: &mut Vec<Zone>, zone_ids: &mut HashMap<String, Option<usize>>) -> Option<(Time, bool)> {
+    let value = value.trim();
+    if prop.param("VALUE").is_some_and(|v| v.eq_ignore_ascii_case("DATE")) || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        return Some((Time { local: date.and_hms_opt(0, 0, 0)?, zone: TimeZone::Floating }, true));
+    }
+    let (value, utc) = match value.strip_suffix(['Z', 'z']) {
+        Some(v) => (v, true),
+        None => (value, false),
+    };
+    let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    let zone = match (utc, prop.param("TZID")) {
+        (true, _) => TimeZone::Utc,
+        (false, Some(id)) => zone_ids.entry(id.to_string()).or_insert_with(|| {
+            let zone = load_zone(id)?;
+            zones.push(zone);
+            Some(zones.len() - 1)
+        }).map_or(TimeZone::Floating, TimeZone::Zone),
+        (false, None) => TimeZone::Floating,
+    };
+    Some((Time { local, zone }, false))
+}
+
+// TZIDs are mostly IANA names, sometimes behind a prefix like "/mozilla.org/20050126_1/Europe/Berlin". Others
+// (Windows names) are taken as local time.
+fn load_zone(id: &str) -> Option<Zone> {
+    if let Ok(zone) = Zone::load(id) { return Some(zone); }
+    let parts: Vec<&str> = id.split('/').filter(|p| !p.is_empty()).collect();
+    (1..=parts.len().min(3)).rev().find_map(|n| Zone::load(&parts[parts.len() - n..].join("/")).ok())
+}
+
+// "P1D", "PT1H30M", "P2W".
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim().trim_start_matches('+');
+    let rest = value.strip_prefix('P')?;
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => {}
+            unit => {
+                let n: i64 = std::mem::take(&mut number).parse().ok()?;
+                total += match unit {
+                    'W' => Duration::weeks(n),
+                    'D' => Duration::days(n),
+                    'H' => Duration::hours(n),
+                    'M' => Duration::minutes(n),
+                    'S' => Duration::seconds(n),
+                    _ => return None,
+                };
+            }
+        }
+    }
+    Some(total)
+}
+
+// SUMMARY text, on one line.
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n' | 'N') => out.push(' '),
+                Some(next) => out.push(next),
+                None => {}
+            },
+            (c, false) => out.push(c),
+        }
+    }
+    out
+}
+
+fn parse_rule(value: &str, all_day: bool) -> Option<Rule> {
+    let mut rule = Rule {
+        frequency: Frequency::Daily, interval: 1, count: None, until: None,
+        by_day: Vec::new(), by_month_day: Vec::new(), by_month: Vec::new(), by_set_pos: Vec::new(),
+        week_start: Weekday::Mon,
+    };
+    let mut frequency = None;
+    for part in value.split(';') {
+        let Some((key, value)) = part.split_once('=') else { continue };
+        let list = || value.split(',').filter_map(|v| v.trim().parse::<i32>().ok());
+        match key.to_ascii_uppercase().as_str() {
+            "FREQ" => frequency = Some(match value.to_ascii_uppercase().as_str() {
+                "DAILY" => Frequency::Daily,
+                "WEEKLY" => Frequency::Weekly,
+                "MONTHLY" => Frequency::Monthly,
+                "YEARLY" => Frequency::Yearly,
+                // Sub-daily rules aren't supported.
+                _ => return None,
+            }),
+            "INTERVAL" => rule.interval = value.parse().ok().filter(|&i| i > 0)?,
+            "COUNT" => rule.count = value.parse().ok(),
+            "UNTIL" => {
+                let value = value.trim();
+                rule.until = if let Some(utc) = value.strip_suffix(['Z', 'z']) {
+                    NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok().map(|t| Until::Utc(t.and_utc()))
+                } else if let Ok(t) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+                    Some(Until::Local(t))
+                } else {
+                    // A date includes the whole day; all-day occurrences start at midnight.
+                    let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+                    let end = if all_day { date.and_hms_opt(0, 0, 0) } else { date.and_hms_opt(23, 59, 59) };
+                    end.map(Until::Local)
+                };
+            }
+            "BYDAY" => rule.by_day = value.split(',').filter_map(parse_weekday).collect(),
+            "BYMONTHDAY" => rule.by_month_day = list().filter(|&d| d != 0 && d.abs() <= 31).collect(),
+            "BYMONTH" => rule.by_month = list().filter(|m| (1..=12).contains(m)).map(|m| m as u32).collect(),
+            "BYSETPOS" => rule.by_set_pos = list().filter(|&p| p != 0).collect(),
+            "WKST" => rule.week_start = parse_weekday(value).map_or(Weekday::Mon, |(_, d)| d),
+            _ => {}
+        }
+    }
+    rule.frequency = frequency?;
+    Some(rule)
+}
+
+// "MO", "2TU", "-1FR".
+fn parse_weekday(value: &str) -> Option<(Option<i32>, Weekday)> {
+    let value = value.trim();
+    let (ordinal, day) = value.split_at(value.len().checked_sub(2)?);
+    let day = match day.to_ascii_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    };
+    let ordinal = if ordinal.is_empty() { None } else { Some(ordinal.trim_start_matches('+').parse().ok()?) };
+    Some((ordinal, day))
+}
+
+// Feeds the wall times the rule produces from `start` on to `f`, until it returns false or COUNT is reached.
+fn expand(rule: &Rule, start: NaiveDateTime, mut f: impl FnMut(NaiveDateTime) -> bool) {
+    let (date, time) = (start.date(), start.time());
+    let mut count = 0;
+    for period in 0..MAX_PERIODS {
+        let Some(mut days) = period_days(rule, date, period * rule.interval) else { return };
+        days.sort();
+        days.dedup();
+        if !rule.by_set_pos.is_empty() {
+            let n = days.len() as i32;
+            let mut picked: Vec<NaiveDate> = rule.by_set_pos.iter()
+                .filter_map(|&p| days.get(if p > 0 { p - 1 } else { n + p } as usize).copied())
+                .collect();
+            picked.sort();
+            picked.dedup();
+            days = picked;
+        }
+        for day in days.into_iter().filter(|&d| d >= date) {
+            if rule.count.is_some_and(|c| count >= c) || !f(day.and_time(time)) { return; }
+            count += 1;
+        }
+    }
+}
+
+// The days of the `n`th period after the one containing `start`, before BYSETPOS. None when past the dates
+// chrono can represent.
+fn period_days(rule: &Rule, start: NaiveDate, n: u32) -> Option<Vec<NaiveDate>> {
+    let month_ok = |d: &NaiveDate| rule.by_month.is_empty() || rule.by_month.contains(&d.month());
+    let month_day_ok = |d: &NaiveDate| rule.by_month_day.is_empty() || rule.by_month_day.iter().any(|&md| month_day(d.year(), d.month(), md) == Some(*d));
+    let weekday_ok = |d: &NaiveDate| rule.by_day.is_empty() || rule.by_day.iter().any(|&(_, w)| w == d.weekday());
+    let days = match rule.frequency {
+        Frequency::Daily => {
+            let day = start.checked_add_signed(Duration::days(n as i64))?;
+            vec![day].into_iter().filter(|d| month_ok(d) && month_day_ok(d) && weekday_ok(d)).collect()
+        }
+        Frequency::Weekly => {
+            let week = start - Duration::days(start.weekday().days_since(rule.week_start) as i64);
+            let week = week.checked_add_signed(Duration::weeks(n as i64))?;
+            let days = (0..7).map(|i| week + Duration::days(i));
+            if rule.by_day.is_empty() {
+                days.filter(|d| d.weekday() == start.weekday() && month_ok(d)).collect()
+            } else {
+                days.filter(|d| weekday_ok(d) && month_ok(d)).collect()
+            }
+        }
+        Frequency::Monthly => {
+            let month = start.with_day(1)?.checked_add_months(Months::new(n))?;
+            if month_ok(&month) { month_days(rule, month.year(), month.month(), start.day()) } else { Vec::new() }
+        }
+        Frequency::Yearly => {
+            let year = start.year() + n as i32;
+            if year > 9999 { return None; }
+            if rule.by_month.is_empty() && rule.by_month_day.is_empty() && !rule.by_day.is_empty() {
+                // Weekdays of the whole year, ordinals counting within it.
+                let first = NaiveDate::from_ymd_opt(year, 1, 1)?;
+                let last = NaiveDate::from_ymd_opt(year, 12, 31)?;
+                nth_weekdays(&rule.by_day, first, last)
+            } else {
+                let months = if rule.by_month.is_empty() { vec![start.month()] } else { rule.by_month.clone() };
+                months.into_iter().flat_map(|m| month_days(rule, year, m, start.day())).collect()
+            }
+        }
+    };
+    Some(days)
+}
+
+// The days BYMONTHDAY and BYDAY pick in a month, or `default_day` without either.
+fn month_days(rule: &Rule, year: i32, month: u32, default_day: u32) -> Vec<NaiveDate> {
+    let (Some(first), Some(last)) = (NaiveDate::from_ymd_opt(year, month, 1), month_day(year, month, -1)) else { return Vec::new() };
+    match (rule.by_month_day.is_empty(), rule.by_day.is_empty()) {
+        (true, true) => NaiveDate::from_ymd_opt(year, month, default_day).into_iter().collect(),
+        (false, true) => rule.by_month_day.iter().filter_map(|&d| month_day(year, month, d)).collect(),
+        (true, false) => nth_weekdays(&rule.by_day, first, last),
+        (false, false) => nth_weekdays(&rule.by_day, first, last).into_iter()
+            .filter(|d| rule.by_month_day.iter().any(|&md| month_day(year, month, md) == Some(*d)))
+            .collect(),
+    }
+}
+
+// Day `day` of the month, negative counting from its end.
+fn month_day(year: i32, month: u32, day: i32) -> Option<NaiveDate> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let length = first.checked_add_months(Months::new(1))?.signed_duration_since(first).num_days() as i32;
+    let day = if day > 0 { day } else { length + day + 1 };
+    if day < 1 || day > length { return None; }
+    first.with_day(day as u32)
+}
+
+// The days in first..=last matching BYDAY, where "2MO" is the second Monday and "-1FR" the last Friday.
+fn nth_weekdays(by_day: &[(Option<i32>, Weekday)], first: NaiveDate, last: NaiveDate) -> Vec<NaiveDate> {
+    let mut days = Vec::new();
+    for &(ordinal, weekday) in by_day {
+        let first_match = first + Duration::days(weekday.days_since(first.weekday()) as i64);
+        let all: Vec<NaiveDate> = first_match.iter_weeks().take_while(|d| *d <= last).collect();
+        match ordinal {
+            None => days.extend(all),
+            Some(n) if n > 0 => days.extend(all.get(n as usize - 1)),
+            Some(n) => days.extend(all.len().checked_sub(n.unsigned_abs() as usize).and_then(|i| all.get(i))),
+        }
+    }
+    days
+}
+
+// The days an occurrence touches, as the dates shown in the calendar.
+pub fn days(occurrence: &Occurrence, local: Option<&Zone>) -> impl Iterator<Item = NaiveDate> {
+    let first = tz::localize(local, occurrence.start).date_naive();
+    // The end is exclusive, so an event until midnight doesn't mark the next day.
+    let end = if occurrence.end > occurrence.start { occurrence.end - Duration::seconds(1) } else { occurrence.end };
+    let last = tz::localize(local, end).date_naive().max(first);
+    first.iter_days().take_while(move |d| *d <= last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: &str = "CET-1CEST,M3.5.0,M10.5.0/3";
+
+    // The events of one calendar made of `lines`.
+    fn calendar(lines: &[&str]) -> Events {
+        let text = ["BEGIN:VCALENDAR", "VERSION:2.0"].iter().chain(lines).chain(&["END:VCALENDAR", ""]).copied().collect::<Vec<_>>().join("\r\n");
+        let mut events = Events { sources: Vec::new(), files: Vec::new(), checked: Instant::now(), zones: Vec::new(), events: Vec::new() };
+        let mut zone_ids = HashMap::new();
+        parse(&text, &mut |props| events.events.extend(event(&props, &mut events.zones, &mut zone_ids)));
+        events
+    }
+
+    fn vevent(props: &[&str]) -> Vec<String> {
+        let mut lines = vec!["BEGIN:VEVENT".to_string()];
+        lines.extend(props.iter().map(|p| p.to_string()));
+        lines.push("END:VEVENT".to_string());
+        lines
+    }
+
+    fn single(props: &[&str]) -> Events {
+        let lines = vevent(props);
+        calendar(&lines.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    fn local_time(s: &str) -> DateTime<Utc> {
+        let zone = Zone::from_rule(LOCAL).unwrap();
+        tz::resolve(Some(&zone), NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap())
+    }
+
+    // Each occurrence from `from` to `to` as "start..end summary" in local time.
+    fn occurrences(events: &Events, from: &str, to: &str) -> Vec<String> {
+        let zone = Zone::from_rule(LOCAL).unwrap();
+        let show = |t| tz::localize(Some(&zone), t).format("%Y-%m-%d %H:%M").to_string();
+        events.occurrences(local_time(from), local_time(to), Some(&zone)).iter()
+            .map(|o| format!("{}..{} {}", show(o.start), show(o.end), o.summary))
+            .collect()
+    }
+
+    fn starts(events: &Events, from: &str, to: &str) -> Vec<String> {
+        occurrences(events, from, to).iter().map(|o| o[..16].to_string()).collect()
+    }
+
+    fn rule(start: &str, rrule: &str) -> Events {
+        single(&["UID:r", &format!("DTSTART:{}", start), &format!("RRULE:{}", rrule), "SUMMARY:R"])
+    }
+
+    #[test]
+    fn unfolding_and_escapes() {
+        let events = calendar(&[
+            "BEGIN:VEVENT", "UID:u", "DTSTART:20240105T100000Z",
+            "SUMMARY:Long", "  summary", "\tcontinued\\, with\\nbreaks",
+            "END:VEVENT",
+        ]);
+        assert_eq!(events.events[0].summary, "Long summarycontinued, with breaks");
+    }
+
+    #[test]
+    fn quoted_parameters() {
+        let prop = Property::parse("ATTENDEE;CN=\"Doe; John\";role=REQ-PARTICIPANT:mailto:jd@example.com").unwrap();
+        assert_eq!(prop.name, "ATTENDEE");
+        assert_eq!(prop.param("CN"), Some("Doe; John"));
+        assert_eq!(prop.param("ROLE"), Some("REQ-PARTICIPANT"));
+        assert_eq!(prop.value, "mailto:jd@example.com");
+        let prop = Property::parse("X-A;B=\"c:d;e\":value").unwrap();
+        assert_eq!((prop.param("B"), prop.value.as_str()), (Some("c:d;e"), "value"));
+        assert!(Property::parse("no colon").is_none());
+    }
+
+    #[test]
+    fn dates_and_date_times() {
+        let mut lines = Vec::new();
+        for props in [
+            &["UID:1", "DTSTART;VALUE=DATE:20240105", "DTEND;VALUE=DATE:20240107", "SUMMARY:Trip"][..],
+            &["UID:2", "DTSTART:20240110", "SUMMARY:Day"],
+            &["UID:3", "DTSTART:20240105T100000Z", "DTEND:20240105T110000Z", "SUMMARY:UTC"],
+            &["UID:4", "DTSTART:20240105T100000", "DURATION:PT1H30M", "SUMMARY:Floating"],
+            &["UID:5", "DTSTART:20240105T120000", "SUMMARY:Instant"],
+        ] {
+            lines.extend(vevent(props));
+        }
+        let events = calendar(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(occurrences(&events, "2024-01-01 00:00", "2024-02-01 00:00"), [
+            "2024-01-05 00:00..2024-01-07 00:00 Trip",
+            "2024-01-05 10:00..2024-01-05 11:30 Floating",
+            "2024-01-05 11:00..2024-01-05 12:00 UTC",
+            "2024-01-05 12:00..2024-01-05 12:00 Instant",
+            "2024-01-10 00:00..2024-01-11 00:00 Day",
+        ]);
+        let zone = Zone::from_rule(LOCAL).unwrap();
+        let trip = &events.occurrences(local_time("2024-01-05 00:00"), local_time("2024-01-06 00:00"), Some(&zone))[0];
+        assert!(trip.all_day);
+        let days: Vec<String> = super::days(trip, Some(&zone)).map(|d| d.to_string()).collect();
+        assert_eq!(days, ["2024-01-05", "2024-01-06"]);
+    }
+
+    #[test]
+    fn ends_in_other_zones() {
+        // Floating 10:00 is 09:00 UTC, an hour before the end.
+        let events = single(&["UID:m", "DTSTART:20240105T100000", "DTEND:20240105T100000Z", "SUMMARY:Mixed"]);
+        assert_eq!(occurrences(&events, "2024-01-05 00:00", "2024-01-06 00:00"), ["2024-01-05 10:00..2024-01-05 11:00 Mixed"]);
+        if Zone::load("Europe/Berlin").is_err() || Zone::load("America/New_York").is_err() { return; }
+        let events = single(&[
+            "UID:z", "DTSTART;TZID=/mozilla.org/20050126_1/Europe/Berlin:20240105T100000",
+            "DTEND;TZID=America/New_York:20240105T100000", "RRULE:FREQ=DAILY;COUNT=2", "SUMMARY:Flight",
+        ]);
+        assert_eq!(occurrences(&events, "2024-01-05 00:00", "2024-01-07 00:00"), [
+            "2024-01-05 10:00..2024-01-05 16:00 Flight",
+            "2024-01-06 10:00..2024-01-06 16:00 Flight",
+        ]);
+    }
+
+    #[test]
+    fn daily_interval_count() {
+        let events = rule("20240101T090000", "FREQ=DAILY;INTERVAL=2;COUNT=3");
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2024-02-01 00:00"), ["2024-01-01 09:00", "2024-01-03 09:00", "2024-01-05 09:00"]);
+        // Floating times keep their wall time over a DST change.
+        let events = rule("20240330T090000", "FREQ=DAILY");
+        assert_eq!(starts(&events, "2024-03-30 00:00", "2024-04-01 00:00"), ["2024-03-30 09:00", "2024-03-31 09:00"]);
+    }
+
+    #[test]
+    fn weekly_by_day_until() {
+        let events = rule("20240101T090000", "FREQ=WEEKLY;BYDAY=MO,WE,FR;UNTIL=20240110T235959Z");
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2024-02-01 00:00"), [
+            "2024-01-01 09:00", "2024-01-03 09:00", "2024-01-05 09:00", "2024-01-08 09:00", "2024-01-10 09:00",
+        ]);
+        let events = rule("20240101T090000", "FREQ=WEEKLY;INTERVAL=2;UNTIL=20240129");
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2024-03-01 00:00"), ["2024-01-01 09:00", "2024-01-15 09:00", "2024-01-29 09:00"]);
+        // A date ends all-day ones on that day.
+        let events = single(&["UID:a", "DTSTART;VALUE=DATE:20240101", "RRULE:FREQ=DAILY;UNTIL=20240103", "SUMMARY:A"]);
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2024-02-01 00:00"), ["2024-01-01 00:00", "2024-01-02 00:00", "2024-01-03 00:00"]);
+    }
+
+    #[test]
+    fn monthly_and_yearly() {
+        let events = rule("20240126T090000", "FREQ=MONTHLY;BYDAY=-1FR;COUNT=3");
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2025-01-01 00:00"), ["2024-01-26 09:00", "2024-02-23 09:00", "2024-03-29 09:00"]);
+        let events = rule("20240109T090000", "FREQ=MONTHLY;BYDAY=2TU");
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2024-04-01 00:00"), ["2024-01-09 09:00", "2024-02-13 09:00", "2024-03-12 09:00"]);
+        // The last weekday of the month.
+        let events = rule("20240131T090000", "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1");
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2024-04-01 00:00"), ["2024-01-31 09:00", "2024-02-29 09:00", "2024-03-29 09:00"]);
+        // Months without a 31st are skipped.
+        let events = rule("20240131T090000", "FREQ=MONTHLY");
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2024-06-01 00:00"), ["2024-01-31 09:00", "2024-03-31 09:00", "2024-05-31 09:00"]);
+        // Thanksgiving.
+        let events = rule("20241128T120000", "FREQ=YEARLY;BYMONTH=11;BYDAY=4TH");
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2027-01-01 00:00"), ["2024-11-28 12:00", "2025-11-27 12:00", "2026-11-26 12:00"]);
+        let events = rule("20240229T120000", "FREQ=YEARLY");
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2029-01-01 00:00"), ["2024-02-29 12:00", "2028-02-29 12:00"]);
+    }
+
+    #[test]
+    fn exdates() {
+        let events = single(&[
+            "UID:x", "DTSTART:20240101T100000Z", "RRULE:FREQ=DAILY;COUNT=5",
+            "EXDATE:20240102T100000Z,20240104T100000Z", "SUMMARY:X",
+        ]);
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2024-02-01 00:00"), ["2024-01-01 11:00", "2024-01-03 11:00", "2024-01-05 11:00"]);
+        let events = single(&["UID:d", "DTSTART;VALUE=DATE:20240101", "RRULE:FREQ=DAILY;COUNT=3", "EXDATE;VALUE=DATE:20240102", "SUMMARY:D"]);
+        assert_eq!(starts(&events, "2024-01-01 00:00", "2024-02-01 00:00"), ["2024-01-01 00:00", "2024-01-03 00:00"]);
+    }
+
+    #[test]
+    fn recurrence_id_overrides() {
+        let mut lines = vevent(&["UID:w", "DTSTART:20240101T100000Z", "DTEND:20240101T110000Z", "RRULE:FREQ=WEEKLY;COUNT=3", "SUMMARY:Weekly"]);
+        lines.extend(vevent(&["UID:w", "RECURRENCE-ID:20240108T100000Z", "DTSTART:20240109T150000Z", "DTEND:20240109T160000Z", "SUMMARY:Moved"]));
+        let events = calendar(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(occurrences(&events, "2024-01-01 00:00", "2024-02-01 00:00"), [
+            "2024-01-01 11:00..2024-01-01 12:00 Weekly",
+            "2024-01-09 16:00..2024-01-09 17:00 Moved",
+            "2024-01-15 11:00..2024-01-15 12:00 Weekly",
+        ]);
+    }
+
+    #[test]
+    fn cancelled_and_nested() {
+        let mut lines = vevent(&["UID:c", "STATUS:CANCELLED", "DTSTART:20240105T100000Z", "SUMMARY:Cancelled"]);
+        lines.extend(vevent(&[
+            "UID:n", "DTSTART:20240105T100000Z",
+            "BEGIN:VALARM", "TRIGGER:-PT15M", "SUMMARY:Alarm", "END:VALARM",
+            "SUMMARY:Kept",
+        ]));
+        let events = calendar(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+        assert_eq!(occurrences(&events, "2024-01-05 00:00", "2024-01-06 00:00"), ["2024-01-05 11:00..2024-01-05 11:00 Kept"]);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1DT2H"), Some(Duration::hours(26)));
+        assert_eq!(parse_duration("+P2W"), Some(Duration::weeks(2)));
+        assert_eq!(parse_duration("1H"), None);
+    }
+}
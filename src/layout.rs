@@ -0,0 +1,110 @@
+use x11rb::protocol::xproto::Rectangle;
+
+use crate::widget::{Change, Ctx, Widget};
+
+// A horizontal slice of the panel that is re-rendered and presented on its own.
+#[derive(Clone, Copy, Default)]
+pub struct Region {
+    pub x: i16,
+    pub width: u16,
+    pub dirty: bool,
+}
+
+impl Region {
+    pub fn contains(&self, x: i16) -> bool {
+        x >= self.x && (x as i32) < self.x as i32 + self.width as i32
+    }
+
+    pub fn rect(&self, height: u16) -> Rectangle {
+        Rectangle { x: self.x, y: 0, width: self.width, height }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Left,
+    Center,
+    Right,
+}
+
+pub struct Slot {
+    pub widget: Box<dyn Widget>,
+    pub section: Section,
+    pub region: Region,
+}
+
+// Where every widget sits on the panel, kept between redraws so that a change only re-renders its own region.
+#[derive(Default)]
+pub struct Layout {
+    pub slots: Vec<Slot>,
+    // The slot under the pointer, which gets hover updates.
+    pub hovered: Option<usize>,
+    // Positions must be recomputed: a widget changed width.
+    pub stale: bool,
+    // The window lost its contents (Expose) and the whole back buffer has to be presented again.
+    pub exposed: bool,
+}
+
+impl Layout {
+    pub fn slot_at(&self, x: i16) -> Option<usize> {
+        self.slots.iter().position(|s| s.region.contains(x))
+    }
+
+    pub fn apply(&mut self, index: usize, change: Change) {
+        match change {
+            Change::None => {}
+            Change::Redraw => self.slots[index].region.dirty = true,
+            Change::Relayout => self.stale = true,
+        }
+    }
+
+    // Left widgets are packed from the left edge and right ones from the right edge, both in config order; the
+    // center section is centered in what is left between them. Flexible widgets share the width nobody else
+    // needs, and may take less.
+    pub fn arrange(&mut self, ctx: &Ctx, width: u16, spacing: u16) {
+        // Widgets with nothing to show (no battery, say) take no room and no gap.
+        let mut widths = vec![0u16; self.slots.len()];
+        let mut used = 0u32;
+        let mut flexible = 0;
+        for (slot, w) in self.slots.iter_mut().zip(&mut widths) {
+            if slot.widget.flexible() {
+                flexible += 1;
+            } else {
+                *w = slot.widget.measure(ctx, width).min(width);
+                used += *w as u32;
+            }
+        }
+        let shown = self.slots.iter().zip(&widths).filter(|(s, w)| s.widget.flexible() || **w > 0).count();
+        used += spacing as u32 * shown.saturating_sub(1) as u32;
+        if let Some(share) = ((width as u32).saturating_sub(used) as u16).checked_div(flexible) {
+            for (slot, w) in self.slots.iter_mut().zip(&mut widths) {
+                if slot.widget.flexible() { *w = slot.widget.measure(ctx, share).min(share); }
+            }
+        }
+
+        let gap = spacing as i32;
+        let mut left_end = 0i32;
+        for (i, slot) in self.slots.iter_mut().enumerate().filter(|(_, s)| s.section == Section::Left) {
+            slot.region = Region { x: left_end as i16, width: widths[i], dirty: true };
+            if widths[i] > 0 { left_end += widths[i] as i32 + gap; }
+        }
+        let mut right_start = width as i32;
+        for (i, slot) in self.slots.iter_mut().enumerate().rev().filter(|(_, s)| s.section == Section::Right) {
+            if widths[i] == 0 {
+                slot.region = Region { x: right_start as i16, width: 0, dirty: true };
+                continue;
+            }
+            right_start -= widths[i] as i32;
+            slot.region = Region { x: right_start as i16, width: widths[i], dirty: true };
+            right_start -= gap;
+        }
+
+        let center: Vec<usize> = (0..self.slots.len()).filter(|&i| self.slots[i].section == Section::Center).collect();
+        let total = center.iter().filter(|&&i| widths[i] > 0).map(|&i| widths[i] as i32 + gap).sum::<i32>() - gap;
+        let mut x = ((width as i32 - total) / 2).min(right_start - total).max(left_end);
+        for i in center {
+            self.slots[i].region = Region { x: x as i16, width: widths[i], dirty: true };
+            if widths[i] > 0 { x += widths[i] as i32 + gap; }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::ffi::{CStr, CString};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, FixedOffset, Timelike};
+
+// Day and month names and the preferred date/time formats of a locale's LC_TIME, read through the C library so
+// that whatever locales the system has installed work.
+pub struct Names {
+    // Sunday first, as the C library orders them.
+    days: [String; 7],
+    abdays: [String; 7],
+    months: [String; 12],
+    abmonths: [String; 12],
+    am_pm: [String; 2],
+    // What %c, %x, %X and %r stand for.
+    date_time: String,
+    date: String,
+    time: String,
+    time_ampm: String,
+}
+
+impl Names {
+    // `name` like "de_DE.UTF-8"; None takes LC_ALL, LC_TIME or LANG from the environment. Errors when the locale
+    // isn't installed.
+    pub fn load(name: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let cname = CString::new(name.unwrap_or(""))?;
+        let locale = unsafe { libc::newlocale(libc::LC_TIME_MASK, cname.as_ptr(), std::ptr::null_mut()) };
+        if locale.is_null() {
+            return Err(format!("locale \"{}\" is not available", name.unwrap_or("")).into());
+        }
+        let item = |item: libc::nl_item| unsafe {
+            let s = libc::nl_langinfo_l(item, locale);
+            if s.is_null() { String::new() } else { CStr::from_ptr(s).to_string_lossy().into_owned() }
+        };
+        let names = Self {
+            days: std::array::from_fn(|i| item(libc::DAY_1 + i as libc::nl_item)),
+            abdays: std::array::from_fn(|i| item(libc::ABDAY_1 + i as libc::nl_item)),
+            months: std::array::from_fn(|i| item(libc::MON_1 + i as libc::nl_item)),
+            abmonths: std::array::from_fn(|i| item(libc::ABMON_1 + i as libc::nl_item)),
+            am_pm: [item(libc::AM_STR), item(libc::PM_STR)],
+            date_time: item(libc::D_T_FMT),
+            date: item(libc::D_FMT),
+            time: item(libc::T_FMT),
+            time_ampm: item(libc::T_FMT_AMPM),
+        };
+        unsafe { libc::freelocale(locale) };
+        Ok(names)
+    }
+
+    // Monday is 0.
+    pub fn weekday_abbr(&self, weekday: usize) -> &str {
+        &self.abdays[(weekday + 1) % 7]
+    }
+
+    pub fn month(&self, month0: usize) -> &str {
+        &self.months[month0]
+    }
+
+    // The configured locale, which has to exist, or else the environment's when it does; without names chrono's
+    // English ones are used.
+    pub fn configured(name: Option<&str>) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        match name {
+            Some(name) => Ok(Some(Self::load(Some(name))?)),
+            None => Ok(Self::load(None).ok()),
+        }
+    }
+
+    // Replaces %c, %x, %X and %r with the locale's formats. The alternative-era modifiers (%E, %O) some locales
+    // use are dropped, chrono doesn't know them.
+    fn expand(&self, fmt: &str) -> String {
+        substitute(fmt, |c| match c {
+            'c' => Some(self.date_time.clone()),
+            'x' => Some(self.date.clone()),
+            'X' => Some(self.time.clone()),
+            'r' if !self.time_ampm.is_empty() => Some(self.time_ampm.clone()),
+            'E' | 'O' => Some("%".to_string()),
+            _ => None,
+        })
+    }
+}
+
+// Checks a strftime format up front, since chrono only reports bad ones by panicking while formatting.
+pub fn validate(fmt: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if StrftimeItems::new(fmt).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid time format \"{}\"", fmt).into());
+    }
+    Ok(())
+}
+
+// Whether the format shows seconds, so its text changes every second rather than every minute.
+pub fn has_seconds(fmt: &str, names: Option<&Names>) -> bool {
+    specifiers(&names.map_or_else(|| fmt.to_string(), |n| n.expand(fmt))).any(|c| matches!(c, 'S' | 'T' | 's' | 'c' | 'X' | 'r' | '+' | 'f'))
+}
+
+// The conversion characters of a format, past flags and widths ("%-d" gives 'd', "%.3f" gives 'f').
+fn specifiers(fmt: &str) -> impl Iterator<Item = char> + '_ {
+    let mut chars = fmt.chars();
+    std::iter::from_fn(move || {
+        while let Some(c) = chars.next() {
+            if c != '%' { continue; }
+            for next in chars.by_ref() {
+                if !matches!(next, '-' | '_' | '0'..='9' | '.' | ':' | '#') { return Some(next); }
+            }
+        }
+        None
+    })
+}
+
+// strftime with the day and month names (and %p) of `names`, and %Z as the zone abbreviation when known.
+pub fn format(fmt: &str, time: &DateTime<FixedOffset>, abbr: Option<&str>, names: Option<&Names>) -> String {
+    let escape = |s: &str| s.replace('%', "%%");
+    let (weekday, month) = (time.weekday().num_days_from_sunday() as usize, time.month0() as usize);
+    let expanded = names.map_or_else(|| fmt.to_string(), |n| n.expand(fmt));
+    let localized = substitute(&expanded, |c| match (c, names) {
+        ('Z', _) => abbr.map(escape),
+        ('A', Some(n)) => Some(escape(&n.days[weekday])),
+        ('a', Some(n)) => Some(escape(&n.abdays[weekday])),
+        ('B', Some(n)) => Some(escape(&n.months[month])),
+        ('b' | 'h', Some(n)) => Some(escape(&n.abmonths[month])),
+        ('p', Some(n)) if !n.am_pm[0].is_empty() => Some(escape(&n.am_pm[(time.hour() >= 12) as usize])),
+        _ => None,
+    });
+    // A locale format chrono can't handle falls back to the configured one, which was validated.
+    let fmt = if validate(&localized).is_ok() { localized.as_str() } else { fmt };
+    time.format(fmt).to_string()
+}
+
+// Replaces the conversions `f` has text for, leaving the rest (and "%%") alone.
+fn substitute(fmt: &str, f: impl Fn(char) -> Option<String>) -> String {
+    let mut out = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' { out.push(c); continue; }
+        match chars.next() {
+            Some(next) => match f(next) {
+                Some(text) if next != '%' => out.push_str(&text),
+                _ => { out.push('%'); out.push(next); }
+            },
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn time() -> DateTime<FixedOffset> {
+        FixedOffset::east_opt(7200).unwrap().with_ymd_and_hms(2024, 3, 31, 14, 5, 9).unwrap()
+    }
+
+    #[test]
+    fn substitute_only_what_it_has_text_for() {
+        let f = |c| (c == 'Z').then(|| "UTC".to_string());
+        assert_eq!(substitute("a %Z b %H", f), "a UTC b %H");
+        assert_eq!(substitute("%%Z %Z%", f), "%%Z UTC%");
+        // "%%" stays even when there is text for '%'.
+        assert_eq!(substitute("100%%", |_| Some("x".to_string())), "100%%");
+        assert_eq!(substitute("", f), "");
+    }
+
+    #[test]
+    fn specifiers_skip_flags_and_widths() {
+        assert_eq!(specifiers("%-d.%_m %3f %.3f %:z %%H x").collect::<String>(), "dmffz%");
+    }
+
+    #[test]
+    fn seconds() {
+        assert!(!has_seconds("%H:%M", None));
+        assert!(!has_seconds("%%S", None));
+        for fmt in ["%H:%M:%S", "%T", "%-S", "%.3f", "%s", "%+"] { assert!(has_seconds(fmt, None), "{}", fmt); }
+    }
+
+    #[test]
+    fn format_without_names() {
+        assert_eq!(format("%a %H:%M %Z", &time(), Some("CEST"), None), "Sun 14:05 CEST");
+        // An abbreviation isn't taken for a format.
+        assert_eq!(format("%Z", &time(), Some("A%H"), None), "A%H");
+        assert_eq!(format("%Z", &time(), None, None), "+02:00");
+    }
+
+    #[test]
+    fn invalid_formats() {
+        assert!(validate("%H:%M").is_ok());
+        assert!(validate("%Q").is_err());
+    }
+
+    // The C locale is there on every system.
+    #[test]
+    fn c_locale() {
+        let names = Names::load(Some("C")).unwrap();
+        assert_eq!(names.weekday_abbr(0), "Mon");
+        assert_eq!(names.month(2), "March");
+        assert_eq!(format("%A %B %p", &time(), None, Some(&names)), "Sunday March PM");
+        assert!(has_seconds("%X", Some(&names)));
+        assert!(has_seconds("%c", Some(&names)));
+        assert!(!has_seconds("%x", Some(&names)));
+        assert_eq!(format("%Ey", &time(), None, Some(&names)), "24");
+        assert!(Names::load(Some("xx_NOWHERE.UTF-8")).is_err());
+    }
+}
@@ -1,74 +1,86 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
+mod autohide;
+mod background;
+mod calendar;
+mod color;
+mod command;
+mod config;
+mod emoji;
+mod icon_theme;
+mod ics;
+mod layout;
+mod locale;
+mod menu;
+mod metrics;
+mod render;
+mod sni;
+mod text;
+mod tooltip;
+mod tray;
+mod tz;
+mod widget;
+
 use std::os::unix::io::AsRawFd;
-use std::time::{SystemTime, UNIX_EPOCH};
-use chrono::Local;
+use std::time::{Duration, Instant};
 use x11rb::connection::Connection;
 use x11rb::protocol::xproto::*;
 use x11rb::wrapper::ConnectionExt as _;
 use x11rb::rust_connection::RustConnection;
 use x11rb::protocol::Event;
-use x11rb::CURRENT_TIME;
-
-use ab_glyph::{FontRef, Font, PxScale, ScaleFont, point};
-use image::{RgbaImage, ImageBuffer, imageops::FilterType};
-
-// ... (CONSTANTS REMAIN UNCHANGED) ...
-const PANEL_HEIGHT: u16 = 38;
-const ICON_SIZE: u16 = 24;
-const BG_COLOR: u32 = 0x1d1f21;
-const ACTIVE_BG_COLOR: u32 = 0x373b41;
-const HOVER_BG_COLOR: u32 = 0x282a2e;
-const TEXT_COLOR: u32 = 0xe0e0e0;
-const DATE_COLOR: u32 = 0x969896;
-const TRAY_ICON_WIDTH: u16 = 32;
-const UNDERLINE_COLOR: u32 = 0x5FAFAF;
-const UNDERLINE_HEIGHT: u16 = 2;
-const FONT_PATH: &str = "/usr/share/fonts/TTF/OpenSans-Light.ttf";
-const FONT_SIZE_MAIN: f32 = 15.0;
-const FONT_SIZE_DATE: f32 = 12.0;
-const TEXT_Y_OFFSET: i16 = 11;
-const ICON_Y_OFFSET: i16 = 6;
-
-// ... (STRUCT CachedWindowData REMAINS UNCHANGED) ...
-struct CachedWindowData {
-    title: String,
-    icon_buffer: Option<Vec<u8>>,
-    icon_width: u16,
-    icon_height: u16,
+
+use autohide::Autohide;
+use config::{Config, FullscreenMode, Theme};
+use layout::{Layout, Region, Slot};
+use metrics::Metrics;
+use render::{Backdrop, Renderer};
+use text::Fonts;
+use tooltip::Tooltip;
+use widget::{Change, Ctx};
+
+// How long the pointer rests on a widget before its tooltip shows.
+const TOOLTIP_DELAY: Duration = Duration::from_millis(500);
+
+// Visual shared by the panel and its popups, so they can share GCs and blending code.
+#[derive(Clone, Copy)]
+struct VisualInfo {
+    depth: u8,
+    visual: Visualid,
+    colormap: Colormap,
 }
 
-// In AppState, we add a buffer for the window list to avoid allocating it every frame (Optimization)
-struct AppState<'a> {
+struct AppState {
     conn: RustConnection,
     atoms: Atoms,
     screen_num: usize,
     win_id: Window,
-    pixmap_id: Pixmap,
     gc_id: Gcontext,
+    pixmap: Pixmap,
+    visual: VisualInfo,
+    renderer: Box<dyn Renderer>,
+    metrics: Metrics,
     width: u16,
-    tray_icons: Vec<Window>,
-    click_regions: Vec<(i16, i16, Window)>,
-    font: FontRef<'a>,
+    panel_y: i16,
+    config: Config,
+    theme: Theme,
+    backdrop: Option<Backdrop>,
+    autohide: Autohide,
+    layout: Layout,
+    font: Fonts,
     mouse_x: i16,
-    window_cache: HashMap<Window, CachedWindowData>,
-    hovered_window: Option<Window>,
+    tooltip: Tooltip,
+    // When the pointer came to rest on the hovered widget plus the delay.
+    tooltip_due: Option<Instant>,
     render_buffer: Vec<u8>,
-    raw_windows_buf: Vec<Window>, // <--- !!! Added for optimization
+    // Followed for the fullscreen check, whether or not a taskbar is configured.
+    active_window: Window,
+    // Unmapped or lowered for a fullscreen window.
+    fullscreen_hidden: bool,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // ... (FONT LOADING UNCHANGED) ...
-    let font_path = if std::path::Path::new("/usr/share/fonts/TTF/DejaVuSans.ttf").exists() {
-        "/usr/share/fonts/TTF/DejaVuSans.ttf"
-    } else {
-        FONT_PATH
-    };
-    let mut font_file = File::open(font_path).or_else(|_| File::open(FONT_PATH)).map_err(|_| format!("Could not find font."))?;
-    let mut font_data = Vec::new();
-    font_file.read_to_end(&mut font_data)?;
-    let font = FontRef::try_from_slice(&font_data)?;
+    let mut config = Config::load()?;
+    let widgets: Vec<(String, layout::Section)> = config.layout.widgets()?.into_iter().map(|(n, s)| (n.to_string(), s)).collect();
+
+    let font = Fonts::load(&config.font)?;
 
     let (conn, screen_num) = RustConnection::connect(None)?;
     let screen = &conn.setup().roots[screen_num];
@@ -77,25 +89,50 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let screen_height = screen.height_in_pixels;
 
     let atoms = Atoms::new(&conn)?;
+    let metrics = Metrics::new(config.panel.scale.or_else(|| metrics::xft_scale(&conn, root)).unwrap_or(1.0));
+    let panel_height = metrics.panel_height;
+
+    // The tint keeps its alpha over a background image even when the colors are made opaque below.
+    config.background.tint.get_or_insert(config.colors.background);
+    let argb_visual = if config.panel.transparent && compositor_running(&conn, screen_num)? {
+        find_argb_visual(screen)
+    } else {
+        None
+    };
+    let visual = match argb_visual {
+        Some(visual) => {
+            let colormap = conn.generate_id()?;
+            conn.create_colormap(ColormapAlloc::NONE, colormap, root, visual)?;
+            VisualInfo { depth: 32, visual, colormap }
+        }
+        None => {
+            config.colors = config.colors.opaque();
+            VisualInfo { depth: screen.root_depth, visual: screen.root_visual, colormap: screen.default_colormap }
+        }
+    };
+    let colors = config.colors;
+    let theme = config.theme.resolve(&colors);
+    let theme = if argb_visual.is_some() { theme } else { theme.opaque() };
+
     let win_id = conn.generate_id()?;
     let gc_id = conn.generate_id()?;
     let pixmap_id = conn.generate_id()?;
 
-    let y_pos = (screen_height - PANEL_HEIGHT) as i16;
+    let y_pos = screen_height.saturating_sub(panel_height) as i16;
 
-    // !!! CHANGE 1: Adding EventMask::LEAVE_WINDOW !!!
     let win_values = CreateWindowAux::new()
-        .background_pixel(BG_COLOR)
-        .event_mask(EventMask::EXPOSURE | EventMask::PROPERTY_CHANGE | EventMask::BUTTON_PRESS | EventMask::POINTER_MOTION | EventMask::LEAVE_WINDOW);
+        .background_pixel(colors.background.pixel())
+        .border_pixel(0)
+        .colormap(visual.colormap)
+        .event_mask(EventMask::EXPOSURE | EventMask::PROPERTY_CHANGE | EventMask::BUTTON_PRESS | EventMask::POINTER_MOTION | EventMask::ENTER_WINDOW | EventMask::LEAVE_WINDOW);
 
     conn.create_window(
-        screen.root_depth, win_id, root,
-        0, y_pos, width, PANEL_HEIGHT, 0,
-        WindowClass::INPUT_OUTPUT, screen.root_visual, &win_values,
+        visual.depth, win_id, root,
+        0, y_pos, width, panel_height, 0,
+        WindowClass::INPUT_OUTPUT, visual.visual, &win_values,
     )?;
 
-    // ... (REST OF WINDOW SETUP UNCHANGED) ...
-    conn.create_pixmap(screen.root_depth, pixmap_id, win_id, width, PANEL_HEIGHT)?;
+    conn.create_pixmap(visual.depth, pixmap_id, win_id, width, panel_height)?;
     conn.change_property32(PropMode::REPLACE, win_id, atoms._net_wm_window_type, atoms.atom, &[atoms._net_wm_window_type_dock])?;
     conn.change_property32(PropMode::REPLACE, win_id, atoms._net_wm_desktop, atoms.cardinal, &[0xFFFFFFFF])?;
 
@@ -103,610 +140,422 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     hints_data[0] = 12;
     conn.change_property32(PropMode::REPLACE, win_id, atoms.wm_normal_hints, AtomEnum::WM_SIZE_HINTS, &hints_data)?;
 
-    let struts_partial = [0, 0, 0, PANEL_HEIGHT as u32, 0, 0, 0, 0, 0, 0, 0, width as u32];
-    conn.change_property32(PropMode::REPLACE, win_id, atoms._net_wm_strut_partial, atoms.cardinal, &struts_partial)?;
+    set_struts(&conn, &atoms, win_id, width, panel_height)?;
 
-    let gc_values = CreateGCAux::new().foreground(TEXT_COLOR).background(BG_COLOR);
+    let gc_values = CreateGCAux::new().foreground(colors.text.pixel()).background(colors.background.pixel());
     conn.create_gc(gc_id, win_id, &gc_values)?;
 
-    conn.set_selection_owner(win_id, atoms.net_system_tray_s0, CURRENT_TIME)?;
-    let tray_msg = ClientMessageEvent {
-        response_type: CLIENT_MESSAGE_EVENT,
-        format: 32, sequence: 0, window: root,
-        type_: atoms.manager,
-        data: [CURRENT_TIME, atoms.net_system_tray_s0, win_id, 0, 0].into(),
-    };
-    conn.send_event(false, root, EventMask::STRUCTURE_NOTIFY, tray_msg)?;
-
     conn.map_window(win_id)?;
     conn.configure_window(win_id, &ConfigureWindowAux::new().x(0).y(y_pos as i32))?;
 
     let root_values = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE);
     conn.change_window_attributes(root, &root_values)?;
 
+    let tooltip = Tooltip::new(&conn, screen_num, visual, colors)?;
+    let renderer = render::create(&conn, config.panel.renderer, screen_num, visual, pixmap_id, gc_id, width, panel_height)?;
+
     let mut app = AppState {
-        conn, atoms, screen_num, win_id, pixmap_id, gc_id, width,
-        tray_icons: Vec::new(),
-        click_regions: Vec::new(),
+        conn, atoms, screen_num, win_id, gc_id, visual, renderer, metrics, width,
+        pixmap: pixmap_id,
+        panel_y: y_pos,
+        autohide: Autohide::new(config.autohide),
+        config,
+        theme,
+        backdrop: None,
+        layout: Layout { stale: true, ..Layout::default() },
         font,
         mouse_x: -1,
-        window_cache: HashMap::new(),
-        hovered_window: None,
+        tooltip,
+        tooltip_due: None,
         render_buffer: Vec::with_capacity(2048),
-        raw_windows_buf: Vec::with_capacity(64), // Buffer initialization
+        active_window: x11rb::NONE,
+        fullscreen_hidden: false,
     };
 
+    for (name, section) in &widgets {
+        let (mut ctx, layout) = split(&mut app);
+        let widget = widget::create(name, &mut ctx)?;
+        layout.slots.push(Slot { widget, section: *section, region: Region::default() });
+    }
+
+    update_backdrop(&mut app)?;
+    update_active_window(&mut app)?;
     redraw(&mut app)?;
 
-    let mut last_time_str = Local::now().format("%H:%M").to_string();
     let fd = app.conn.stream().as_raw_fd();
 
     loop {
         app.conn.flush()?;
 
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        let nanos = now.subsec_nanos();
-        let millis_until_next_sec = (1000 - (nanos / 1_000_000)) as i32;
-        let timeout = millis_until_next_sec + 10;
+        let now = Instant::now();
+        let mut wake = app.layout.slots.iter().filter_map(|s| s.widget.next_tick()).chain(app.tooltip_due).min();
+        if let Some(wait) = app.autohide.timeout(now, autohide_max(&app)) {
+            wake = Some(wake.map_or(now + wait, |w| w.min(now + wait)));
+        }
+        // Rounded up, so that a tick is due when poll returns.
+        let timeout = wake.map_or(-1, |w| w.saturating_duration_since(now).as_micros().div_ceil(1000).min(i32::MAX as u128) as i32);
 
+        // The X connection first, then one entry per slot (-1, which poll ignores, for widgets without a descriptor).
+        let mut poll_fds = vec![libc::pollfd { fd, events: libc::POLLIN, revents: 0 }];
+        poll_fds.extend(app.layout.slots.iter().map(|s| libc::pollfd { fd: s.widget.fd().unwrap_or(-1), events: libc::POLLIN, revents: 0 }));
         unsafe {
-            let mut poll_fd = libc::pollfd {
-                fd,
-                events: libc::POLLIN,
-                revents: 0,
-            };
-            libc::poll(&mut poll_fd, 1, timeout);
+            libc::poll(poll_fds.as_mut_ptr(), poll_fds.len() as libc::nfds_t, timeout);
         }
 
-        let mut should_redraw = false;
+        let now = Instant::now();
+        if app.tooltip_due.is_some_and(|at| at <= now) {
+            app.tooltip_due = None;
+            update_tooltip(&mut app)?;
+        }
+        {
+            let (mut ctx, layout) = split(&mut app);
+            for i in 0..layout.slots.len() {
+                let mut change = Change::None;
+                if poll_fds[i + 1].revents != 0 {
+                    change = change.max(layout.slots[i].widget.fd_ready(&mut ctx)?);
+                }
+                if layout.slots[i].widget.next_tick().is_some_and(|at| at <= now) {
+                    change = change.max(layout.slots[i].widget.tick(&mut ctx, now)?);
+                }
+                layout.apply(i, change);
+            }
+        }
 
         while let Some(event) = app.conn.poll_for_event()? {
-            match event {
-                Event::Expose(e) if e.window == win_id && e.count == 0 => { should_redraw = true; },
-                Event::PropertyNotify(e) => {
-                    if e.atom == app.atoms._net_client_list || e.atom == app.atoms._net_active_window {
-                        should_redraw = true;
-                    }
-                    else if e.atom == app.atoms._net_wm_name || e.atom == AtomEnum::WM_NAME.into() || e.atom == app.atoms._net_wm_icon {
-                        app.window_cache.remove(&e.window);
-                        should_redraw = true;
+            match &event {
+                Event::Expose(e) if e.window == win_id && e.count == 0 => { app.layout.exposed = true; },
+                Event::Expose(e) if e.window == app.tooltip.window() && e.count == 0 => {
+                    app.tooltip.draw(&app.conn, app.metrics, &app.font, &mut app.render_buffer)?;
+                },
+                Event::PropertyNotify(e) if e.window == root => {
+                    if e.atom == app.atoms._net_active_window {
+                        update_active_window(&mut app)?;
+                    } else if e.atom == AtomEnum::RESOURCE_MANAGER.into() && app.config.panel.scale.is_none() {
+                        // xrdb -merge with a new Xft.dpi, e.g. from a settings daemon on a display change.
+                        let scale = metrics::xft_scale(&app.conn, root).unwrap_or(1.0);
+                        update_scale(&mut app, scale)?;
+                    } else if e.atom == app.atoms._xrootpmap_id && app.config.background.image.as_deref() == Some("root") {
+                        // The wallpaper changed.
+                        update_backdrop(&mut app)?;
                     }
                 },
-                Event::ButtonPress(e) => {
-                    handle_click(&app, e.event_x, e.detail)?;
-                    should_redraw = true;
+                Event::PropertyNotify(e) if e.window == app.active_window && e.atom == app.atoms._net_wm_state => {
+                    update_fullscreen(&mut app)?;
                 },
-                Event::MotionNotify(e) => {
-                    if e.event_x != app.mouse_x {
-                        app.mouse_x = e.event_x;
-                        let new_hovered = get_hovered_window(&app, e.event_x);
-                        // The logic here is already good: redraw ONLY if the window under the mouse changes
-                        if new_hovered != app.hovered_window {
-                            app.hovered_window = new_hovered;
-                            should_redraw = true;
-                        }
+                Event::ButtonPress(e) if e.event == win_id => {
+                    app.tooltip.hide(&app.conn)?;
+                    app.tooltip_due = None;
+                    let (mut ctx, layout) = split(&mut app);
+                    if let Some(i) = layout.slot_at(e.event_x) {
+                        let slot = &mut layout.slots[i];
+                        let change = match e.detail {
+                            4 | 5 => slot.widget.scroll(&mut ctx, slot.region, e, if e.detail == 4 { -1 } else { 1 })?,
+                            _ => slot.widget.click(&mut ctx, slot.region, e)?,
+                        };
+                        layout.apply(i, change);
                     }
                 },
-                // !!! CHANGE 2: Handling mouse leaving the window !!!
-                Event::LeaveNotify(e) => {
-                    // Check detail != NotifyInferior to not reset hover,
-                    // if mouse moved to a child window (e.g. tray icon if it's inside the panel)
-                    if e.event == win_id && e.detail != NotifyDetail::INFERIOR {
-                        if app.hovered_window.is_some() {
-                            app.hovered_window = None;
-                            app.mouse_x = -1; // Reset X position
-                            should_redraw = true;
-                        }
-                    }
+                Event::MotionNotify(e) if e.event == win_id && e.event_x != app.mouse_x => {
+                    app.mouse_x = e.event_x;
+                    update_hover(&mut app)?;
                 },
-                Event::ClientMessage(e) if e.type_ == app.atoms._net_system_tray_opcode => {
-                    let data = e.data.as_data32();
-                    if data[1] == 0 { handle_docking(&mut app, data[2])?; should_redraw = true; }
-                }
-                Event::DestroyNotify(e) => {
-                    if let Some(pos) = app.tray_icons.iter().position(|&w| w == e.window) {
-                        app.tray_icons.remove(pos);
-                        should_redraw = true;
-                    }
-                    if app.window_cache.contains_key(&e.window) {
-                        app.window_cache.remove(&e.window);
-                        should_redraw = true;
-                    }
+                // Check detail != NotifyInferior to not reset hover,
+                // if mouse moved to a child window (e.g. tray icon if it's inside the panel)
+                Event::LeaveNotify(e) if e.event == win_id && e.detail != NotifyDetail::INFERIOR => {
+                    app.autohide.pointer_left();
+                    app.mouse_x = -1;
+                    update_hover(&mut app)?;
+                },
+                // Reaching the strip left at the screen edge brings a hidden panel back.
+                Event::EnterNotify(e) if e.event == win_id => {
+                    app.autohide.reveal();
+                },
+                // Only client windows select StructureNotify; moves of reparented clients come as synthetic events.
+                Event::ConfigureNotify(e) if e.window == app.active_window => {
+                    update_fullscreen(&mut app)?;
                 }
                 _ => {}
             }
-        }
 
-        let current_time_str = Local::now().format("%H:%M").to_string();
-        if current_time_str != last_time_str {
-            last_time_str = current_time_str;
-            should_redraw = true;
+            app.renderer.event(&event);
+            let (mut ctx, layout) = split(&mut app);
+            for i in 0..layout.slots.len() {
+                let change = layout.slots[i].widget.event(&mut ctx, &event)?;
+                layout.apply(i, change);
+            }
         }
 
-        if should_redraw {
-            redraw(&mut app)?;
-        }
+        redraw(&mut app)?;
+        update_autohide(&mut app)?;
     }
 }
 
-// ... (get_hovered_window and fetch_window_data UNCHANGED) ...
-fn get_hovered_window(app: &AppState, mouse_x: i16) -> Option<Window> {
-    for (start, end, win) in &app.click_regions {
-        if mouse_x >= *start && mouse_x <= *end {
-            return Some(*win);
-        }
-    }
-    None
+// Lends the widgets what they need next to the layout that holds them.
+fn split(app: &mut AppState) -> (Ctx<'_>, &mut Layout) {
+    let AppState {
+        conn, atoms, screen_num, win_id, gc_id, visual, panel_y, metrics, config, theme, font, renderer, render_buffer, layout, ..
+    } = app;
+    let ctx = Ctx {
+        conn, atoms, screen_num: *screen_num, win: *win_id, gc: *gc_id, visual: *visual, panel_y: *panel_y,
+        metrics: *metrics, config, theme, fonts: font, renderer: renderer.as_mut(), render_buffer,
+    };
+    (ctx, layout)
 }
 
-fn fetch_window_data(conn: &RustConnection, atoms: &Atoms, win: Window) -> CachedWindowData {
-    let utf_cookie = conn.get_property(false, win, atoms._net_wm_name, atoms.utf8_string, 0, 1024).ok();
-    let str_cookie = conn.get_property(false, win, AtomEnum::WM_NAME, AtomEnum::STRING, 0, 1024).ok();
-
-    let mut title = String::new();
-    if let Some(cookie) = utf_cookie {
-        if let Ok(r) = cookie.reply() {
-            if r.value_len > 0 { title = String::from_utf8_lossy(&r.value).to_string(); }
-        }
-    }
-    if title.is_empty() {
-        if let Some(cookie) = str_cookie {
-            if let Ok(r) = cookie.reply() { title = String::from_utf8_lossy(&r.value).to_string(); }
-        }
+// Tells the widget under the pointer where it is, and the one it left that it's gone. A tooltip goes with the
+// widget it belongs to and follows the pointer within it.
+fn update_hover(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let x = (app.mouse_x >= 0).then_some(app.mouse_x);
+    let (mut ctx, layout) = split(app);
+    let (old, hovered) = (layout.hovered, x.and_then(|x| layout.slot_at(x)));
+    if let Some(old) = old.filter(|&old| Some(old) != hovered) {
+        let slot = &mut layout.slots[old];
+        let change = slot.widget.hover(&mut ctx, slot.region, None);
+        layout.apply(old, change);
     }
-
-    let sanitized_title: String = title.chars()
-        .filter(|c| !c.is_control() && match *c {
-            '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' | '\u{feff}' => false,
-            _ => true
-        })
-        .collect();
-
-    let mut icon_buffer = None;
-    let mut icon_w = 0;
-    let mut icon_h = 0;
-
-    if let Ok(cookie) = conn.get_property(false, win, atoms._net_wm_icon, atoms.cardinal, 0, 100000) {
-        if let Ok(reply) = cookie.reply() {
-            if reply.value_len > 0 {
-                if let Some(data_iter) = reply.value32() {
-                    let data: Vec<u32> = data_iter.collect();
-                    let mut best_start = 0;
-                    let mut best_w = 0;
-                    let mut max_w = 0;
-                    let mut max_start = 0;
-                    let mut idx = 0;
-                    while idx + 2 < data.len() {
-                        let w = data[idx] as usize;
-                        let h = data[idx+1] as usize;
-                        let size = w * h;
-                        if idx + 2 + size > data.len() { break; }
-                        if w > max_w { max_w = w; max_start = idx + 2; }
-                        if w >= ICON_SIZE as usize {
-                            if best_w == 0 || w < best_w { best_w = w; best_start = idx + 2; }
-                        }
-                        idx += 2 + size;
-                    }
-                    if best_w == 0 && max_w > 0 { best_w = max_w; best_start = max_start; }
-
-                    if best_w > 0 {
-                        let src_w = best_w;
-                        let src_h = data[best_start - 1] as usize;
-                        let pixels = &data[best_start..best_start + (src_w * src_h)];
-                        let mut img_buf: RgbaImage = ImageBuffer::new(src_w as u32, src_h as u32);
-                        for (i, &px) in pixels.iter().enumerate() {
-                            let x_px = (i % src_w) as u32;
-                            let y_px = (i / src_w) as u32;
-                            img_buf.put_pixel(x_px, y_px, image::Rgba([
-                                ((px >> 16) & 0xFF) as u8,
-                                ((px >> 8) & 0xFF) as u8,
-                                (px & 0xFF) as u8,
-                                ((px >> 24) & 0xFF) as u8,
-                            ]));
-                        }
-                        let resized = image::imageops::resize(&img_buf, ICON_SIZE as u32, ICON_SIZE as u32, FilterType::Lanczos3);
-                        icon_buffer = Some(resized.into_raw());
-                        icon_w = ICON_SIZE;
-                        icon_h = ICON_SIZE;
-                    }
-                }
-            }
-        }
+    if let Some(i) = hovered {
+        let slot = &mut layout.slots[i];
+        let change = slot.widget.hover(&mut ctx, slot.region, x);
+        layout.apply(i, change);
     }
+    layout.hovered = hovered;
 
-    CachedWindowData {
-        title: sanitized_title,
-        icon_buffer,
-        icon_width: icon_w,
-        icon_height: icon_h,
+    if hovered != old {
+        app.tooltip.hide(&app.conn)?;
+        app.tooltip_due = hovered.map(|_| Instant::now() + TOOLTIP_DELAY);
+    } else if app.tooltip.slot.is_some() {
+        update_tooltip(app)?;
     }
+    Ok(())
 }
 
-// !!! CHANGE 3: Optimizing redraw (using shared vector buffer) !!!
-fn redraw(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
-    let draw_target = app.pixmap_id;
-
-    let rect = Rectangle { x: 0, y: 0, width: app.width, height: PANEL_HEIGHT };
-    app.conn.change_gc(app.gc_id, &ChangeGCAux::new().foreground(BG_COLOR))?;
-    app.conn.poly_fill_rectangle(draw_target, app.gc_id, &[rect])?;
-    app.conn.change_gc(app.gc_id, &ChangeGCAux::new().foreground(TEXT_COLOR))?;
-
-    app.click_regions.clear();
-
-    let time_str = Local::now().format("%H:%M").to_string();
-    let date_str = Local::now().format("%Y-%m-%d").to_string();
-    let tray_w = (app.tray_icons.len() as u16) * TRAY_ICON_WIDTH;
-
-    let time_width = calculate_text_width(&app.font, FONT_SIZE_MAIN, &time_str);
-    let date_width = calculate_text_width(&app.font, FONT_SIZE_DATE, &date_str);
-    let max_text_width = if time_width > date_width { time_width } else { date_width };
-
-    let clock_x_start = app.width as i16 - (max_text_width as i16 + 8);
-    let tray_start_x = clock_x_start - (tray_w as i16 + 15);
-
-    let time_x_offset = if time_width < max_text_width { (max_text_width - time_width) / 2.0 } else { 0.0 };
-    draw_text_render(&app.conn, draw_target, app.gc_id, &app.font, &mut app.render_buffer, &time_str, FONT_SIZE_MAIN, clock_x_start + time_x_offset as i16, 2, BG_COLOR, TEXT_COLOR)?;
-
-    let date_x_offset = if date_width < max_text_width { (max_text_width - date_width) / 2.0 } else { 0.0 };
-    draw_text_render(&app.conn, draw_target, app.gc_id, &app.font, &mut app.render_buffer, &date_str, FONT_SIZE_DATE, clock_x_start + date_x_offset as i16, 20, BG_COLOR, DATE_COLOR)?;
-
-    for (i, &win) in app.tray_icons.iter().enumerate() {
-        let x = tray_start_x + (i as i16 * TRAY_ICON_WIDTH as i16);
-        let y_tray = (PANEL_HEIGHT - 24) / 2;
-        app.conn.configure_window(win, &ConfigureWindowAux::new().x(x as i32).y(y_tray as i32).width(24).height(24))?;
-        app.conn.map_window(win)?;
+// Shows or refreshes the tooltip of the hovered widget, or hides it when the widget has none there.
+fn update_tooltip(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let x = app.mouse_x;
+    let (ctx, layout) = split(app);
+    let hovered = layout.hovered.filter(|_| x >= 0);
+    let lines = hovered.and_then(|i| {
+        let slot = &mut layout.slots[i];
+        slot.widget.tooltip(&ctx, slot.region, x)
+    });
+    match (hovered, lines) {
+        (Some(i), Some(lines)) => {
+            let anchor_y = app.panel_y + app.autohide.offset() as i16;
+            app.tooltip.show(&app.conn, app.screen_num, app.metrics, &app.font, &mut app.render_buffer, i, lines, x, anchor_y)
+        }
+        _ => app.tooltip.hide(&app.conn),
     }
+}
 
-    let window_area_limit = tray_start_x - 10;
-    let available_width_for_windows = window_area_limit as f32;
-
+// Follows _NET_ACTIVE_WINDOW for the fullscreen check, watching the active window for state and geometry changes.
+fn update_active_window(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
     let root = app.conn.setup().roots[app.screen_num].root;
-    let client_cookie = app.conn.get_property(false, root, app.atoms._net_client_list, AtomEnum::ANY, 0, 1024)?;
-    let active_cookie = app.conn.get_property(false, root, app.atoms._net_active_window, AtomEnum::ANY, 0, 1)?;
-
-    let active_win = active_cookie.reply().ok()
+    let active = app.conn.get_property(false, root, app.atoms._net_active_window, AtomEnum::ANY, 0, 1)?.reply().ok()
         .and_then(|r| r.value32().and_then(|mut i| i.next()))
         .unwrap_or(0);
-
-    // OPTIMIZATION: Using buffer from AppState instead of creating a new vector
-    app.raw_windows_buf.clear();
-    if let Ok(reply) = client_cookie.reply() {
-        if let Some(list) = reply.value32() {
-            // extend instead of creating a new Vec
-            app.raw_windows_buf.extend(list);
+    if active != app.active_window {
+        app.active_window = active;
+        if active != x11rb::NONE {
+            let values = ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY);
+            app.conn.change_window_attributes(active, &values).ok();
         }
+        update_fullscreen(app)?;
     }
-    // Fallback for windows without _NET_CLIENT_LIST
-    if app.raw_windows_buf.is_empty() {
-        if let Ok(tree) = app.conn.query_tree(root)?.reply() {
-            for w in tree.children {
-                 let state = app.conn.get_property(false, w, app.atoms.wm_state, AtomEnum::ANY, 0, 1)?.reply();
-                 if let Ok(r) = state { if r.value_len > 0 { app.raw_windows_buf.push(w); } }
-            }
-        }
-    }
-
-    struct WindowDrawData<'a> {
-        win: Window,
-        data: &'a CachedWindowData,
-        ideal_width: f32,
-        is_active: bool,
-    }
+    Ok(())
+}
 
-    let mut visible_windows: Vec<WindowDrawData> = Vec::new();
-    let mut total_ideal_width: f32 = 0.0;
-
-    // Iterating over the cached vector
-    for &w in &app.raw_windows_buf {
-        if !app.window_cache.contains_key(&w) {
-            let type_cookie = app.conn.get_property(false, w, app.atoms._net_wm_window_type, AtomEnum::ATOM, 0, 1024).ok();
-            let mut is_dock = false;
-            if let Some(cookie) = type_cookie {
-                if let Ok(reply) = cookie.reply() {
-                    if let Some(mut atoms_iter) = reply.value32() {
-                        if atoms_iter.any(|a|
-                            a == app.atoms._net_wm_window_type_dock ||
-                            a == app.atoms._net_wm_window_type_desktop ||
-                            a == app.atoms._net_wm_window_type_splash
-                        ) { is_dock = true; }
-                    }
-                }
-            }
-            if !is_dock {
-                app.conn.change_window_attributes(w, &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE)).ok();
-                let data = fetch_window_data(&app.conn, &app.atoms, w);
-                app.window_cache.insert(w, data);
-            }
+// Gets the panel out of the way while the active window is fullscreen over it, and back once it isn't.
+fn update_fullscreen(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let mode = app.config.panel.fullscreen;
+    let covered = mode != FullscreenMode::Ignore && app.active_window != x11rb::NONE
+        && fullscreen_over_panel(app, app.active_window).unwrap_or(false);
+    if covered == app.fullscreen_hidden { return Ok(()); }
+    app.fullscreen_hidden = covered;
+
+    match (mode, covered) {
+        (FullscreenMode::Unmap, true) => { app.conn.unmap_window(app.win_id)?; }
+        (FullscreenMode::Lower, true) => {
+            app.conn.configure_window(app.win_id, &ConfigureWindowAux::new().stack_mode(StackMode::BELOW))?;
         }
-    }
-
-    for &w in &app.raw_windows_buf {
-        if w == app.win_id || app.tray_icons.contains(&w) { continue; }
-
-        if let Some(data) = app.window_cache.get(&w) {
-            let class_str = data.title.to_lowercase();
-            if class_str.contains("conky") || class_str.contains("tint2") || class_str.contains("plank") { continue; }
-
-            let text_width = calculate_text_width(&app.font, FONT_SIZE_MAIN, &data.title);
-            let max_window_width = 250.0;
-            let calc_width = (ICON_SIZE as f32 + text_width + 24.0).min(max_window_width);
-
-            total_ideal_width += calc_width;
-
-            visible_windows.push(WindowDrawData {
-                win: w,
-                data,
-                ideal_width: calc_width,
-                is_active: w == active_win,
-            });
+        // Restores whichever was done; the other is a no-op.
+        (_, false) => {
+            app.conn.map_window(app.win_id)?;
+            app.conn.configure_window(app.win_id, &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE))?;
         }
+        (FullscreenMode::Ignore, true) => {}
     }
+    Ok(())
+}
 
-    let window_count = visible_windows.len();
-    if window_count == 0 {
-        app.conn.copy_area(app.pixmap_id, app.win_id, app.gc_id, 0, 0, 0, 0, app.width, PANEL_HEIGHT)?;
-        app.conn.flush()?;
-        return Ok(());
-    }
-
-    let use_compression = total_ideal_width > available_width_for_windows;
-    let fixed_width_per_window = if use_compression {
-        (available_width_for_windows / window_count as f32).floor()
-    } else { 0.0 };
-
-    let mut current_x: i16 = 0;
-
-    for win_data in visible_windows {
-        let actual_width = if use_compression { fixed_width_per_window as i16 } else { win_data.ideal_width as i16 };
-
-        let mut bg = BG_COLOR;
-        if win_data.is_active {
-            bg = ACTIVE_BG_COLOR;
-            app.conn.change_gc(app.gc_id, &ChangeGCAux::new().foreground(ACTIVE_BG_COLOR))?;
-            app.conn.poly_fill_rectangle(draw_target, app.gc_id, &[Rectangle{x: current_x, y: 2, width: actual_width as u16, height: PANEL_HEIGHT-4}])?;
-
-            app.conn.change_gc(app.gc_id, &ChangeGCAux::new().foreground(UNDERLINE_COLOR))?;
-            app.conn.poly_fill_rectangle(draw_target, app.gc_id, &[Rectangle{
-                x: current_x,
-                y: (PANEL_HEIGHT - UNDERLINE_HEIGHT - 2) as i16,
-                width: actual_width as u16,
-                height: UNDERLINE_HEIGHT
-            }])?;
-        } else {
-            if Some(win_data.win) == app.hovered_window {
-                bg = HOVER_BG_COLOR;
-                app.conn.change_gc(app.gc_id, &ChangeGCAux::new().foreground(HOVER_BG_COLOR))?;
-                app.conn.poly_fill_rectangle(draw_target, app.gc_id, &[Rectangle{x: current_x, y: 2, width: actual_width as u16, height: PANEL_HEIGHT-4}])?;
-            }
-        }
-
-        if actual_width >= (ICON_SIZE as i16 + 6) {
-             if let Some(ref pixels) = win_data.data.icon_buffer {
-                 draw_icon_fast(&app.conn, draw_target, app.gc_id, pixels, win_data.data.icon_width, win_data.data.icon_height, current_x + 6, ICON_Y_OFFSET, bg, &mut app.render_buffer)?;
-             }
-        }
-
-        let text_area_w = actual_width - (ICON_SIZE as i16 + 18);
-        if text_area_w > 10 {
-            let display_text = if use_compression || calculate_text_width(&app.font, FONT_SIZE_MAIN, &win_data.data.title) > text_area_w as f32 {
-                shorten_text_to_fit(&app.font, FONT_SIZE_MAIN, &win_data.data.title, text_area_w as f32)
-            } else {
-                win_data.data.title.clone()
-            };
-
-            draw_text_render(&app.conn, draw_target, app.gc_id, &app.font, &mut app.render_buffer, &display_text, FONT_SIZE_MAIN, current_x + ICON_SIZE as i16 + 14, TEXT_Y_OFFSET, bg, TEXT_COLOR)?;
-        }
+// Whether `win` has _NET_WM_STATE_FULLSCREEN and its root-relative geometry overlaps the panel's shown position.
+fn fullscreen_over_panel(app: &AppState, win: Window) -> Result<bool, Box<dyn std::error::Error>> {
+    let root = app.conn.setup().roots[app.screen_num].root;
+    let state_cookie = app.conn.get_property(false, win, app.atoms._net_wm_state, AtomEnum::ATOM, 0, 64)?;
+    let geometry_cookie = app.conn.get_geometry(win)?;
+    let origin_cookie = app.conn.translate_coordinates(win, root, 0, 0)?;
+
+    let fullscreen = state_cookie.reply()?.value32().is_some_and(|mut i| i.any(|a| a == app.atoms._net_wm_state_fullscreen));
+    let (geometry, origin) = (geometry_cookie.reply()?, origin_cookie.reply()?);
+    if !fullscreen { return Ok(false); }
+
+    let (x, y) = (origin.dst_x as i32, origin.dst_y as i32);
+    let panel_top = app.panel_y as i32;
+    let panel_bottom = panel_top + app.metrics.panel_height as i32;
+    Ok(x < app.width as i32 && x + geometry.width as i32 > 0 && y < panel_bottom && y + geometry.height as i32 > panel_top)
+}
 
-        app.click_regions.push((current_x, current_x + actual_width, win_data.win));
-        current_x += actual_width;
+// Applies a new scale factor: the panel changes height and everything sized from the metrics is redone.
+fn update_scale(app: &mut AppState, scale: f32) -> Result<(), Box<dyn std::error::Error>> {
+    let m = Metrics::new(scale);
+    if m == app.metrics { return Ok(()); }
+    app.metrics = m;
+
+    let screen_height = app.conn.setup().roots[app.screen_num].height_in_pixels;
+    app.panel_y = screen_height.saturating_sub(m.panel_height) as i16;
+    let y = app.panel_y as i32 + app.autohide.offset() as i32;
+    app.conn.configure_window(app.win_id, &ConfigureWindowAux::new().y(y).height(m.panel_height as u32))?;
+    let strut = if app.autohide.offset() > 0 { 0 } else { m.panel_height };
+    set_struts(&app.conn, &app.atoms, app.win_id, app.width, strut)?;
+
+    app.conn.free_pixmap(app.pixmap)?;
+    app.pixmap = app.conn.generate_id()?;
+    app.conn.create_pixmap(app.visual.depth, app.pixmap, app.win_id, app.width, m.panel_height)?;
+    app.renderer.resize(&app.conn, app.pixmap, app.width, m.panel_height)?;
+    update_backdrop(app)?;
+
+    app.tooltip.hide(&app.conn)?;
+    let (mut ctx, layout) = split(app);
+    for slot in &mut layout.slots {
+        slot.widget.rescale(&mut ctx)?;
     }
-
-    app.conn.copy_area(app.pixmap_id, app.win_id, app.gc_id, 0, 0, 0, 0, app.width, PANEL_HEIGHT)?;
-    app.conn.flush()?;
+    app.layout.stale = true;
     Ok(())
 }
 
-// ... (ALL OTHER FUNCTIONS UNCHANGED) ...
-// draw_icon_fast, draw_text_render, calculate_text_width, shorten_text_to_fit, layout_paragraph, handle_click, handle_docking, Atoms
-// ... Insert them here ...
-fn draw_icon_fast(
-    conn: &RustConnection, target: Drawable, gc: Gcontext,
-    pixels: &[u8], width: u16, height: u16,
-    x: i16, y: i16, bg_color: u32,
-    render_buf: &mut Vec<u8>
-) -> Result<(), Box<dyn std::error::Error>> {
-
-    render_buf.clear();
-    let bg_r = ((bg_color >> 16) & 0xFF) as u16;
-    let bg_g = ((bg_color >> 8) & 0xFF) as u16;
-    let bg_b = (bg_color & 0xFF) as u16;
-
-    for chunk in pixels.chunks(4) {
-        let r = chunk[0] as u16;
-        let g = chunk[1] as u16;
-        let b = chunk[2] as u16;
-        let a = chunk[3] as u16;
-
-        let inv_alpha = 255 - a;
-
-        let out_r = ((r * a + bg_r * inv_alpha) >> 8) as u8;
-        let out_g = ((g * a + bg_g * inv_alpha) >> 8) as u8;
-        let out_b = ((b * a + bg_b * inv_alpha) >> 8) as u8;
-
-        render_buf.push(out_b);
-        render_buf.push(out_g);
-        render_buf.push(out_r);
-        render_buf.push(0xFF);
-    }
-
-    conn.put_image(ImageFormat::Z_PIXMAP, target, gc, width, height, x, y, 0, 24, &render_buf)?;
-    Ok(())
+// How far autohide pushes the panel down: all of it but the trigger strip.
+fn autohide_max(app: &AppState) -> u16 {
+    let m = app.metrics;
+    m.panel_height.saturating_sub(m.px(app.config.autohide.strip).clamp(1, m.panel_height))
 }
 
-fn draw_text_render(
-    conn: &RustConnection,
-    target: Drawable,
-    gc: Gcontext,
-    font: &FontRef,
-    render_buf: &mut Vec<u8>,
-    text: &str,
-    font_size: f32, x: i16, y: i16, bg_color: u32, fg_color: u32
-) -> Result<(), Box<dyn std::error::Error>> {
-    if text.is_empty() { return Ok(()); }
-
-    let scale = PxScale::from(font_size);
-    let scaled_font = font.as_scaled(scale);
-
-    let mut glyphs = Vec::new();
-    layout_paragraph(scaled_font, point(0.0, 0.0), 9999.0, text, &mut glyphs);
-    if glyphs.is_empty() { return Ok(()); }
-
-    let height = (font_size.ceil() as usize) + 8;
-    let width = glyphs.last().map(|g| g.position.x + scaled_font.h_advance(g.id)).unwrap_or(0.0).ceil() as usize + 4;
-    if width == 0 { return Ok(()); }
-
-    let buf_size = width * height * 4;
-    if render_buf.capacity() < buf_size {
-        render_buf.reserve(buf_size - render_buf.capacity());
-    }
-    render_buf.clear();
-    render_buf.resize(buf_size, 0);
-
-    let bg_r = ((bg_color >> 16) & 0xFF) as u8;
-    let bg_g = ((bg_color >> 8) & 0xFF) as u8;
-    let bg_b = (bg_color & 0xFF) as u8;
-
-    let fg_r = ((fg_color >> 16) & 0xFF) as u16;
-    let fg_g = ((fg_color >> 8) & 0xFF) as u16;
-    let fg_b = (fg_color & 0xFF) as u16;
-
-    for i in 0..(width * height) {
-        render_buf[i * 4 + 0] = bg_b;
-        render_buf[i * 4 + 1] = bg_g;
-        render_buf[i * 4 + 2] = bg_r;
-        render_buf[i * 4 + 3] = 0xFF;
+// Keeps the panel up while something needs it, starts due hides and moves the window along the slide. Struts
+// go away as soon as the panel starts hiding and come back once it is fully shown again.
+fn update_autohide(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
+    if !app.autohide.enabled() { return Ok(()); }
+    let now = Instant::now();
+    let urgent = app.config.autohide.reveal_on_urgent && app.layout.slots.iter().any(|s| s.widget.wants_attention());
+    if urgent {
+        app.autohide.reveal();
+        app.autohide.postpone();
+    } else if app.autohide.hide_due(now) {
+        let pointer = app.conn.query_pointer(app.win_id)?.reply()?;
+        let over = pointer.same_screen && pointer.win_x >= 0 && pointer.win_y >= 0
+            && (pointer.win_x as u16) < app.width && (pointer.win_y as u16) < app.metrics.panel_height;
+        if over || app.layout.slots.iter().any(|s| s.widget.has_popup()) { app.autohide.postpone(); }
     }
 
-    for glyph in glyphs {
-        if let Some(outlined) = scaled_font.outline_glyph(glyph) {
-            let bounds = outlined.px_bounds();
-            outlined.draw(|gx, gy, c| {
-                let px = bounds.min.x as i32 + gx as i32;
-                let py = bounds.min.y as i32 + gy as i32;
-                if px >= 0 && px < width as i32 && py >= 0 && py < height as i32 {
-                    let idx = ((py as usize) * width + (px as usize)) * 4;
-
-                    let alpha = (c * 256.0) as u16;
-                    let inv_alpha = 256 - alpha;
-
-                    let cur_b = render_buf[idx+0] as u16;
-                    let cur_g = render_buf[idx+1] as u16;
-                    let cur_r = render_buf[idx+2] as u16;
-
-                    render_buf[idx+0] = ((fg_b * alpha + cur_b * inv_alpha) >> 8) as u8;
-                    render_buf[idx+1] = ((fg_g * alpha + cur_g * inv_alpha) >> 8) as u8;
-                    render_buf[idx+2] = ((fg_r * alpha + cur_r * inv_alpha) >> 8) as u8;
-                }
-            });
-        }
+    let before = app.autohide.offset();
+    let Some(offset) = app.autohide.step(now, autohide_max(app)) else { return Ok(()) };
+    app.conn.configure_window(app.win_id, &ConfigureWindowAux::new().y(app.panel_y as i32 + offset as i32))?;
+    if (before == 0) != (offset == 0) {
+        let strut = if offset == 0 { app.metrics.panel_height } else { 0 };
+        set_struts(&app.conn, &app.atoms, app.win_id, app.width, strut)?;
     }
-
-    conn.put_image(ImageFormat::Z_PIXMAP, target, gc, width as u16, height as u16, x, y, 0, 24, &render_buf)?;
     Ok(())
 }
 
-fn calculate_text_width(font: &FontRef, size: f32, text: &str) -> f32 {
-    let scale = PxScale::from(size);
-    let scaled_font = font.as_scaled(scale);
-    let mut width = 0.0;
-    let mut last_glyph_id = None;
-    for c in text.chars() {
-        let glyph_id = scaled_font.glyph_id(c);
-        if let Some(last) = last_glyph_id {
-            width += scaled_font.kern(last, glyph_id);
-        }
-        width += scaled_font.h_advance(glyph_id);
-        last_glyph_id = Some(glyph_id);
+// Recomposes the background image for the panel's current size and position and hands it to the renderer; the
+// window background shows it too, for Expose and tray icons with a parent-relative background.
+fn update_backdrop(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
+    let root = app.conn.setup().roots[app.screen_num].root;
+    let (width, height) = (app.width, app.metrics.panel_height);
+    let tint = app.config.background.tint.unwrap_or(app.config.colors.background);
+    let pixels = background::compose(
+        &app.conn, root, &app.config.background, tint, width, height, app.panel_y, app.visual.depth != 32,
+    )?;
+
+    let old = app.backdrop.take();
+    if let Some(pixels) = pixels {
+        let pixmap = app.conn.generate_id()?;
+        app.conn.create_pixmap(app.visual.depth, pixmap, app.win_id, width, height)?;
+        background::upload(&app.conn, pixmap, app.gc_id, app.visual.depth, width, &pixels)?;
+        app.backdrop = Some(Backdrop { pixels, pixmap });
     }
-    width.ceil()
+    app.renderer.set_backdrop(&app.conn, app.backdrop.as_ref())?;
+    let values = match &app.backdrop {
+        Some(backdrop) => ChangeWindowAttributesAux::new().background_pixmap(backdrop.pixmap),
+        None => ChangeWindowAttributesAux::new().background_pixel(app.config.colors.background.pixel()),
+    };
+    app.conn.change_window_attributes(app.win_id, &values)?;
+    if let Some(old) = old { app.conn.free_pixmap(old.pixmap)?; }
+    app.layout.stale = true;
+    Ok(())
 }
 
-fn shorten_text_to_fit(font: &FontRef, size: f32, text: &str, max_width: f32) -> String {
-    let ellipsis = "...";
-    let ellipsis_width = calculate_text_width(font, size, ellipsis);
-
-    if max_width < ellipsis_width {
-        return String::new();
+// Re-renders only the dirty regions and presents just those, unless a relayout or Expose needs the whole panel.
+// Waits, keeping the regions dirty, while the renderer's last frame is still being read.
+fn redraw(app: &mut AppState) -> Result<(), Box<dyn std::error::Error>> {
+    if app.renderer.busy() { return Ok(()); }
+    let height = app.metrics.panel_height;
+    let full = Rectangle { x: 0, y: 0, width: app.width, height };
+    let relaid = app.layout.stale;
+    if relaid {
+        let (width, spacing) = (app.width, app.metrics.px(app.config.layout.spacing));
+        let (ctx, layout) = split(app);
+        layout.arrange(&ctx, width, spacing);
+        layout.stale = false;
+        // Clears the gaps between regions too.
+        ctx.renderer.fill_background(ctx.conn, full, ctx.config.colors.background)?;
+        layout.exposed = true;
+        // Widgets may have moved under a stationary pointer.
+        update_hover(app)?;
     }
 
-    let target_width = max_width - ellipsis_width;
-    let mut current_text = text.to_string();
-
-    while !current_text.is_empty() {
-        let w = calculate_text_width(font, size, &current_text);
-        if w <= target_width {
-            current_text.push_str(ellipsis);
-            return current_text;
-        }
-        current_text.pop();
+    let mut damage = Vec::new();
+    let tooltip_slot = app.tooltip.slot;
+    let mut tooltip_stale = false;
+    let (mut ctx, layout) = split(app);
+    for (i, slot) in layout.slots.iter_mut().enumerate() {
+        if !slot.region.dirty { continue; }
+        slot.region.dirty = false;
+        if slot.region.width == 0 { continue; }
+        slot.widget.render(&mut ctx, slot.region, relaid, &mut damage)?;
+        tooltip_stale |= tooltip_slot == Some(i);
     }
+    // What the tooltip says may have changed along with the widget.
+    if tooltip_stale { update_tooltip(app)?; }
+    let layout = &mut app.layout;
 
-    if ellipsis_width <= max_width { return ellipsis.to_string(); }
-    String::new()
-}
-
-pub fn layout_paragraph<F, SF>(
-    font: SF, position: ab_glyph::Point, _max_width: f32, text: &str, target: &mut Vec<ab_glyph::Glyph>,
-) where F: Font, SF: ScaleFont<F>, {
-    let v_advance = font.height() + font.line_gap();
-    let mut caret = position + point(0.0, font.ascent());
-    let mut last_glyph_id = None;
-    for c in text.chars() {
-        if c.is_control() {
-            if c == '\n' { caret = point(position.x, caret.y + v_advance); last_glyph_id = None; }
-            continue;
-        }
-        let mut glyph = font.scaled_glyph(c);
-        if let Some(previous) = last_glyph_id { caret.x += font.kern(previous, glyph.id); }
-        glyph.position = point(caret.x.round(), caret.y);
-        last_glyph_id = Some(glyph.id);
-        caret.x += font.h_advance(glyph.id);
-        target.push(glyph);
+    if layout.exposed {
+        damage = vec![full];
+        layout.exposed = false;
     }
+    if damage.is_empty() { return Ok(()); }
+    app.renderer.present(&app.conn, app.win_id, &damage)?;
+    app.conn.flush()?;
+    Ok(())
 }
 
-fn handle_click(app: &AppState, x: i16, button: u8) -> Result<(), Box<dyn std::error::Error>> {
-    for (start, end, win) in &app.click_regions {
-        if x >= *start && x <= *end {
-            if button == 1 {
-                let event = ClientMessageEvent {
-                    response_type: CLIENT_MESSAGE_EVENT, format: 32, sequence: 0, window: *win,
-                    type_: app.atoms._net_active_window, data: [2, CURRENT_TIME, 0, 0, 0].into(),
-                };
-                app.conn.send_event(false, app.conn.setup().roots[app.screen_num].root, EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY, event)?;
-                app.conn.set_input_focus(InputFocus::POINTER_ROOT, *win, CURRENT_TIME)?;
-            } else if button == 3 {
-                let event = ClientMessageEvent {
-                    response_type: CLIENT_MESSAGE_EVENT, format: 32, sequence: 0, window: *win,
-                    type_: app.atoms._net_close_window, data: [CURRENT_TIME, 2, 0, 0, 0].into(),
-                };
-                app.conn.send_event(false, app.conn.setup().roots[app.screen_num].root, EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY, event)?;
-            }
-            app.conn.flush()?;
-            return Ok(());
-        }
-    }
+// Reserves the bottom `height` rows of the screen.
+fn set_struts(conn: &RustConnection, atoms: &Atoms, win: Window, width: u16, height: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let struts_partial = [0, 0, 0, height as u32, 0, 0, 0, 0, 0, 0, 0, width as u32];
+    conn.change_property32(PropMode::REPLACE, win, atoms._net_wm_strut_partial, atoms.cardinal, &struts_partial)?;
     Ok(())
 }
 
-fn handle_docking(app: &mut AppState, win: Window) -> Result<(), Box<dyn std::error::Error>> {
-    if !app.tray_icons.contains(&win) {
-        app.tray_icons.push(win);
-        app.conn.reparent_window(win, app.win_id, 0, 0)?;
-        app.conn.change_window_attributes(win, &ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY))?;
-        app.conn.map_window(win)?;
-    }
-    Ok(())
+// A compositing manager owns the _NET_WM_CM_Sn selection for the screens it manages.
+fn compositor_running(conn: &RustConnection, screen_num: usize) -> Result<bool, Box<dyn std::error::Error>> {
+    let name = format!("_NET_WM_CM_S{}", screen_num);
+    let atom = conn.intern_atom(false, name.as_bytes())?.reply()?.atom;
+    Ok(conn.get_selection_owner(atom)?.reply()?.owner != x11rb::NONE)
+}
+
+fn find_argb_visual(screen: &Screen) -> Option<Visualid> {
+    screen.allowed_depths.iter()
+        .filter(|d| d.depth == 32)
+        .flat_map(|d| d.visuals.iter())
+        .find(|v| v.class == VisualClass::TRUE_COLOR)
+        .map(|v| v.visual_id)
 }
 
 struct Atoms {
@@ -716,6 +565,8 @@ struct Atoms {
     _net_wm_window_type_desktop: Atom, _net_wm_window_type_splash: Atom,
     _net_client_list: Atom, _net_wm_name: Atom, _net_active_window: Atom, _net_wm_icon: Atom,
     _net_system_tray_opcode: Atom, net_system_tray_s0: Atom, _net_close_window: Atom,
+    _net_system_tray_visual: Atom, _net_wm_state: Atom, _net_wm_state_hidden: Atom,
+    _net_wm_state_demands_attention: Atom, _net_wm_state_fullscreen: Atom, _xrootpmap_id: Atom,
 }
 
 impl Atoms {
@@ -736,6 +587,11 @@ impl Atoms {
             _net_wm_name: i(b"_NET_WM_NAME"), _net_active_window: i(b"_NET_ACTIVE_WINDOW"), _net_wm_icon: i(b"_NET_WM_ICON"),
             _net_system_tray_opcode: i(b"_NET_SYSTEM_TRAY_OPCODE"), net_system_tray_s0: i(b"_NET_SYSTEM_TRAY_S0"),
             _net_close_window: i(b"_NET_CLOSE_WINDOW"),
+            _net_system_tray_visual: i(b"_NET_SYSTEM_TRAY_VISUAL"),
+            _net_wm_state: i(b"_NET_WM_STATE"), _net_wm_state_hidden: i(b"_NET_WM_STATE_HIDDEN"),
+            _net_wm_state_demands_attention: i(b"_NET_WM_STATE_DEMANDS_ATTENTION"),
+            _net_wm_state_fullscreen: i(b"_NET_WM_STATE_FULLSCREEN"),
+            _xrootpmap_id: i(b"_XROOTPMAP_ID"),
         })
     }
 }
\ No newline at end of file
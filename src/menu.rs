@@ -0,0 +1,222 @@
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::*;
+use x11rb::rust_connection::RustConnection;
+use x11rb::CURRENT_TIME;
+
+use crate::config::ColorsConfig;
+use crate::sni::MenuNode;
+use crate::render::software::draw_text_render;
+use crate::text::{calculate_text_width, Fonts};
+use crate::metrics::Metrics;
+use crate::VisualInfo;
+
+const ROW_HEIGHT: u16 = 26;
+const SEPARATOR_HEIGHT: u16 = 9;
+const PADDING: u16 = 12;
+const MIN_WIDTH: u16 = 160;
+
+pub enum MenuAction {
+    None,
+    Close,
+    Clicked(i32),
+}
+
+// A DBusMenu shown above the panel. Submenus replace the current level, with a "Back" row on top.
+pub struct Menu {
+    pub key: String,
+    win: Window,
+    pixmap: Pixmap,
+    gc: Gcontext,
+    visual: VisualInfo,
+    colors: ColorsConfig,
+    metrics: Metrics,
+    stack: Vec<MenuNode>,
+    hovered: Option<usize>,
+    anchor_x: i16,
+    anchor_y: i16,
+    width: u16,
+    height: u16,
+}
+
+enum Row<'a> {
+    Back,
+    Item(&'a MenuNode),
+}
+
+impl Menu {
+    #[allow(clippy::too_many_arguments)]
+    pub fn open(
+        conn: &RustConnection, screen_num: usize, visual: VisualInfo, colors: ColorsConfig, metrics: Metrics, font: &Fonts,
+        key: String, root: MenuNode, anchor_x: i16, anchor_y: i16,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let screen = &conn.setup().roots[screen_num];
+        let win = conn.generate_id()?;
+        let pixmap = conn.generate_id()?;
+        let gc = conn.generate_id()?;
+
+        let values = CreateWindowAux::new()
+            .background_pixel(colors.background.pixel())
+            .border_pixel(colors.date.pixel())
+            .colormap(visual.colormap)
+            .override_redirect(1)
+            .event_mask(EventMask::EXPOSURE | EventMask::BUTTON_PRESS | EventMask::POINTER_MOTION);
+        conn.create_window(
+            visual.depth, win, screen.root,
+            0, 0, 1, 1, 1,
+            WindowClass::INPUT_OUTPUT, visual.visual, &values,
+        )?;
+        conn.create_gc(gc, win, &CreateGCAux::new().foreground(colors.text.pixel()).background(colors.background.pixel()))?;
+
+        let mut menu = Self {
+            key, win, pixmap, gc, visual, colors, metrics,
+            stack: vec![root],
+            hovered: None,
+            anchor_x, anchor_y,
+            width: 0, height: 0,
+        };
+        menu.relayout(conn, screen_num, font)?;
+        conn.map_window(win)?;
+        conn.grab_pointer(
+            false, win,
+            EventMask::BUTTON_PRESS | EventMask::POINTER_MOTION,
+            GrabMode::ASYNC, GrabMode::ASYNC, x11rb::NONE, x11rb::NONE, CURRENT_TIME,
+        )?;
+        Ok(menu)
+    }
+
+    pub fn window(&self) -> Window {
+        self.win
+    }
+
+    fn rows(&self) -> Vec<Row<'_>> {
+        let mut rows = Vec::new();
+        if self.stack.len() > 1 { rows.push(Row::Back); }
+        if let Some(node) = self.stack.last() {
+            rows.extend(node.children.iter().map(Row::Item));
+        }
+        rows
+    }
+
+    fn row_height(&self, row: &Row) -> u16 {
+        match row {
+            Row::Item(node) if node.separator => self.metrics.px(SEPARATOR_HEIGHT),
+            _ => self.metrics.px(ROW_HEIGHT),
+        }
+    }
+
+    fn label(row: &Row) -> String {
+        match row {
+            Row::Back => "‹ Back".to_string(),
+            Row::Item(node) => {
+                let mark = match node.toggle { Some(true) => "✓ ", Some(false) => "   ", None => "" };
+                let arrow = if node.children.is_empty() { "" } else { "  ›" };
+                format!("{}{}{}", mark, node.label, arrow)
+            }
+        }
+    }
+
+    fn relayout(&mut self, conn: &RustConnection, screen_num: usize, font: &Fonts) -> Result<(), Box<dyn std::error::Error>> {
+        let rows = self.rows();
+        let text_width = rows.iter()
+            .map(|r| calculate_text_width(font, self.metrics.font_main, &Self::label(r)) as u16)
+            .max()
+            .unwrap_or(0);
+        let width = (text_width + 2 * self.metrics.px(PADDING)).max(self.metrics.px(MIN_WIDTH));
+        let height = rows.iter().map(|r| self.row_height(r)).sum::<u16>().max(1);
+
+        let screen = &conn.setup().roots[screen_num];
+        let x = (self.anchor_x as i32 - width as i32 / 2).clamp(0, (screen.width_in_pixels as i32 - width as i32 - 2).max(0));
+        let y = (self.anchor_y as i32 - height as i32 - 2).max(0);
+
+        if width != self.width || height != self.height {
+            if self.width != 0 { conn.free_pixmap(self.pixmap)?; }
+            conn.create_pixmap(self.visual.depth, self.pixmap, self.win, width, height)?;
+            self.width = width;
+            self.height = height;
+        }
+        conn.configure_window(self.win, &ConfigureWindowAux::new()
+            .x(x).y(y).width(width as u32).height(height as u32)
+            .stack_mode(StackMode::ABOVE))?;
+        self.hovered = None;
+        Ok(())
+    }
+
+    pub fn draw(&self, conn: &RustConnection, font: &Fonts, render_buf: &mut Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        let colors = &self.colors;
+        conn.change_gc(self.gc, &ChangeGCAux::new().foreground(colors.background.pixel()))?;
+        conn.poly_fill_rectangle(self.pixmap, self.gc, &[Rectangle { x: 0, y: 0, width: self.width, height: self.height }])?;
+
+        let mut y = 0i16;
+        for (i, row) in self.rows().iter().enumerate() {
+            let h = self.row_height(row);
+            match row {
+                Row::Item(node) if node.separator => {
+                    conn.change_gc(self.gc, &ChangeGCAux::new().foreground(colors.active_background.pixel()))?;
+                    let (inset, line) = (self.metrics.px(4), self.metrics.px(1));
+                    conn.poly_fill_rectangle(self.pixmap, self.gc, &[Rectangle { x: inset as i16, y: y + (h / 2) as i16, width: self.width - 2 * inset, height: line }])?;
+                }
+                _ => {
+                    let enabled = !matches!(row, Row::Item(node) if !node.enabled);
+                    let mut bg = colors.background;
+                    if enabled && self.hovered == Some(i) {
+                        bg = colors.hover_background;
+                        conn.change_gc(self.gc, &ChangeGCAux::new().foreground(bg.pixel()))?;
+                        conn.poly_fill_rectangle(self.pixmap, self.gc, &[Rectangle { x: 0, y, width: self.width, height: h }])?;
+                    }
+                    let fg = if enabled { colors.text } else { colors.date };
+                    let padding = self.metrics.px(PADDING) as i16;
+                    draw_text_render(conn, self.pixmap, self.gc, self.visual.depth, font, render_buf, &Self::label(row), self.metrics.font_main, padding, y, bg, fg)?;
+                }
+            }
+            y += h as i16;
+        }
+
+        conn.copy_area(self.pixmap, self.win, self.gc, 0, 0, 0, 0, self.width, self.height)?;
+        Ok(())
+    }
+
+    fn row_at(&self, x: i16, y: i16) -> Option<usize> {
+        if x < 0 || x >= self.width as i16 || y < 0 { return None; }
+        let mut top = 0i16;
+        for (i, row) in self.rows().iter().enumerate() {
+            let h = self.row_height(row) as i16;
+            if y < top + h { return Some(i); }
+            top += h;
+        }
+        None
+    }
+
+    // Returns true if the highlighted row changed.
+    pub fn handle_motion(&mut self, x: i16, y: i16) -> bool {
+        let hovered = self.row_at(x, y);
+        let changed = hovered != self.hovered;
+        self.hovered = hovered;
+        changed
+    }
+
+    pub fn handle_click(&mut self, conn: &RustConnection, screen_num: usize, font: &Fonts, x: i16, y: i16) -> Result<MenuAction, Box<dyn std::error::Error>> {
+        let Some(idx) = self.row_at(x, y) else { return Ok(MenuAction::Close) };
+        let action = match &self.rows()[idx] {
+            Row::Back => {
+                self.stack.pop();
+                None
+            }
+            Row::Item(node) if node.separator || !node.enabled => return Ok(MenuAction::None),
+            Row::Item(node) if !node.children.is_empty() => Some((*node).clone()),
+            Row::Item(node) => return Ok(MenuAction::Clicked(node.id)),
+        };
+        if let Some(submenu) = action {
+            self.stack.push(submenu);
+        }
+        self.relayout(conn, screen_num, font)?;
+        Ok(MenuAction::None)
+    }
+
+    pub fn close(self, conn: &RustConnection) -> Result<(), Box<dyn std::error::Error>> {
+        conn.ungrab_pointer(CURRENT_TIME)?;
+        conn.destroy_window(self.win)?;
+        conn.free_pixmap(self.pixmap)?;
+        conn.free_gc(self.gc)?;
+        Ok(())
+    }
+}
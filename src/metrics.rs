@@ -0,0 +1,85 @@
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _, Window};
+use x11rb::rust_connection::RustConnection;
+
+// Sizes at 96 DPI; everything drawn is multiplied by the scale factor.
+pub const PANEL_HEIGHT: u16 = 38;
+pub const ICON_SIZE: u16 = 24;
+pub const FONT_SIZE_MAIN: f32 = 15.0;
+pub const FONT_SIZE_DATE: f32 = 12.0;
+pub const TEXT_Y_OFFSET: u16 = 11;
+pub const ICON_Y_OFFSET: u16 = 6;
+// The scale factors followed, whether configured or from Xft.dpi; anything outside is clamped.
+pub const MIN_SCALE: f32 = 0.5;
+pub const MAX_SCALE: f32 = 4.0;
+
+// The scaled sizes, recomputed whenever the scale factor changes.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Metrics {
+    pub scale: f32,
+    pub panel_height: u16,
+    pub icon_size: u16,
+    pub font_main: f32,
+    pub font_date: f32,
+    pub text_y: i16,
+    pub icon_y: i16,
+}
+
+impl Metrics {
+    pub fn new(scale: f32) -> Self {
+        let scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+        let m = Self { scale, ..Self::default() };
+        Self {
+            panel_height: m.px(PANEL_HEIGHT),
+            icon_size: m.px(ICON_SIZE),
+            font_main: FONT_SIZE_MAIN * scale,
+            font_date: FONT_SIZE_DATE * scale,
+            text_y: m.px(TEXT_Y_OFFSET) as i16,
+            icon_y: m.px(ICON_Y_OFFSET) as i16,
+            ..m
+        }
+    }
+
+    // A length given at 96 DPI, in device pixels; never rounds a non-zero length away.
+    pub fn px(&self, v: u16) -> u16 {
+        if v == 0 { 0 } else { ((v as f32 * self.scale).round() as u16).max(1) }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            panel_height: PANEL_HEIGHT, icon_size: ICON_SIZE,
+            font_main: FONT_SIZE_MAIN, font_date: FONT_SIZE_DATE,
+            text_y: TEXT_Y_OFFSET as i16, icon_y: ICON_Y_OFFSET as i16,
+        }
+    }
+}
+
+// The scale factor Xft.dpi in the root window's RESOURCE_MANAGER asks for (what xrdb -merge sets up), if any.
+pub fn xft_scale(conn: &RustConnection, root: Window) -> Option<f32> {
+    let reply = conn.get_property(false, root, AtomEnum::RESOURCE_MANAGER, AtomEnum::STRING, 0, 1 << 20).ok()?.reply().ok()?;
+    let resources = String::from_utf8_lossy(&reply.value);
+    resources.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim() == "Xft.dpi")
+        .and_then(|(_, value)| value.trim().parse::<f32>().ok())
+        .filter(|dpi| dpi.is_finite() && *dpi > 0.0)
+        .map(|dpi| dpi / 96.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_clamped() {
+        assert_eq!(Metrics::new(0.01).scale, MIN_SCALE);
+        assert_eq!(Metrics::new(0.01).panel_height, 19);
+        assert_eq!(Metrics::new(100.0).scale, MAX_SCALE);
+        assert_eq!(Metrics::new(1.5).panel_height, 57);
+        // Every graph keeps room between its paddings.
+        let m = Metrics::new(MIN_SCALE);
+        assert!(m.panel_height > 2 * m.px(8));
+    }
+}
@@ -0,0 +1,106 @@
+mod shm;
+pub mod software;
+pub mod xrender;
+
+use serde::Deserialize;
+use x11rb::protocol::xproto::{Gcontext, Pixmap, Point, Rectangle, Window};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use crate::color::{Color, Fill};
+use crate::text::Fonts;
+use crate::VisualInfo;
+
+pub use software::SoftwareRenderer;
+pub use xrender::XRenderRenderer;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Software,
+    Xrender,
+}
+
+// Identifies an icon across redraws so backends can keep it uploaded on the server.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum IconKey {
+    Window(Window),
+    Sni(String),
+    // A color glyph: font index, glyph id and font size bits.
+    Glyph(usize, u16, u32),
+}
+
+// The panel background image as premultiplied BGRA, and the pixmap of the panel's visual it was uploaded to.
+pub struct Backdrop {
+    pub pixels: Vec<u8>,
+    pub pixmap: Pixmap,
+}
+
+// Draws into the panel's back buffer, which keeps its contents between redraws; `present` copies the
+// given rectangles of the back buffer to the window.
+pub trait Renderer {
+    fn fill_rect(&mut self, conn: &RustConnection, rect: Rectangle, color: Color) -> Result<(), Box<dyn std::error::Error>>;
+
+    // Restores the panel background in `rect`: the backdrop when one is set, otherwise `color`.
+    fn fill_background(&mut self, conn: &RustConnection, rect: Rectangle, color: Color) -> Result<(), Box<dyn std::error::Error>>;
+
+    // The backdrop has the panel's size; None goes back to solid backgrounds.
+    fn set_backdrop(&mut self, conn: &RustConnection, backdrop: Option<&Backdrop>) -> Result<(), Box<dyn std::error::Error>>;
+
+    fn fill_triangle(&mut self, conn: &RustConnection, points: [Point; 3], color: Color) -> Result<(), Box<dyn std::error::Error>>;
+
+    // Blends an anti-aliased rectangle with corners of `radius` over what is there.
+    fn fill_rounded_rect(&mut self, conn: &RustConnection, rect: Rectangle, radius: u16, fill: Fill) -> Result<(), Box<dyn std::error::Error>>;
+
+    // Blends the outline of the same shape, `width` wide and inside `rect`.
+    fn stroke_rounded_rect(
+        &mut self, conn: &RustConnection, rect: Rectangle, radius: u16, width: u16, color: Color,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_text(
+        &mut self, conn: &RustConnection, font: &Fonts, text: &str, size: f32,
+        x: i16, y: i16, fg: Color,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    // `pixels` is straight-alpha RGBA.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_icon(
+        &mut self, conn: &RustConnection, key: &IconKey, pixels: &[u8], width: u16, height: u16,
+        x: i16, y: i16,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    // Called when an icon changes or its owner goes away.
+    fn forget_icon(&mut self, _conn: &RustConnection, _key: &IconKey) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    fn present(&mut self, conn: &RustConnection, window: Window, rects: &[Rectangle]) -> Result<(), Box<dyn std::error::Error>>;
+
+    // True while the server may still be reading what the last `present` sent, so nothing should be drawn yet.
+    fn busy(&self) -> bool {
+        false
+    }
+
+    // Sees every X event, for backends waiting on one.
+    fn event(&mut self, _event: &Event) {}
+
+    // The panel changed size (the scale factor changed) and `pixmap` is its new back buffer; contents and the
+    // backdrop are lost.
+    fn resize(&mut self, conn: &RustConnection, pixmap: Pixmap, width: u16, height: u16) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+// Falls back to the software renderer when the RENDER extension is missing.
+#[allow(clippy::too_many_arguments)]
+pub fn create(
+    conn: &RustConnection, backend: Backend, screen_num: usize, visual: VisualInfo,
+    pixmap: Pixmap, gc: Gcontext, width: u16, height: u16,
+) -> Result<Box<dyn Renderer>, Box<dyn std::error::Error>> {
+    if backend == Backend::Xrender {
+        if let Some(renderer) = XRenderRenderer::new(conn, screen_num, visual, pixmap, gc)? {
+            return Ok(Box::new(renderer));
+        }
+    }
+    Ok(Box::new(SoftwareRenderer::new(conn, visual, gc, width, height)?))
+}
@@ -0,0 +1,51 @@
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::shm::{self, Seg};
+use x11rb::rust_connection::RustConnection;
+
+// A System V shared memory segment attached by both us and the server, so images skip the socket.
+pub struct ShmSegment {
+    seg: Seg,
+    addr: *mut u8,
+    len: usize,
+}
+
+impl ShmSegment {
+    // None when the extension is missing or the server can't attach it (a remote display).
+    pub fn new(conn: &RustConnection, len: usize) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if conn.extension_information(shm::X11_EXTENSION_NAME)?.is_none() {
+            return Ok(None);
+        }
+        let id = unsafe { libc::shmget(libc::IPC_PRIVATE, len, libc::IPC_CREAT | 0o600) };
+        if id < 0 { return Ok(None); }
+        let addr = unsafe { libc::shmat(id, std::ptr::null(), 0) };
+        if addr as isize == -1 {
+            unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+            return Ok(None);
+        }
+
+        let seg = conn.generate_id()?;
+        let attached = shm::attach(conn, seg, id as u32, false)?.check().is_ok();
+        // Marked for removal right away; the kernel frees it once both sides have detached.
+        unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+        if !attached {
+            unsafe { libc::shmdt(addr) };
+            return Ok(None);
+        }
+        Ok(Some(Self { seg, addr: addr as *mut u8, len }))
+    }
+
+    pub fn seg(&self) -> Seg {
+        self.seg
+    }
+
+    pub fn data(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.addr, self.len) }
+    }
+
+    // Detaches both sides; the server must be done with pending requests on the segment.
+    pub fn destroy(self, conn: &RustConnection) -> Result<(), Box<dyn std::error::Error>> {
+        shm::detach(conn, self.seg)?;
+        unsafe { libc::shmdt(self.addr as *const libc::c_void) };
+        Ok(())
+    }
+}
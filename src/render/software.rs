@@ -0,0 +1,365 @@
+use ab_glyph::point;
+use x11rb::connection::RequestConnection;
+use x11rb::protocol::shm;
+use x11rb::protocol::xproto::*;
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use super::shm::ShmSegment;
+use super::{Backdrop, IconKey, Renderer};
+use crate::color::{self, Color, Fill};
+use crate::text::{calculate_text_width, layout_paragraph, Fonts};
+use crate::VisualInfo;
+
+enum Canvas {
+    Shm(ShmSegment),
+    Heap(Vec<u8>),
+}
+
+impl Canvas {
+    fn new(conn: &RustConnection, width: u16, height: u16) -> Result<Self, Box<dyn std::error::Error>> {
+        let len = width as usize * height as usize * 4;
+        Ok(match ShmSegment::new(conn, len)? {
+            Some(seg) => Canvas::Shm(seg),
+            None => Canvas::Heap(vec![0; len]),
+        })
+    }
+
+    fn data(&mut self) -> &mut [u8] {
+        match self {
+            Canvas::Shm(seg) => seg.data(),
+            Canvas::Heap(buf) => buf,
+        }
+    }
+}
+
+// Rasterizes the whole panel on the CPU into one canvas and uploads the damaged parts in `present`: through
+// a shared memory segment when the server supports MIT-SHM, otherwise with as few PutImage requests as fit.
+pub struct SoftwareRenderer {
+    gc: Gcontext,
+    depth: u8,
+    width: u16,
+    height: u16,
+    canvas: Canvas,
+    // Rows of a damaged rectangle, copied out of the canvas when uploading without SHM.
+    upload: Vec<u8>,
+    backdrop: Option<Vec<u8>>,
+    // The server hasn't yet sent the ShmCompletion for the last upload from the segment.
+    reading: bool,
+}
+
+impl SoftwareRenderer {
+    pub fn new(conn: &RustConnection, visual: VisualInfo, gc: Gcontext, width: u16, height: u16) -> Result<Self, Box<dyn std::error::Error>> {
+        let canvas = Canvas::new(conn, width, height)?;
+        Ok(Self { gc, depth: visual.depth, width, height, canvas, upload: Vec::new(), backdrop: None, reading: false })
+    }
+
+    fn image(&mut self) -> Image<'_> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        Image { data: self.canvas.data(), width, height }
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn fill_rect(&mut self, _conn: &RustConnection, rect: Rectangle, color: Color) -> Result<(), Box<dyn std::error::Error>> {
+        self.image().fill(rect, color);
+        Ok(())
+    }
+
+    fn fill_background(&mut self, conn: &RustConnection, rect: Rectangle, color: Color) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(backdrop) = &self.backdrop else { return self.fill_rect(conn, rect, color) };
+        let width = self.width as usize;
+        let (x0, y0, x1, y1) = clip(rect, width, self.height as usize);
+        let data = self.canvas.data();
+        for y in y0..y1 {
+            let (start, end) = ((y * width + x0) * 4, (y * width + x1) * 4);
+            data[start..end].copy_from_slice(&backdrop[start..end]);
+        }
+        Ok(())
+    }
+
+    fn set_backdrop(&mut self, _conn: &RustConnection, backdrop: Option<&Backdrop>) -> Result<(), Box<dyn std::error::Error>> {
+        self.backdrop = backdrop.map(|b| b.pixels.clone());
+        Ok(())
+    }
+
+    fn fill_triangle(&mut self, _conn: &RustConnection, points: [Point; 3], color: Color) -> Result<(), Box<dyn std::error::Error>> {
+        self.image().fill_triangle(points, color);
+        Ok(())
+    }
+
+    fn fill_rounded_rect(&mut self, _conn: &RustConnection, rect: Rectangle, radius: u16, fill: Fill) -> Result<(), Box<dyn std::error::Error>> {
+        self.image().fill_rounded(rect, radius as f32, None, fill);
+        Ok(())
+    }
+
+    fn stroke_rounded_rect(
+        &mut self, _conn: &RustConnection, rect: Rectangle, radius: u16, width: u16, color: Color,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.image().fill_rounded(rect, radius as f32, Some(width as f32), Fill::Solid(color));
+        Ok(())
+    }
+
+    fn draw_text(
+        &mut self, _conn: &RustConnection, fonts: &Fonts, text: &str, size: f32,
+        x: i16, y: i16, fg: Color,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.image().draw_text(fonts, text, size, x as i32, y as i32, fg);
+        Ok(())
+    }
+
+    fn draw_icon(
+        &mut self, _conn: &RustConnection, _key: &IconKey, pixels: &[u8], width: u16, height: u16,
+        x: i16, y: i16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.image().draw_icon(pixels, width as usize, height as usize, x as i32, y as i32);
+        Ok(())
+    }
+
+    fn present(&mut self, conn: &RustConnection, window: Window, rects: &[Rectangle]) -> Result<(), Box<dyn std::error::Error>> {
+        let (canvas_w, canvas_h) = (self.width as usize, self.height as usize);
+        let clip = |r: &Rectangle| {
+            let (x0, y0, x1, y1) = clip(*r, canvas_w, canvas_h);
+            (x0, y0, x1 - x0, y1 - y0)
+        };
+
+        match &mut self.canvas {
+            Canvas::Shm(seg) => {
+                // The server reads the segment asynchronously; only the last upload asks for a completion event,
+                // and the next frame waits for it rather than drawing over what is still being read.
+                let mut rects = rects.iter().map(clip).filter(|&(_, _, w, h)| w > 0 && h > 0).peekable();
+                while let Some((x, y, w, h)) = rects.next() {
+                    let last = rects.peek().is_none();
+                    shm::put_image(
+                        conn, window, self.gc, self.width, self.height,
+                        x as u16, y as u16, w as u16, h as u16, x as i16, y as i16,
+                        self.depth, ImageFormat::Z_PIXMAP.into(), last, seg.seg(), 0,
+                    )?;
+                    self.reading |= last;
+                }
+            }
+            Canvas::Heap(buf) => {
+                for (x, y, w, h) in rects.iter().map(clip).filter(|&(_, _, w, h)| w > 0 && h > 0) {
+                    let rows = ((conn.maximum_request_bytes() - 32) / (w * 4)).clamp(1, h);
+                    for top in (y..y + h).step_by(rows) {
+                        let n = rows.min(y + h - top);
+                        self.upload.clear();
+                        for row in top..top + n {
+                            let start = (row * canvas_w + x) * 4;
+                            self.upload.extend_from_slice(&buf[start..start + w * 4]);
+                        }
+                        conn.put_image(
+                            ImageFormat::Z_PIXMAP, window, self.gc, w as u16, n as u16, x as i16, top as i16, 0, self.depth,
+                            &self.upload,
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn busy(&self) -> bool {
+        self.reading
+    }
+
+    fn event(&mut self, event: &Event) {
+        if let (Event::ShmCompletion(e), Canvas::Shm(seg)) = (event, &self.canvas) {
+            if e.shmseg == seg.seg() { self.reading = false; }
+        }
+    }
+
+    // The old segment is detached after the uploads already queued from it, and the new one is free to draw into.
+    fn resize(&mut self, conn: &RustConnection, _pixmap: Pixmap, width: u16, height: u16) -> Result<(), Box<dyn std::error::Error>> {
+        let old = std::mem::replace(&mut self.canvas, Canvas::new(conn, width, height)?);
+        if let Canvas::Shm(seg) = old { seg.destroy(conn)?; }
+        self.reading = false;
+        self.width = width;
+        self.height = height;
+        self.backdrop = None;
+        Ok(())
+    }
+}
+
+// A premultiplied BGRA image, the layout of a 32bpp ZPixmap.
+struct Image<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+}
+
+impl Image<'_> {
+    fn clear(&mut self, color: Color) {
+        let [b, g, r, a] = color.premultiplied();
+        for px in self.data.chunks_exact_mut(4) {
+            px.copy_from_slice(&[b as u8, g as u8, r as u8, a as u8]);
+        }
+    }
+
+    fn blend(&mut self, x: i32, y: i32, src: [u16; 4]) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height { return; }
+        let idx = (y as usize * self.width + x as usize) * 4;
+        color::over(&mut self.data[idx..idx + 4], src);
+    }
+
+    // Replaces the pixels, like a GC fill.
+    fn fill(&mut self, rect: Rectangle, color: Color) {
+        let [b, g, r, a] = color.premultiplied();
+        let (x0, y0, x1, y1) = clip(rect, self.width, self.height);
+        for y in y0..y1 {
+            let row = &mut self.data[(y * self.width + x0) * 4..(y * self.width + x1) * 4];
+            for px in row.chunks_exact_mut(4) {
+                px.copy_from_slice(&[b as u8, g as u8, r as u8, a as u8]);
+            }
+        }
+    }
+
+    fn fill_triangle(&mut self, points: [Point; 3], color: Color) {
+        let src = color.premultiplied();
+        let [p0, p1, p2] = points.map(|p| (p.x as i32, p.y as i32));
+        let edge = |a: (i32, i32), b: (i32, i32), x: i32, y: i32| (b.0 - a.0) * (y - a.1) - (b.1 - a.1) * (x - a.0);
+        let (min_x, max_x) = (p0.0.min(p1.0).min(p2.0), p0.0.max(p1.0).max(p2.0));
+        let (min_y, max_y) = (p0.1.min(p1.1).min(p2.1), p0.1.max(p1.1).max(p2.1));
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let (e0, e1, e2) = (edge(p0, p1, x, y), edge(p1, p2, x, y), edge(p2, p0, x, y));
+                // Either winding order.
+                if (e0 >= 0 && e1 >= 0 && e2 >= 0) || (e0 <= 0 && e1 <= 0 && e2 <= 0) {
+                    self.blend(x, y, src);
+                }
+            }
+        }
+    }
+
+    // Blends a rounded rectangle, or only its outline `border` wide, with coverage from the distance between each
+    // pixel center and the edge.
+    fn fill_rounded(&mut self, rect: Rectangle, radius: f32, border: Option<f32>, fill: Fill) {
+        let (x, y, w, h) = (rect.x as f32, rect.y as f32, rect.width as f32, rect.height as f32);
+        if w <= 0.0 || h <= 0.0 { return; }
+        let coverage = |px: f32, py: f32| {
+            let outer = (0.5 - rounded_box_distance(x, y, w, h, radius, px, py)).clamp(0.0, 1.0);
+            match border {
+                Some(b) if b * 2.0 < w.min(h) => {
+                    let inner = rounded_box_distance(x + b, y + b, w - 2.0 * b, h - 2.0 * b, radius - b, px, py);
+                    outer - (0.5 - inner).clamp(0.0, 1.0)
+                }
+                _ => outer,
+            }
+        };
+        let horizontal = matches!(fill, Fill::Gradient { horizontal: true, .. });
+        let (x0, y0, x1, y1) = clip(rect, self.width, self.height);
+        for py in y0..y1 {
+            for px in x0..x1 {
+                let (cx, cy) = (px as f32 + 0.5, py as f32 + 0.5);
+                let c = coverage(cx, cy);
+                if c <= 0.0 { continue; }
+                let t = if horizontal { (cx - x) / w } else { (cy - y) / h };
+                let c = (c * 255.0).round() as u16;
+                let src = fill.at(t).premultiplied().map(|v| (v * c + 127) / 255);
+                self.blend(px as i32, py as i32, src);
+            }
+        }
+    }
+
+    // (x, y) is the top-left of the line box, as in layout_paragraph.
+    fn draw_text(&mut self, fonts: &Fonts, text: &str, size: f32, x: i32, y: i32, fg: Color) {
+        let mut glyphs = Vec::new();
+        layout_paragraph(fonts, size, point(0.0, 0.0), text, &mut glyphs);
+
+        let fg = fg.premultiplied();
+        for (font, glyph) in &glyphs {
+            let (pen_x, pen_y, bitmap) = fonts.glyph(*font, glyph);
+            let (left, top) = (x + pen_x + bitmap.left, y + pen_y + bitmap.top);
+            if let Some(rgba) = &bitmap.color {
+                self.draw_icon(rgba, bitmap.width, bitmap.height(), left, top);
+                continue;
+            }
+            for (i, &coverage) in bitmap.coverage.iter().enumerate() {
+                if coverage == 0 { continue; }
+                let src = fg.map(|v| (v * coverage as u16 + 127) / 255);
+                self.blend(left + (i % bitmap.width) as i32, top + (i / bitmap.width) as i32, src);
+            }
+        }
+    }
+
+    // `pixels` is straight-alpha RGBA.
+    fn draw_icon(&mut self, pixels: &[u8], width: usize, height: usize, x: i32, y: i32) {
+        for (i, chunk) in pixels.chunks_exact(4).take(width * height).enumerate() {
+            let a = chunk[3] as u16;
+            if a == 0 { continue; }
+            self.blend(x + (i % width) as i32, y + (i / width) as i32, [
+                (chunk[2] as u16 * a + 127) / 255,
+                (chunk[1] as u16 * a + 127) / 255,
+                (chunk[0] as u16 * a + 127) / 255,
+                a,
+            ]);
+        }
+    }
+}
+
+// The pixel bounds (x0, y0, x1, y1) of `rect` inside a width x height image; empty ranges when it is outside.
+fn clip(rect: Rectangle, width: usize, height: usize) -> (usize, usize, usize, usize) {
+    let x0 = (rect.x.max(0) as usize).min(width);
+    let y0 = (rect.y.max(0) as usize).min(height);
+    let x1 = ((rect.x as i32 + rect.width as i32).max(0) as usize).clamp(x0, width);
+    let y1 = ((rect.y as i32 + rect.height as i32).max(0) as usize).clamp(y0, height);
+    (x0, y0, x1, y1)
+}
+
+// Signed distance from (px, py) to the edge of a rounded rectangle, negative inside.
+#[allow(clippy::too_many_arguments)]
+fn rounded_box_distance(x: f32, y: f32, w: f32, h: f32, radius: f32, px: f32, py: f32) -> f32 {
+    let r = radius.clamp(0.0, w.min(h) / 2.0);
+    let qx = (px - x - w / 2.0).abs() - w / 2.0 + r;
+    let qy = (py - y - h / 2.0).abs() - h / 2.0 + r;
+    qx.max(0.0).hypot(qy.max(0.0)) + qx.max(qy).min(0.0) - r
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn draw_icon_fast(
+    conn: &RustConnection, target: Drawable, gc: Gcontext, depth: u8,
+    pixels: &[u8], width: u16, height: u16,
+    x: i16, y: i16, bg_color: Color,
+    render_buf: &mut Vec<u8>
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (w, h) = (width as usize, height as usize);
+    render_buf.clear();
+    render_buf.resize(w * h * 4, 0);
+
+    let mut image = Image { data: render_buf, width: w, height: h };
+    image.clear(bg_color);
+    image.draw_icon(pixels, w, h, 0, 0);
+
+    conn.put_image(ImageFormat::Z_PIXMAP, target, gc, width, height, x, y, 0, depth, render_buf)?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn draw_text_render(
+    conn: &RustConnection,
+    target: Drawable,
+    gc: Gcontext,
+    depth: u8,
+    fonts: &Fonts,
+    render_buf: &mut Vec<u8>,
+    text: &str,
+    font_size: f32, x: i16, y: i16, bg_color: Color, fg_color: Color
+) -> Result<(), Box<dyn std::error::Error>> {
+    if text.is_empty() { return Ok(()); }
+
+    let text_width = calculate_text_width(fonts, font_size, text);
+    if text_width <= 0.0 { return Ok(()); }
+
+    let height = (font_size.ceil() as usize) + 8;
+    let width = text_width as usize + 4;
+
+    render_buf.clear();
+    render_buf.resize(width * height * 4, 0);
+
+    let mut image = Image { data: render_buf, width, height };
+    image.clear(bg_color);
+    image.draw_text(fonts, text, font_size, 0, 0, fg_color);
+
+    conn.put_image(ImageFormat::Z_PIXMAP, target, gc, width as u16, height as u16, x, y, 0, depth, render_buf)?;
+    Ok(())
+}
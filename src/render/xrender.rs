@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+
+use ab_glyph::{Glyph, GlyphId, point};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::render::{self, Glyphinfo, Glyphset, PictOp, PictType, Pictformat, Picture, Pointfix, Repeat, Triangle};
+use x11rb::protocol::xproto::*;
+use x11rb::rust_connection::RustConnection;
+
+use super::{Backdrop, IconKey, Renderer};
+use crate::color::{Color, Fill};
+use crate::text::{layout_paragraph, Fonts};
+use crate::VisualInfo;
+
+// Glyphs uploaded to one server-side glyph set per font size; false marks glyphs without an image (spaces).
+struct GlyphCache {
+    set: Glyphset,
+    glyphs: HashMap<u32, bool>,
+}
+
+// Composites on the server: glyphs and icons are uploaded once and then drawn by id.
+pub struct XRenderRenderer {
+    pixmap: Pixmap,
+    gc: Gcontext,
+    picture: Picture,
+    format: Pictformat,
+    a8: Pictformat,
+    argb32: Pictformat,
+    glyph_sets: HashMap<u32, GlyphCache>,
+    fills: HashMap<u32, Picture>,
+    icons: HashMap<IconKey, Picture>,
+    backdrop: Option<Picture>,
+}
+
+impl XRenderRenderer {
+    pub fn new(
+        conn: &RustConnection, screen_num: usize, visual: VisualInfo, pixmap: Pixmap, gc: Gcontext,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if conn.extension_information(render::X11_EXTENSION_NAME)?.is_none() {
+            return Ok(None);
+        }
+        let formats = render::query_pict_formats(conn)?.reply()?;
+
+        let visual_format = formats.screens.get(screen_num)
+            .and_then(|s| s.depths.iter().flat_map(|d| d.visuals.iter()).find(|v| v.visual == visual.visual))
+            .map(|v| v.format);
+        let a8 = formats.formats.iter()
+            .find(|f| f.type_ == PictType::DIRECT && f.depth == 8 && f.direct.alpha_mask == 0xff)
+            .map(|f| f.id);
+        let argb32 = formats.formats.iter()
+            .find(|f| f.type_ == PictType::DIRECT && f.depth == 32
+                && f.direct.alpha_mask == 0xff && f.direct.alpha_shift == 24
+                && f.direct.red_mask == 0xff && f.direct.red_shift == 16
+                && f.direct.green_shift == 8 && f.direct.blue_shift == 0)
+            .map(|f| f.id);
+        let (Some(visual_format), Some(a8), Some(argb32)) = (visual_format, a8, argb32) else { return Ok(None) };
+
+        let picture = conn.generate_id()?;
+        render::create_picture(conn, picture, pixmap, visual_format, &render::CreatePictureAux::new())?;
+
+        Ok(Some(Self {
+            pixmap, gc, picture, format: visual_format, a8, argb32,
+            glyph_sets: HashMap::new(),
+            fills: HashMap::new(),
+            icons: HashMap::new(),
+            backdrop: None,
+        }))
+    }
+
+    fn fill_picture(&mut self, conn: &RustConnection, color: Color) -> Result<Picture, Box<dyn std::error::Error>> {
+        if let Some(&picture) = self.fills.get(&color.0) { return Ok(picture); }
+        let picture = conn.generate_id()?;
+        render::create_solid_fill(conn, picture, render_color(color))?;
+        self.fills.insert(color.0, picture);
+        Ok(picture)
+    }
+
+    // A source picture for `fill` over `rect`, in destination coordinates; gradients are freed by the caller.
+    fn shape_source(&mut self, conn: &RustConnection, rect: Rectangle, fill: Fill) -> Result<(Picture, bool), Box<dyn std::error::Error>> {
+        let Fill::Gradient { from, to, horizontal } = fill else {
+            return Ok((self.fill_picture(conn, fill.at(0.0))?, false));
+        };
+        let (x, y) = ((rect.x as i32) << 16, (rect.y as i32) << 16);
+        let end = if horizontal {
+            Pointfix { x: (rect.x as i32 + rect.width as i32) << 16, y }
+        } else {
+            Pointfix { x, y: (rect.y as i32 + rect.height as i32) << 16 }
+        };
+        let picture = conn.generate_id()?;
+        render::create_linear_gradient(conn, picture, Pointfix { x, y }, end, &[0, 1 << 16], &[straight_color(from), straight_color(to)])?;
+        render::change_picture(conn, picture, &render::ChangePictureAux::new().repeat(Repeat::PAD))?;
+        Ok((picture, true))
+    }
+
+    // Uploads the glyphs not yet in the glyph set for `size`. Glyph set ids carry the font index in the high bits.
+    fn upload_glyphs(&mut self, conn: &RustConnection, fonts: &Fonts, size: f32, glyphs: &[(usize, Glyph)]) -> Result<Glyphset, Box<dyn std::error::Error>> {
+        let cache = match self.glyph_sets.entry(size.to_bits()) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => {
+                let set = conn.generate_id()?;
+                render::create_glyph_set(conn, set, self.a8)?;
+                e.insert(GlyphCache { set, glyphs: HashMap::new() })
+            }
+        };
+
+        for (font, glyph) in glyphs {
+            let id = glyph_set_id(*font, glyph.id);
+            if cache.glyphs.contains_key(&id) { continue; }
+            let origin = glyph.id.with_scale_and_position(glyph.scale, point(0.0, 0.0));
+            let (_, _, bitmap) = fonts.glyph(*font, &origin);
+            // Color glyphs are composited from ARGB pictures like icons instead.
+            if bitmap.width == 0 || bitmap.color.is_some() {
+                cache.glyphs.insert(id, false);
+                continue;
+            }
+
+            // A8 rows are padded to 4 bytes.
+            let (w, h) = (bitmap.width, bitmap.height());
+            let stride = (w + 3) & !3;
+            let mut data = vec![0u8; stride * h];
+            for (row, src) in data.chunks_exact_mut(stride).zip(bitmap.coverage.chunks_exact(w)) {
+                row[..w].copy_from_slice(src);
+            }
+            let info = Glyphinfo {
+                width: w as u16, height: h as u16,
+                x: -bitmap.left as i16, y: -bitmap.top as i16,
+                x_off: 0, y_off: 0,
+            };
+            render::add_glyphs(conn, cache.set, &[id], &[info], &data)?;
+            cache.glyphs.insert(id, true);
+        }
+        Ok(cache.set)
+    }
+}
+
+fn glyph_set_id(font: usize, id: GlyphId) -> u32 {
+    ((font as u32) << 16) | id.0 as u32
+}
+
+impl Renderer for XRenderRenderer {
+    fn fill_rect(&mut self, conn: &RustConnection, rect: Rectangle, color: Color) -> Result<(), Box<dyn std::error::Error>> {
+        render::fill_rectangles(conn, PictOp::SRC, self.picture, render_color(color), &[rect])?;
+        Ok(())
+    }
+
+    fn fill_background(&mut self, conn: &RustConnection, rect: Rectangle, color: Color) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(backdrop) = self.backdrop else { return self.fill_rect(conn, rect, color) };
+        render::composite(conn, PictOp::SRC, backdrop, x11rb::NONE, self.picture, rect.x, rect.y, 0, 0, rect.x, rect.y, rect.width, rect.height)?;
+        Ok(())
+    }
+
+    fn set_backdrop(&mut self, conn: &RustConnection, backdrop: Option<&Backdrop>) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(picture) = self.backdrop.take() {
+            render::free_picture(conn, picture)?;
+        }
+        if let Some(backdrop) = backdrop {
+            let picture = conn.generate_id()?;
+            render::create_picture(conn, picture, backdrop.pixmap, self.format, &render::CreatePictureAux::new())?;
+            self.backdrop = Some(picture);
+        }
+        Ok(())
+    }
+
+    fn fill_triangle(&mut self, conn: &RustConnection, points: [Point; 3], color: Color) -> Result<(), Box<dyn std::error::Error>> {
+        let src = self.fill_picture(conn, color)?;
+        let fixed = |p: Point| Pointfix { x: (p.x as i32) << 16, y: (p.y as i32) << 16 };
+        let triangle = Triangle { p1: fixed(points[0]), p2: fixed(points[1]), p3: fixed(points[2]) };
+        render::triangles(conn, PictOp::OVER, src, self.picture, self.a8, 0, 0, &[triangle])?;
+        Ok(())
+    }
+
+    fn fill_rounded_rect(&mut self, conn: &RustConnection, rect: Rectangle, radius: u16, fill: Fill) -> Result<(), Box<dyn std::error::Error>> {
+        if rect.width == 0 || rect.height == 0 { return Ok(()); }
+        let (src, temporary) = self.shape_source(conn, rect, fill)?;
+        // A fan around the center; the source is offset so its coordinates are the panel's.
+        let (cx, cy) = (rect.x + (rect.width / 2) as i16, rect.y + (rect.height / 2) as i16);
+        let outline = rounded_outline(rect, radius as f32, 0.0);
+        let mut points = Vec::with_capacity(outline.len() + 2);
+        points.push(Pointfix { x: (cx as i32) << 16, y: (cy as i32) << 16 });
+        points.extend_from_slice(&outline);
+        points.push(outline[0]);
+        render::tri_fan(conn, PictOp::OVER, src, self.picture, self.a8, cx, cy, &points)?;
+        if temporary { render::free_picture(conn, src)?; }
+        Ok(())
+    }
+
+    fn stroke_rounded_rect(
+        &mut self, conn: &RustConnection, rect: Rectangle, radius: u16, width: u16, color: Color,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if width == 0 || rect.width == 0 || rect.height == 0 { return Ok(()); }
+        if width * 2 >= rect.width.min(rect.height) {
+            return self.fill_rounded_rect(conn, rect, radius, Fill::Solid(color));
+        }
+        let src = self.fill_picture(conn, color)?;
+        // The band between the outline and the same outline inset by `width`, two triangles per segment.
+        let outer = rounded_outline(rect, radius as f32, 0.0);
+        let inner = rounded_outline(rect, radius as f32, width as f32);
+        let n = outer.len();
+        let mut triangles = Vec::with_capacity(n * 2);
+        for i in 0..n {
+            let j = (i + 1) % n;
+            triangles.push(Triangle { p1: outer[i], p2: outer[j], p3: inner[i] });
+            triangles.push(Triangle { p1: inner[i], p2: outer[j], p3: inner[j] });
+        }
+        render::triangles(conn, PictOp::OVER, src, self.picture, self.a8, 0, 0, &triangles)?;
+        Ok(())
+    }
+
+    fn draw_text(
+        &mut self, conn: &RustConnection, fonts: &Fonts, text: &str, size: f32,
+        x: i16, y: i16, fg: Color,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut glyphs = Vec::new();
+        layout_paragraph(fonts, size, point(0.0, 0.0), text, &mut glyphs);
+        if glyphs.is_empty() { return Ok(()); }
+
+        let set = self.upload_glyphs(conn, fonts, size, &glyphs)?;
+        let cache = &self.glyph_sets[&size.to_bits()];
+
+        // One element per glyph: deltas are relative to the previous glyph since x_off/y_off are zero.
+        let mut cmds = Vec::with_capacity(glyphs.len() * 12);
+        let (mut pen_x, mut pen_y) = (0i32, 0i32);
+        for (font, glyph) in &glyphs {
+            let id = glyph_set_id(*font, glyph.id);
+            if cache.glyphs.get(&id) != Some(&true) { continue; }
+            let gx = x as i32 + glyph.position.x.round() as i32;
+            let gy = y as i32 + glyph.position.y.round() as i32;
+            cmds.extend_from_slice(&[1, 0, 0, 0]);
+            cmds.extend_from_slice(&((gx - pen_x) as i16).to_ne_bytes());
+            cmds.extend_from_slice(&((gy - pen_y) as i16).to_ne_bytes());
+            cmds.extend_from_slice(&id.to_ne_bytes());
+            pen_x = gx;
+            pen_y = gy;
+        }
+
+        if !cmds.is_empty() {
+            let src = self.fill_picture(conn, fg)?;
+            render::composite_glyphs32(conn, PictOp::OVER, src, self.picture, self.a8, set, 0, 0, &cmds)?;
+        }
+
+        for (font, glyph) in &glyphs {
+            let origin = glyph.id.with_scale_and_position(glyph.scale, point(0.0, 0.0));
+            let (_, _, bitmap) = fonts.glyph(*font, &origin);
+            let Some(rgba) = &bitmap.color else { continue };
+            let key = IconKey::Glyph(*font, glyph.id.0, size.to_bits());
+            let gx = x as i32 + glyph.position.x.round() as i32 + bitmap.left;
+            let gy = y as i32 + glyph.position.y.round() as i32 + bitmap.top;
+            self.draw_icon(conn, &key, rgba, bitmap.width as u16, bitmap.height() as u16, gx as i16, gy as i16)?;
+        }
+        Ok(())
+    }
+
+    fn draw_icon(
+        &mut self, conn: &RustConnection, key: &IconKey, pixels: &[u8], width: u16, height: u16,
+        x: i16, y: i16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.icons.contains_key(key) {
+            let data: Vec<u8> = pixels.chunks_exact(4).flat_map(|px| {
+                let [b, g, r, a] = Color(u32::from_be_bytes([px[3], px[0], px[1], px[2]])).premultiplied();
+                [b as u8, g as u8, r as u8, a as u8]
+            }).collect();
+
+            let pixmap = conn.generate_id()?;
+            let gc = conn.generate_id()?;
+            let picture = conn.generate_id()?;
+            conn.create_pixmap(32, pixmap, self.pixmap, width, height)?;
+            conn.create_gc(gc, pixmap, &CreateGCAux::new())?;
+            conn.put_image(ImageFormat::Z_PIXMAP, pixmap, gc, width, height, 0, 0, 0, 32, &data)?;
+            render::create_picture(conn, picture, pixmap, self.argb32, &render::CreatePictureAux::new())?;
+            conn.free_gc(gc)?;
+            conn.free_pixmap(pixmap)?;
+            self.icons.insert(key.clone(), picture);
+        }
+
+        let picture = self.icons[key];
+        render::composite(conn, PictOp::OVER, picture, x11rb::NONE, self.picture, 0, 0, 0, 0, x, y, width, height)?;
+        Ok(())
+    }
+
+    fn forget_icon(&mut self, conn: &RustConnection, key: &IconKey) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(picture) = self.icons.remove(key) {
+            render::free_picture(conn, picture)?;
+        }
+        Ok(())
+    }
+
+    fn present(&mut self, conn: &RustConnection, window: Window, rects: &[Rectangle]) -> Result<(), Box<dyn std::error::Error>> {
+        for r in rects {
+            conn.copy_area(self.pixmap, window, self.gc, r.x, r.y, r.x, r.y, r.width, r.height)?;
+        }
+        Ok(())
+    }
+
+    // Text is drawn at new sizes from now on, so the glyph sets and color glyphs of the old ones are dropped too.
+    fn resize(&mut self, conn: &RustConnection, pixmap: Pixmap, _width: u16, _height: u16) -> Result<(), Box<dyn std::error::Error>> {
+        render::free_picture(conn, self.picture)?;
+        self.pixmap = pixmap;
+        render::create_picture(conn, self.picture, pixmap, self.format, &render::CreatePictureAux::new())?;
+        for (_, cache) in self.glyph_sets.drain() {
+            render::free_glyph_set(conn, cache.set)?;
+        }
+        let glyphs: Vec<IconKey> = self.icons.keys().filter(|k| matches!(k, IconKey::Glyph(..))).cloned().collect();
+        for key in glyphs { self.forget_icon(conn, &key)?; }
+        self.set_backdrop(conn, None)
+    }
+}
+
+// The outline of a rounded rectangle inset by `inset`, clockwise from the top-left corner. The point count only
+// depends on the radius so outlines at different insets pair up point by point.
+fn rounded_outline(rect: Rectangle, radius: f32, inset: f32) -> Vec<Pointfix> {
+    let (w, h) = (rect.width as f32, rect.height as f32);
+    let radius = radius.clamp(0.0, w.min(h) / 2.0);
+    let r = (radius - inset).max(0.0);
+    let steps = ((radius * std::f32::consts::FRAC_PI_2 / 3.0).ceil() as usize).clamp(1, 16);
+    let (left, top) = (rect.x as f32 + inset, rect.y as f32 + inset);
+    let (right, bottom) = (rect.x as f32 + w - inset, rect.y as f32 + h - inset);
+    // Corner centers with the angle each arc starts at, going clockwise in screen coordinates.
+    let corners = [
+        (left + r, top + r, std::f32::consts::PI),
+        (right - r, top + r, -std::f32::consts::FRAC_PI_2),
+        (right - r, bottom - r, 0.0),
+        (left + r, bottom - r, std::f32::consts::FRAC_PI_2),
+    ];
+    let fixed = |v: f32| (v * 65536.0).round() as i32;
+    let mut points = Vec::with_capacity(4 * (steps + 1));
+    for (cx, cy, start) in corners {
+        for i in 0..=steps {
+            let angle = start + std::f32::consts::FRAC_PI_2 * i as f32 / steps as f32;
+            points.push(Pointfix { x: fixed(cx + r * angle.cos()), y: fixed(cy + r * angle.sin()) });
+        }
+    }
+    points
+}
+
+// Gradient stops take colors without premultiplication.
+fn straight_color(color: Color) -> render::Color {
+    let channel = |shift: u32| ((color.0 >> shift) & 0xFF) as u16 * 257;
+    render::Color { red: channel(16), green: channel(8), blue: channel(0), alpha: channel(24) }
+}
+
+// RENDER colors are premultiplied and 16 bits per channel.
+fn render_color(color: Color) -> render::Color {
+    let [b, g, r, a] = color.premultiplied();
+    render::Color { red: r * 257, green: g * 257, blue: b * 257, alpha: a * 257 }
+}
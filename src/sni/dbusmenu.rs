@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use zbus::blocking::Connection;
+use zbus::zvariant::{OwnedValue, Value};
+
+const INTERFACE: &str = "com.canonical.dbusmenu";
+
+// (id, properties, children) as sent by GetLayout.
+type RawLayout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+#[derive(Clone)]
+pub struct MenuNode {
+    pub id: i32,
+    pub label: String,
+    pub enabled: bool,
+    pub separator: bool,
+    // Some(checked) for checkmark and radio items.
+    pub toggle: Option<bool>,
+    pub children: Vec<MenuNode>,
+}
+
+pub fn fetch_layout(conn: &Connection, bus: &str, path: &str) -> zbus::Result<MenuNode> {
+    // AboutToShow lets lazily populated menus (Qt, Electron) fill themselves in; the result is irrelevant.
+    conn.call_method(Some(bus), path, Some(INTERFACE), "AboutToShow", &(0i32,)).ok();
+
+    let reply = conn.call_method(Some(bus), path, Some(INTERFACE), "GetLayout", &(0i32, -1i32, Vec::<&str>::new()))?;
+    let (_revision, (id, props, children)): (u32, RawLayout) = reply.body().deserialize()?;
+
+    let props = props.iter().map(|(k, v)| (k.as_str(), unwrap_variant(v))).collect();
+    Ok(build_node(id, &props, children.iter().map(|c| unwrap_variant(c))))
+}
+
+pub fn send_clicked(conn: &Connection, bus: &str, path: &str, id: i32) -> zbus::Result<()> {
+    conn.call_method(Some(bus), path, Some(INTERFACE), "Event", &(id, "clicked", Value::from(""), 0u32))?;
+    Ok(())
+}
+
+fn unwrap_variant<'a>(value: &'a Value<'a>) -> &'a Value<'a> {
+    match value {
+        Value::Value(inner) => unwrap_variant(inner),
+        other => other,
+    }
+}
+
+fn build_node<'a>(id: i32, props: &HashMap<&str, &Value<'_>>, children: impl Iterator<Item = &'a Value<'a>>) -> MenuNode {
+    let string = |key: &str| match props.get(key) {
+        Some(Value::Str(s)) => Some(s.to_string()),
+        _ => None,
+    };
+    let boolean = |key: &str, default: bool| match props.get(key) {
+        Some(Value::Bool(b)) => *b,
+        _ => default,
+    };
+
+    // Labels use '_' for mnemonics and "__" for a literal underscore.
+    let label = string("label").unwrap_or_default().replace("__", "\0").replace('_', "").replace('\0', "_");
+    let toggle = match string("toggle-type").as_deref() {
+        Some("checkmark") | Some("radio") => Some(matches!(props.get("toggle-state"), Some(Value::I32(1)))),
+        _ => None,
+    };
+
+    MenuNode {
+        id,
+        label,
+        enabled: boolean("enabled", true),
+        separator: string("type").as_deref() == Some("separator"),
+        toggle,
+        children: children.filter_map(parse_child).collect(),
+    }
+}
+
+fn parse_child<'a>(value: &'a Value<'a>) -> Option<MenuNode> {
+    let Value::Structure(s) = value else { return None };
+    let [Value::I32(id), Value::Dict(dict), Value::Array(children)] = s.fields() else { return None };
+
+    let mut props = HashMap::new();
+    for (key, value) in dict.iter() {
+        if let Value::Str(key) = key { props.insert(key.as_str(), unwrap_variant(value)); }
+    }
+    if matches!(props.get("visible"), Some(Value::Bool(false))) { return None; }
+
+    Some(build_node(*id, &props, children.inner().iter().map(unwrap_variant)))
+}
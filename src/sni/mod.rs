@@ -0,0 +1,365 @@
+mod dbusmenu;
+mod watcher;
+
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use image::{ImageBuffer, RgbaImage, imageops::FilterType};
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::fdo::{RequestNameFlags, RequestNameReply};
+use zbus::zvariant::OwnedValue;
+use zbus::MatchRule;
+
+use crate::icon_theme::{self, IconLookup};
+pub use dbusmenu::MenuNode;
+use watcher::{Watcher, WATCHER_NAME, WATCHER_PATH};
+
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+
+// Display data for one StatusNotifierItem, sent to the main thread whenever it changes.
+pub struct SniItem {
+    pub key: String,
+    pub id: String,
+    pub title: String,
+    pub icon: Option<Vec<u8>>,
+    pub passive: bool,
+}
+
+pub enum SniEvent {
+    Updated(SniItem),
+    Removed(String),
+    Menu { key: String, x: i16, y: i16, root: MenuNode },
+}
+
+pub enum SniCommand {
+    Activate { key: String, x: i16, y: i16 },
+    SecondaryActivate { key: String, x: i16, y: i16 },
+    Scroll { key: String, delta: i32, horizontal: bool },
+    // Shows the DBusMenu if the item exports one, otherwise asks the item to show its own.
+    ContextMenu { key: String, x: i16, y: i16 },
+    MenuClicked { key: String, id: i32 },
+}
+
+enum HostMsg {
+    Register { service: String, sender: String },
+    Unregister(String),
+    Changed { sender: String, path: String },
+    NameLost(String),
+    Command(SniCommand),
+    IconSize(u32),
+}
+
+pub struct SniHost {
+    events: Receiver<SniEvent>,
+    commands: Sender<HostMsg>,
+    wake_fd: RawFd,
+}
+
+impl SniHost {
+    // Serves org.kde.StatusNotifierWatcher if nobody else does, and acts as the host either way.
+    pub fn start(icon_size: u32, icon_theme: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = Connection::session()?;
+
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let (host_tx, host_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let registry = Arc::new(Mutex::new(Vec::new()));
+
+        let own_watcher = serve_watcher(&conn, &registry, &host_tx)?;
+        let host_name = format!("org.kde.StatusNotifierHost-{}", std::process::id());
+        conn.request_name(host_name.as_str())?;
+
+        listen(&conn, MatchRule::builder().msg_type(zbus::message::Type::Signal).interface(ITEM_INTERFACE)?.build(), &host_tx, |msg| {
+            let header = msg.header();
+            Some(HostMsg::Changed {
+                sender: header.sender()?.to_string(),
+                path: header.path()?.to_string(),
+            })
+        })?;
+        listen(&conn, MatchRule::builder().msg_type(zbus::message::Type::Signal).sender("org.freedesktop.DBus")?.member("NameOwnerChanged")?.build(), &host_tx, |msg| {
+            let (name, _old, new): (String, String, String) = msg.body().deserialize().ok()?;
+            new.is_empty().then_some(HostMsg::NameLost(name))
+        })?;
+
+        if !own_watcher {
+            listen(&conn, MatchRule::builder().msg_type(zbus::message::Type::Signal).interface(WATCHER_NAME)?.build(), &host_tx, |msg| {
+                let service: String = msg.body().deserialize().ok()?;
+                match msg.header().member()?.as_str() {
+                    "StatusNotifierItemRegistered" => Some(HostMsg::Register { service, sender: String::new() }),
+                    "StatusNotifierItemUnregistered" => Some(HostMsg::Unregister(service)),
+                    _ => None,
+                }
+            })?;
+            conn.call_method(Some(WATCHER_NAME), WATCHER_PATH, Some(WATCHER_NAME), "RegisterStatusNotifierHost", &(host_name.as_str(),))?;
+            let reply = conn.call_method(Some(WATCHER_NAME), WATCHER_PATH, Some("org.freedesktop.DBus.Properties"), "Get", &(WATCHER_NAME, "RegisteredStatusNotifierItems"))?;
+            let items: Vec<String> = reply.body().deserialize::<OwnedValue>()?.try_into()?;
+            for service in items {
+                host_tx.send(HostMsg::Register { service, sender: String::new() }).ok();
+            }
+        }
+
+        let mut worker = Worker {
+            conn, own_watcher, registry,
+            items: HashMap::new(),
+            icons: IconLookup::new(icon_theme),
+            icon_size,
+            events: event_tx,
+            wake_fd: fds[1],
+        };
+        std::thread::spawn(move || {
+            for msg in host_rx { worker.handle(msg); }
+        });
+
+        Ok(Self { events: event_rx, commands: host_tx, wake_fd: fds[0] })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.wake_fd
+    }
+
+    pub fn poll_events(&self) -> Vec<SniEvent> {
+        let mut buf = [0u8; 64];
+        while unsafe { libc::read(self.wake_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 {}
+        self.events.try_iter().collect()
+    }
+
+    pub fn send(&self, cmd: SniCommand) {
+        self.commands.send(HostMsg::Command(cmd)).ok();
+    }
+
+    // Reloads every icon at the new size, e.g. after the scale factor changed.
+    pub fn set_icon_size(&self, size: u32) {
+        self.commands.send(HostMsg::IconSize(size)).ok();
+    }
+}
+
+fn serve_watcher(conn: &Connection, registry: &Arc<Mutex<Vec<String>>>, tx: &Sender<HostMsg>) -> zbus::Result<bool> {
+    conn.object_server().at(WATCHER_PATH, Watcher { items: registry.clone(), tx: tx.clone() })?;
+    match conn.request_name_with_flags(WATCHER_NAME, RequestNameFlags::DoNotQueue.into()) {
+        Ok(RequestNameReply::PrimaryOwner) => Ok(true),
+        _ => {
+            conn.object_server().remove::<Watcher, _>(WATCHER_PATH)?;
+            Ok(false)
+        }
+    }
+}
+
+fn listen<F>(conn: &Connection, rule: MatchRule<'static>, tx: &Sender<HostMsg>, map: F) -> zbus::Result<()>
+where F: Fn(&zbus::Message) -> Option<HostMsg> + Send + 'static {
+    let messages = MessageIterator::for_match_rule(rule, conn, None)?;
+    let tx = tx.clone();
+    std::thread::spawn(move || {
+        for msg in messages.flatten() {
+            if let Some(host_msg) = map(&msg) {
+                if tx.send(host_msg).is_err() { break; }
+            }
+        }
+    });
+    Ok(())
+}
+
+struct Item {
+    bus: String,
+    path: String,
+    owner: String,
+    menu: Option<String>,
+    item_is_menu: bool,
+}
+
+struct Worker {
+    conn: Connection,
+    own_watcher: bool,
+    registry: Arc<Mutex<Vec<String>>>,
+    items: HashMap<String, Item>,
+    icons: IconLookup,
+    icon_size: u32,
+    events: Sender<SniEvent>,
+    wake_fd: RawFd,
+}
+
+impl Worker {
+    fn handle(&mut self, msg: HostMsg) {
+        match msg {
+            HostMsg::Register { service, sender } => self.register(&service, &sender),
+            HostMsg::Unregister(service) => {
+                let (bus, path) = split_service(&service, "");
+                self.remove(&format!("{}{}", bus, path));
+            }
+            HostMsg::Changed { sender, path } => {
+                let key = self.items.iter().find(|(_, i)| i.owner == sender && i.path == path).map(|(k, _)| k.clone());
+                if let Some(key) = key { self.refresh(&key); }
+            }
+            HostMsg::NameLost(name) => {
+                let gone: Vec<String> = self.items.iter()
+                    .filter(|(_, i)| i.owner == name || i.bus == name)
+                    .map(|(k, _)| k.clone())
+                    .collect();
+                for key in gone { self.remove(&key); }
+            }
+            HostMsg::Command(cmd) => self.command(cmd),
+            HostMsg::IconSize(size) => {
+                self.icon_size = size;
+                let keys: Vec<String> = self.items.keys().cloned().collect();
+                for key in keys { self.refresh(&key); }
+            }
+        }
+    }
+
+    fn register(&mut self, service: &str, sender: &str) {
+        let (bus, path) = split_service(service, sender);
+        let key = format!("{}{}", bus, path);
+        if !self.items.contains_key(&key) {
+            let owner = if bus.starts_with(':') { bus.clone() } else {
+                self.conn.call_method(Some("org.freedesktop.DBus"), "/org/freedesktop/DBus", Some("org.freedesktop.DBus"), "GetNameOwner", &(bus.as_str(),))
+                    .and_then(|r| r.body().deserialize::<String>())
+                    .unwrap_or_else(|_| bus.clone())
+            };
+            self.items.insert(key.clone(), Item { bus, path, owner, menu: None, item_is_menu: false });
+            if self.own_watcher {
+                self.registry.lock().unwrap().push(key.clone());
+                self.conn.emit_signal(None::<&str>, WATCHER_PATH, WATCHER_NAME, "StatusNotifierItemRegistered", &(key.as_str(),)).ok();
+            }
+        }
+        self.refresh(&key);
+    }
+
+    fn remove(&mut self, key: &str) {
+        if self.items.remove(key).is_none() { return; }
+        if self.own_watcher {
+            self.registry.lock().unwrap().retain(|k| k != key);
+            self.conn.emit_signal(None::<&str>, WATCHER_PATH, WATCHER_NAME, "StatusNotifierItemUnregistered", &(key,)).ok();
+        }
+        self.notify(SniEvent::Removed(key.to_string()));
+    }
+
+    fn refresh(&mut self, key: &str) {
+        let Some(item) = self.items.get_mut(key) else { return };
+        let reply = self.conn.call_method(Some(item.bus.as_str()), item.path.as_str(), Some("org.freedesktop.DBus.Properties"), "GetAll", &(ITEM_INTERFACE,));
+        let Ok(mut props) = reply.and_then(|r| r.body().deserialize::<HashMap<String, OwnedValue>>()) else { return };
+
+        let mut take_string = |name: &str| props.remove(name).and_then(|v| String::try_from(v).ok()).unwrap_or_default();
+        let id = take_string("Id");
+        let title = take_string("Title");
+        let status = take_string("Status");
+        let icon_name = take_string("IconName");
+        let attention_name = take_string("AttentionIconName");
+        let theme_path = take_string("IconThemePath");
+        item.menu = props.remove("Menu")
+            .and_then(|v| zbus::zvariant::OwnedObjectPath::try_from(v).ok())
+            .map(|p| p.to_string())
+            .filter(|p| p != "/");
+        item.item_is_menu = props.remove("ItemIsMenu").and_then(|v| bool::try_from(v).ok()).unwrap_or(false);
+
+        let take_pixmaps = |props: &mut HashMap<String, OwnedValue>, name: &str| -> Vec<(i32, i32, Vec<u8>)> {
+            props.remove(name).and_then(|v| Vec::try_from(v).ok()).unwrap_or_default()
+        };
+        let pixmaps = take_pixmaps(&mut props, "IconPixmap");
+        let attention_pixmaps = take_pixmaps(&mut props, "AttentionIconPixmap");
+
+        let extra_dirs: Vec<PathBuf> = if theme_path.is_empty() { Vec::new() } else { vec![PathBuf::from(&theme_path)] };
+        let mut candidates = Vec::new();
+        if status == "NeedsAttention" {
+            candidates.push((attention_name, attention_pixmaps));
+        }
+        candidates.push((icon_name, pixmaps));
+
+        let size = self.icon_size;
+        let icon = candidates.into_iter().find_map(|(name, pixmaps)| {
+            self.icons.find(&name, size, &extra_dirs)
+                .and_then(|path| icon_theme::load_icon(&path, size))
+                .or_else(|| pixmap_to_rgba(&pixmaps, size))
+        });
+
+        self.notify(SniEvent::Updated(SniItem {
+            key: key.to_string(),
+            id,
+            title,
+            icon,
+            passive: status == "Passive",
+        }));
+    }
+
+    fn command(&mut self, cmd: SniCommand) {
+        let call = |item: &Item, method: &str, body: &(i32, i32)| {
+            self.conn.call_method(Some(item.bus.as_str()), item.path.as_str(), Some(ITEM_INTERFACE), method, body).ok();
+        };
+        match cmd {
+            SniCommand::Activate { key, x, y } => {
+                let Some(item) = self.items.get(&key) else { return };
+                if item.item_is_menu && item.menu.is_some() {
+                    self.command(SniCommand::ContextMenu { key, x, y });
+                } else {
+                    call(item, "Activate", &(x as i32, y as i32));
+                }
+            }
+            SniCommand::SecondaryActivate { key, x, y } => {
+                if let Some(item) = self.items.get(&key) { call(item, "SecondaryActivate", &(x as i32, y as i32)); }
+            }
+            SniCommand::Scroll { key, delta, horizontal } => {
+                if let Some(item) = self.items.get(&key) {
+                    let orientation = if horizontal { "horizontal" } else { "vertical" };
+                    self.conn.call_method(Some(item.bus.as_str()), item.path.as_str(), Some(ITEM_INTERFACE), "Scroll", &(delta, orientation)).ok();
+                }
+            }
+            SniCommand::ContextMenu { key, x, y } => {
+                let Some(item) = self.items.get(&key) else { return };
+                match &item.menu {
+                    Some(menu) => {
+                        if let Ok(root) = dbusmenu::fetch_layout(&self.conn, &item.bus, menu) {
+                            self.notify(SniEvent::Menu { key, x, y, root });
+                        }
+                    }
+                    None => call(item, "ContextMenu", &(x as i32, y as i32)),
+                }
+            }
+            SniCommand::MenuClicked { key, id } => {
+                if let Some(Item { bus, menu: Some(menu), .. }) = self.items.get(&key) {
+                    dbusmenu::send_clicked(&self.conn, bus, menu, id).ok();
+                }
+            }
+        }
+    }
+
+    fn notify(&self, event: SniEvent) {
+        if self.events.send(event).is_ok() {
+            unsafe { libc::write(self.wake_fd, [1u8].as_ptr() as *const libc::c_void, 1) };
+        }
+    }
+}
+
+// Items register either as a bus name, a bus name followed by a path, or just a path (libappindicator).
+fn split_service(service: &str, sender: &str) -> (String, String) {
+    if service.starts_with('/') {
+        (sender.to_string(), service.to_string())
+    } else if let Some(pos) = service.find('/') {
+        (service[..pos].to_string(), service[pos..].to_string())
+    } else {
+        (service.to_string(), "/StatusNotifierItem".to_string())
+    }
+}
+
+// Larger pixmaps are dropped rather than decoded; no tray icon needs more.
+const MAX_PIXMAP: i32 = 1024;
+
+// IconPixmap is ARGB32 in network byte order; picks the smallest image at least `size` wide.
+fn pixmap_to_rgba(pixmaps: &[(i32, i32, Vec<u8>)], size: u32) -> Option<Vec<u8>> {
+    let valid = pixmaps.iter().filter(|(w, h, data)| {
+        let len = (*w as usize).checked_mul(*h as usize).and_then(|n| n.checked_mul(4));
+        (1..=MAX_PIXMAP).contains(w) && (1..=MAX_PIXMAP).contains(h) && len.is_some_and(|len| data.len() >= len)
+    });
+    let (w, h, data) = valid.clone().filter(|(w, _, _)| *w as u32 >= size).min_by_key(|(w, _, _)| *w)
+        .or_else(|| valid.max_by_key(|(w, _, _)| *w))?;
+
+    let mut img: RgbaImage = ImageBuffer::new(*w as u32, *h as u32);
+    for (px, argb) in img.pixels_mut().zip(data.chunks_exact(4)) {
+        *px = image::Rgba([argb[1], argb[2], argb[3], argb[0]]);
+    }
+    Some(image::imageops::resize(&img, size, size, FilterType::Lanczos3).into_raw())
+}
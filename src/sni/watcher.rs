@@ -0,0 +1,52 @@
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+
+use zbus::message::Header;
+use zbus::object_server::SignalEmitter;
+
+use super::HostMsg;
+
+pub const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+pub const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+
+// Served only when no other watcher owns the name; registrations are forwarded to the host worker.
+pub struct Watcher {
+    pub items: Arc<Mutex<Vec<String>>>,
+    pub tx: Sender<HostMsg>,
+}
+
+#[zbus::interface(name = "org.kde.StatusNotifierWatcher")]
+impl Watcher {
+    async fn register_status_notifier_item(&self, service: &str, #[zbus(header)] header: Header<'_>) {
+        let sender = header.sender().map(|s| s.to_string()).unwrap_or_default();
+        self.tx.send(HostMsg::Register { service: service.to_string(), sender }).ok();
+    }
+
+    async fn register_status_notifier_host(&self, _service: &str, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) {
+        Self::status_notifier_host_registered(&emitter).await.ok();
+    }
+
+    #[zbus(property)]
+    fn registered_status_notifier_items(&self) -> Vec<String> {
+        self.items.lock().unwrap().clone()
+    }
+
+    #[zbus(property)]
+    fn is_status_notifier_host_registered(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn protocol_version(&self) -> i32 {
+        0
+    }
+
+    #[zbus(signal)]
+    async fn status_notifier_item_registered(emitter: &SignalEmitter<'_>, service: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn status_notifier_item_unregistered(emitter: &SignalEmitter<'_>, service: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn status_notifier_host_registered(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
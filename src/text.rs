@@ -0,0 +1,289 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use ab_glyph::{Font, FontRef, Glyph, GlyphId, Point, PxScale, ScaleFont, point};
+use fontconfig::Fontconfig;
+use rustybuzz::{Direction, UnicodeBuffer};
+use unicode_bidi::BidiInfo;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::config::FontConfig;
+use crate::emoji;
+
+// Glyph positions are snapped to quarter pixels, so each glyph has at most four cached bitmaps per size.
+const SUBPIXEL_STEPS: f32 = 4.0;
+// Titles come and go; the caches are simply emptied when they grow past this many entries.
+const CACHE_LIMIT: usize = 4096;
+
+// Coverage rows of one rasterized glyph, placed relative to the integer part of the glyph position.
+// Color glyphs (emoji) carry straight-alpha RGBA pixels instead and are blended like icons.
+pub struct GlyphBitmap {
+    pub left: i32,
+    pub top: i32,
+    pub width: usize,
+    pub coverage: Vec<u8>,
+    pub color: Option<Vec<u8>>,
+}
+
+impl GlyphBitmap {
+    pub fn height(&self) -> usize {
+        match &self.color {
+            Some(rgba) => rgba.len() / 4 / self.width.max(1),
+            None => self.coverage.len() / self.width.max(1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: usize,
+    id: u16,
+    size: u32,
+    subpixel: u8,
+}
+
+// One font file, as seen by the rasterizer and by the shaper.
+struct Face {
+    font: FontRef<'static>,
+    shaper: rustybuzz::Face<'static>,
+    // Has color bitmaps (CBDT, sbix) or layers (COLR).
+    color: bool,
+}
+
+// A shaped glyph in visual order; `cluster` is the byte offset of the text it was shaped from.
+struct ShapedGlyph {
+    font: usize,
+    id: GlyphId,
+    x: f32,
+    y: f32,
+    advance: f32,
+    cluster: usize,
+}
+
+struct ShapedLine {
+    glyphs: Vec<ShapedGlyph>,
+    width: f32,
+    rtl: bool,
+}
+
+// The font fallback chain plus caches of rasterized glyphs and measured strings, shared by everything that draws text.
+pub struct Fonts {
+    faces: Vec<Face>,
+    glyphs: RefCell<HashMap<GlyphKey, Rc<GlyphBitmap>>>,
+    // By font size bits, then text, so lookups can borrow the text.
+    widths: RefCell<HashMap<u32, HashMap<String, f32>>>,
+}
+
+impl Fonts {
+    // Resolves the family and then each fallback through fontconfig; faces already in the chain are skipped,
+    // since fontconfig answers a family that isn't installed with its default font.
+    pub fn load(cfg: &FontConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let fc = Fontconfig::new().ok_or("Could not initialize fontconfig.")?;
+        let mut seen = Vec::new();
+        let mut faces = Vec::new();
+        for family in std::iter::once(&cfg.family).chain(&cfg.fallback) {
+            let Ok(found) = fc.find(family, None) else { continue };
+            let face = (found.path, found.index.unwrap_or(0) as u32);
+            if seen.contains(&face) { continue; }
+            let Ok(data) = std::fs::read(&face.0) else { continue };
+            // Fonts are kept for the life of the panel, so the file is leaked for both parsers to borrow.
+            let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+            if let (Ok(font), Some(shaper)) = (FontRef::try_from_slice_and_index(data, face.1), rustybuzz::Face::from_slice(data, face.1)) {
+                let tables = shaper.tables();
+                let color = tables.cbdt.is_some() || tables.sbix.is_some() || tables.colr.is_some();
+                faces.push(Face { font, shaper, color });
+                seen.push(face);
+            }
+        }
+        if faces.is_empty() { return Err("Could not find font.".into()); }
+        Ok(Self { faces, glyphs: RefCell::new(HashMap::new()), widths: RefCell::new(HashMap::new()) })
+    }
+
+    pub fn font(&self, index: usize) -> &FontRef<'static> {
+        &self.faces[index].font
+    }
+
+    // The first font in the chain that has the whole grapheme cluster (joiners and variation selectors aside),
+    // else the first with its base character, else the primary font (whose .notdef gets drawn).
+    // A cluster asking for emoji presentation (U+FE0F) goes to a color font first.
+    pub fn font_for(&self, cluster: &str) -> usize {
+        let significant = |c: &char| !matches!(c, '\u{200c}' | '\u{200d}' | '\u{fe00}'..='\u{fe0f}');
+        let has_all = |f: &Face| cluster.chars().filter(significant).all(|c| f.font.glyph_id(c) != GlyphId(0));
+        let first = cluster.chars().next().unwrap_or(' ');
+        let emoji = cluster.contains('\u{fe0f}');
+        self.faces.iter().position(|f| emoji && f.color && has_all(f))
+            .or_else(|| self.faces.iter().position(has_all))
+            .or_else(|| self.faces.iter().position(|f| f.font.glyph_id(first) != GlyphId(0)))
+            .unwrap_or(0)
+    }
+
+    // Returns the bitmap for a laid out glyph and the integer pen position to draw it at. Only x keeps a
+    // subpixel offset; baselines are snapped to whole pixels so the cached bitmap fits any of them.
+    pub fn glyph(&self, font: usize, glyph: &Glyph) -> (i32, i32, Rc<GlyphBitmap>) {
+        let steps = (glyph.position.x * SUBPIXEL_STEPS).round();
+        let (x, y) = ((steps / SUBPIXEL_STEPS).floor(), glyph.position.y.round());
+        let subpixel = steps - x * SUBPIXEL_STEPS;
+        let key = GlyphKey { font, id: glyph.id.0, size: glyph.scale.y.to_bits(), subpixel: subpixel as u8 };
+
+        let mut glyphs = self.glyphs.borrow_mut();
+        if let Some(bitmap) = glyphs.get(&key) {
+            return (x as i32, y as i32, bitmap.clone());
+        }
+
+        let positioned = Glyph {
+            id: glyph.id,
+            scale: glyph.scale,
+            position: point(subpixel / SUBPIXEL_STEPS, 0.0),
+        };
+        let face = &self.faces[font];
+        let color = face.color.then(|| {
+            emoji::paint(&face.shaper, &face.font, glyph.id, glyph.scale).or_else(|| emoji::bitmap(&face.font, glyph.id, glyph.scale))
+        });
+        let bitmap = color.flatten().unwrap_or_else(|| match face.font.outline_glyph(positioned) {
+            Some(outlined) => {
+                let bounds = outlined.px_bounds();
+                let (width, height) = (bounds.width() as usize, bounds.height() as usize);
+                let mut coverage = vec![0u8; width * height];
+                outlined.draw(|gx, gy, c| {
+                    if (gx as usize) < width && (gy as usize) < height {
+                        coverage[gy as usize * width + gx as usize] = (c.clamp(0.0, 1.0) * 255.0) as u8;
+                    }
+                });
+                GlyphBitmap { left: bounds.min.x as i32, top: bounds.min.y as i32, width, coverage, color: None }
+            }
+            None => GlyphBitmap { left: 0, top: 0, width: 0, coverage: Vec::new(), color: None },
+        });
+
+        if glyphs.len() >= CACHE_LIMIT { glyphs.clear(); }
+        let bitmap = Rc::new(bitmap);
+        glyphs.insert(key, bitmap.clone());
+        (x as i32, y as i32, bitmap)
+    }
+
+    // Reorders `text` into bidi runs, splits each run where the font changes and shapes the pieces,
+    // so ligatures, marks and Arabic joining come out right. Control characters must be gone already.
+    fn shape(&self, size: f32, text: &str) -> ShapedLine {
+        let scale = PxScale::from(size);
+        let info = BidiInfo::new(text, None);
+        let rtl = info.paragraphs.first().is_some_and(|p| p.level.is_rtl());
+        let mut line = ShapedLine { glyphs: Vec::new(), width: 0.0, rtl };
+
+        for para in &info.paragraphs {
+            let (levels, runs) = info.visual_runs(para, para.range.clone());
+            for run in runs {
+                let run_rtl = levels[run.start].is_rtl();
+                let mut segments: Vec<(usize, Range<usize>)> = Vec::new();
+                for (offset, cluster) in text[run.clone()].grapheme_indices(true) {
+                    let (start, font) = (run.start + offset, self.font_for(cluster));
+                    match segments.last_mut() {
+                        Some((last, range)) if *last == font => range.end = start + cluster.len(),
+                        _ => segments.push((font, start..start + cluster.len())),
+                    }
+                }
+                if run_rtl { segments.reverse(); }
+
+                for (font, range) in segments {
+                    let face = &self.faces[font];
+                    let mut buffer = UnicodeBuffer::new();
+                    buffer.push_str(&text[range.clone()]);
+                    buffer.set_pre_context(&text[run.start..range.start]);
+                    buffer.set_post_context(&text[range.end..run.end]);
+                    buffer.set_direction(if run_rtl { Direction::RightToLeft } else { Direction::LeftToRight });
+                    buffer.guess_segment_properties();
+                    let shaped = rustybuzz::shape(&face.shaper, &[], buffer);
+
+                    // The shaper works in font units; ab_glyph's pixel size is the font height, not the em.
+                    let factor = face.font.as_scaled(scale).h_scale_factor();
+                    for (info, pos) in shaped.glyph_infos().iter().zip(shaped.glyph_positions()) {
+                        line.glyphs.push(ShapedGlyph {
+                            font,
+                            id: GlyphId(info.glyph_id as u16),
+                            x: line.width + pos.x_offset as f32 * factor,
+                            y: -pos.y_offset as f32 * factor,
+                            advance: pos.x_advance as f32 * factor,
+                            cluster: range.start + info.cluster as usize,
+                        });
+                        line.width += pos.x_advance as f32 * factor;
+                    }
+                }
+            }
+        }
+        line
+    }
+}
+
+fn without_controls(text: &str) -> Cow<'_, str> {
+    if text.chars().any(char::is_control) {
+        Cow::Owned(text.chars().filter(|c| !c.is_control()).collect())
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+pub fn calculate_text_width(fonts: &Fonts, size: f32, text: &str) -> f32 {
+    if let Some(&width) = fonts.widths.borrow().get(&size.to_bits()).and_then(|sized| sized.get(text)) {
+        return width;
+    }
+
+    let width = fonts.shape(size, &without_controls(text)).width.ceil();
+    let mut widths = fonts.widths.borrow_mut();
+    let sized = widths.entry(size.to_bits()).or_default();
+    if sized.len() >= CACHE_LIMIT { sized.clear(); }
+    sized.insert(text.to_string(), width);
+    width
+}
+
+// Keeps the grapheme clusters that fit from the visual start of the line (the right edge when the text is
+// right-to-left), so the ellipsis always stands in for what would have been drawn past the end.
+pub fn shorten_text_to_fit(fonts: &Fonts, size: f32, text: &str, max_width: f32) -> String {
+    let ellipsis = "...";
+    let ellipsis_width = calculate_text_width(fonts, size, ellipsis);
+
+    if max_width < ellipsis_width {
+        return String::new();
+    }
+    let target_width = max_width - ellipsis_width;
+
+    let text = without_controls(text);
+    let line = fonts.shape(size, &text);
+    let clusters: Vec<(usize, &str)> = text.grapheme_indices(true).collect();
+
+    // Visual extent of each cluster; one without glyphs of its own (inside a ligature) shares the previous one's.
+    let mut extents: Vec<Option<(f32, f32)>> = vec![None; clusters.len()];
+    for glyph in &line.glyphs {
+        let i = clusters.partition_point(|&(start, _)| start <= glyph.cluster).saturating_sub(1);
+        let (left, right) = (glyph.x, glyph.x + glyph.advance);
+        extents[i] = Some(extents[i].map_or((left, right), |(l, r)| (l.min(left), r.max(right))));
+    }
+    for i in 1..extents.len() {
+        if extents[i].is_none() { extents[i] = extents[i - 1]; }
+    }
+
+    // How far each cluster reaches from the start edge; sorted, the ones that fit form a prefix.
+    let mut reach: Vec<(f32, usize)> = extents.iter().enumerate().map(|(i, extent)| {
+        let (left, right) = extent.unwrap_or_default();
+        (if line.rtl { line.width - left } else { right }, i)
+    }).collect();
+    reach.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let fitting = reach.partition_point(|&(r, _)| r.ceil() <= target_width);
+
+    let mut keep = vec![false; clusters.len()];
+    for &(_, i) in &reach[..fitting] { keep[i] = true; }
+    let mut shortened: String = clusters.iter().zip(keep).filter(|(_, k)| *k).map(|(&(_, g), _)| g).collect();
+    shortened.push_str(ellipsis);
+    shortened
+}
+
+// Shapes `text` and lays it out with its top-left at `position`, pairing each glyph with the index of its font.
+// All fonts share the primary font's baseline.
+pub fn layout_paragraph(fonts: &Fonts, size: f32, position: Point, text: &str, target: &mut Vec<(usize, Glyph)>) {
+    let scale = PxScale::from(size);
+    let baseline = position.y + fonts.font(0).as_scaled(scale).ascent();
+    for glyph in fonts.shape(size, &without_controls(text)).glyphs {
+        let position = point(position.x + glyph.x, baseline + glyph.y);
+        target.push((glyph.font, glyph.id.with_scale_and_position(scale, position)));
+    }
+}
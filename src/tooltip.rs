@@ -0,0 +1,103 @@
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::*;
+use x11rb::rust_connection::RustConnection;
+
+use crate::config::ColorsConfig;
+use crate::metrics::Metrics;
+use crate::render::software::draw_text_render;
+use crate::text::{calculate_text_width, Fonts};
+use crate::VisualInfo;
+
+const ROW_HEIGHT: u16 = 20;
+const PADDING: u16 = 8;
+
+// Lines of text above the panel for the widget under the pointer. The window is created once and mapped while
+// a tooltip is shown.
+pub struct Tooltip {
+    win: Window,
+    pixmap: Pixmap,
+    gc: Gcontext,
+    visual: VisualInfo,
+    colors: ColorsConfig,
+    lines: Vec<String>,
+    width: u16,
+    height: u16,
+    // The layout slot whose widget the tooltip belongs to, while shown.
+    pub slot: Option<usize>,
+}
+
+impl Tooltip {
+    pub fn new(conn: &RustConnection, screen_num: usize, visual: VisualInfo, colors: ColorsConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let screen = &conn.setup().roots[screen_num];
+        let win = conn.generate_id()?;
+        let gc = conn.generate_id()?;
+        let values = CreateWindowAux::new()
+            .background_pixel(colors.background.pixel())
+            .border_pixel(colors.date.pixel())
+            .colormap(visual.colormap)
+            .override_redirect(1)
+            .event_mask(EventMask::EXPOSURE);
+        conn.create_window(
+            visual.depth, win, screen.root,
+            0, 0, 1, 1, 1,
+            WindowClass::INPUT_OUTPUT, visual.visual, &values,
+        )?;
+        conn.create_gc(gc, win, &CreateGCAux::new().foreground(colors.text.pixel()).background(colors.background.pixel()))?;
+        Ok(Self { win, pixmap: conn.generate_id()?, gc, visual, colors, lines: Vec::new(), width: 0, height: 0, slot: None })
+    }
+
+    pub fn window(&self) -> Window {
+        self.win
+    }
+
+    // Shows `lines` centered over `anchor_x` with the bottom edge at `anchor_y`, or updates what is shown.
+    #[allow(clippy::too_many_arguments)]
+    pub fn show(
+        &mut self, conn: &RustConnection, screen_num: usize, metrics: Metrics, font: &Fonts, render_buf: &mut Vec<u8>,
+        slot: usize, lines: Vec<String>, anchor_x: i16, anchor_y: i16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.slot == Some(slot) && lines == self.lines { return Ok(()); }
+        let text_width = lines.iter().map(|l| calculate_text_width(font, metrics.font_date, l) as u16).max().unwrap_or(0);
+        let width = text_width + 2 * metrics.px(PADDING);
+        let height = lines.len() as u16 * metrics.px(ROW_HEIGHT) + metrics.px(PADDING);
+
+        let screen = &conn.setup().roots[screen_num];
+        let x = (anchor_x as i32 - width as i32 / 2).clamp(0, (screen.width_in_pixels as i32 - width as i32 - 2).max(0));
+        let y = (anchor_y as i32 - height as i32 - 2).max(0);
+        if width != self.width || height != self.height {
+            if self.width != 0 { conn.free_pixmap(self.pixmap)?; }
+            conn.create_pixmap(self.visual.depth, self.pixmap, self.win, width, height)?;
+            self.width = width;
+            self.height = height;
+        }
+        conn.configure_window(self.win, &ConfigureWindowAux::new()
+            .x(x).y(y).width(width as u32).height(height as u32)
+            .stack_mode(StackMode::ABOVE))?;
+        self.lines = lines;
+        self.slot = Some(slot);
+        conn.map_window(self.win)?;
+        self.draw(conn, metrics, font, render_buf)
+    }
+
+    pub fn hide(&mut self, conn: &RustConnection) -> Result<(), Box<dyn std::error::Error>> {
+        if self.slot.take().is_some() {
+            conn.unmap_window(self.win)?;
+            self.lines.clear();
+        }
+        Ok(())
+    }
+
+    pub fn draw(&self, conn: &RustConnection, metrics: Metrics, font: &Fonts, render_buf: &mut Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        if self.slot.is_none() { return Ok(()); }
+        let colors = &self.colors;
+        conn.change_gc(self.gc, &ChangeGCAux::new().foreground(colors.background.pixel()))?;
+        conn.poly_fill_rectangle(self.pixmap, self.gc, &[Rectangle { x: 0, y: 0, width: self.width, height: self.height }])?;
+        let padding = metrics.px(PADDING) as i16;
+        for (i, line) in self.lines.iter().enumerate() {
+            let y = padding / 2 + (i as u16 * metrics.px(ROW_HEIGHT)) as i16;
+            draw_text_render(conn, self.pixmap, self.gc, self.visual.depth, font, render_buf, line, metrics.font_date, padding, y, colors.background, colors.text)?;
+        }
+        conn.copy_area(self.pixmap, self.win, self.gc, 0, 0, 0, 0, self.width, self.height)?;
+        Ok(())
+    }
+}
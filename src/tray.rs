@@ -0,0 +1,325 @@
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::*;
+use x11rb::rust_connection::RustConnection;
+
+use crate::color::Color;
+use crate::config::TrayConfig;
+use crate::metrics::Metrics;
+use crate::sni::SniItem;
+use crate::VisualInfo;
+
+pub const ARROW_WIDTH: u16 = 16;
+
+enum IconKind {
+    // `placed` is the geometry last configured, so that icons which didn't move aren't configured again.
+    Xembed { win: Window, parent: Window, placed: Option<(i32, i32, u32)> },
+    // Drawn by the panel itself; `key` identifies the item towards the SNI host.
+    Sni { key: String, icon: Option<Vec<u8>>, passive: bool },
+}
+
+// For SNI items `instance` and `class` are the item's Id and Title.
+struct TrayIcon {
+    kind: IconKind,
+    instance: String,
+    class: String,
+}
+
+impl TrayIcon {
+    fn matches(&self, names: &[String]) -> Option<usize> {
+        names.iter().position(|n| n.eq_ignore_ascii_case(&self.instance) || n.eq_ignore_ascii_case(&self.class))
+    }
+}
+
+// XEMBED and SNI icons in arrival order; placement (visible, collapsed into the popup, hidden) is decided on every layout.
+pub struct Tray {
+    icons: Vec<TrayIcon>,
+    popup: Window,
+    popup_open: bool,
+    arrow_region: Option<(i16, i16)>,
+    screen_width: u16,
+    // (x, y, icon index) of the SNI icons placed by the last layout, on the panel and in the popup.
+    sni_slots: Vec<(i16, i16, usize)>,
+    popup_sni_slots: Vec<(i16, i16, usize)>,
+    icon_size: u16,
+    metrics: Metrics,
+}
+
+impl Tray {
+    pub fn new(conn: &RustConnection, screen_num: usize, visual: VisualInfo, bg_color: Color, metrics: Metrics) -> Result<Self, Box<dyn std::error::Error>> {
+        let screen = &conn.setup().roots[screen_num];
+        let popup = conn.generate_id()?;
+        let values = CreateWindowAux::new()
+            .background_pixel(bg_color.pixel())
+            .border_pixel(0)
+            .colormap(visual.colormap)
+            .override_redirect(1)
+            .event_mask(EventMask::EXPOSURE | EventMask::BUTTON_PRESS);
+        conn.create_window(
+            visual.depth, popup, screen.root,
+            0, 0, 1, 1, 0,
+            WindowClass::INPUT_OUTPUT, visual.visual, &values,
+        )?;
+        Ok(Self {
+            icons: Vec::new(), popup, popup_open: false, arrow_region: None,
+            screen_width: screen.width_in_pixels,
+            sni_slots: Vec::new(), popup_sni_slots: Vec::new(), icon_size: 0, metrics,
+        })
+    }
+
+    // Slot sizes follow the scale factor; the next layout resizes the docked windows.
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    // Never taller than the panel, at any scale.
+    pub fn icon_size(&self, cfg: &TrayConfig) -> u16 {
+        self.metrics.px(cfg.icon_size).clamp(1, self.metrics.panel_height)
+    }
+
+    fn spacing(&self, cfg: &TrayConfig) -> u16 {
+        self.metrics.px(cfg.spacing)
+    }
+
+    fn slot_width(&self, cfg: &TrayConfig) -> u16 {
+        self.icon_size(cfg) + self.spacing(cfg)
+    }
+
+    fn arrow_width(&self) -> u16 {
+        self.metrics.px(ARROW_WIDTH)
+    }
+
+    pub fn popup(&self) -> Window {
+        self.popup
+    }
+
+    pub fn contains(&self, win: Window) -> bool {
+        self.icons.iter().any(|i| matches!(i.kind, IconKind::Xembed { win: w, .. } if w == win))
+    }
+
+    pub fn dock(&mut self, conn: &RustConnection, panel: Window, win: Window) -> Result<(), Box<dyn std::error::Error>> {
+        if self.contains(win) { return Ok(()); }
+
+        let (instance, class) = read_wm_class(conn, win);
+        conn.reparent_window(win, panel, 0, 0)?;
+        conn.change_window_attributes(win, &ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY))?;
+        self.icons.push(TrayIcon { kind: IconKind::Xembed { win, parent: panel, placed: None }, instance, class });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, win: Window) -> bool {
+        let len = self.icons.len();
+        self.icons.retain(|i| !matches!(i.kind, IconKind::Xembed { win: w, .. } if w == win));
+        self.icons.len() != len
+    }
+
+    // Adds a new SNI item or updates an existing one in place, keeping its arrival position.
+    pub fn update_sni(&mut self, item: SniItem) {
+        let existing = self.icons.iter_mut().find(|i| matches!(&i.kind, IconKind::Sni { key, .. } if *key == item.key));
+        let icon = TrayIcon {
+            kind: IconKind::Sni { key: item.key, icon: item.icon, passive: item.passive },
+            instance: item.id,
+            class: item.title,
+        };
+        match existing {
+            Some(slot) => *slot = icon,
+            None => self.icons.push(icon),
+        }
+    }
+
+    pub fn remove_sni(&mut self, key: &str) {
+        self.icons.retain(|i| !matches!(&i.kind, IconKind::Sni { key: k, .. } if k == key));
+    }
+
+    // SNI icons placed on the panel (or in the popup) by the last layout, as (x, y, key, RGBA pixels).
+    pub fn sni_icons(&self, in_popup: bool) -> impl Iterator<Item = (i16, i16, &str, &[u8])> {
+        let slots = if in_popup { &self.popup_sni_slots } else { &self.sni_slots };
+        slots.iter().filter_map(|&(x, y, idx)| match &self.icons[idx].kind {
+            IconKind::Sni { key, icon: Some(icon), .. } => Some((x, y, key.as_str(), icon.as_slice())),
+            _ => None,
+        })
+    }
+
+    pub fn sni_at(&self, in_popup: bool, x: i16) -> Option<&str> {
+        let slots = if in_popup { &self.popup_sni_slots } else { &self.sni_slots };
+        slots.iter()
+            .find(|&&(slot_x, _, _)| x >= slot_x && x < slot_x + self.icon_size as i16)
+            .and_then(|&(_, _, idx)| match &self.icons[idx].kind {
+                IconKind::Sni { key, .. } => Some(key.as_str()),
+                IconKind::Xembed { .. } => None,
+            })
+    }
+
+    pub fn width(&self, cfg: &TrayConfig) -> u16 {
+        let (visible, collapsed) = classify(&self.icons, cfg);
+        let arrow = if collapsed.is_empty() { 0 } else { self.arrow_width() };
+        visible.len() as u16 * self.slot_width(cfg) + arrow
+    }
+
+    pub fn arrow_region(&self) -> Option<(i16, i16)> {
+        self.arrow_region
+    }
+
+    pub fn popup_open(&self) -> bool {
+        self.popup_open
+    }
+
+    pub fn toggle_popup(&mut self) {
+        self.popup_open = !self.popup_open;
+    }
+
+    // Places the icons starting at `x` on the panel; collapsed icons go into the popup above the arrow.
+    pub fn layout(
+        &mut self, conn: &RustConnection, cfg: &TrayConfig,
+        panel: Window, x: i16, panel_y: i16, panel_height: u16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (visible, collapsed) = classify(&self.icons, cfg);
+        let slot = self.slot_width(cfg) as i16;
+        let (icon_size, spacing, arrow_width) = (self.icon_size(cfg), self.spacing(cfg), self.arrow_width());
+        let size = icon_size as u32;
+
+        for (idx, icon) in self.icons.iter_mut().enumerate() {
+            if let IconKind::Xembed { win, placed, .. } = &mut icon.kind {
+                if !visible.contains(&idx) && !collapsed.contains(&idx) {
+                    conn.unmap_window(*win)?;
+                    *placed = None;
+                }
+            }
+        }
+        self.icon_size = icon_size;
+        self.sni_slots.clear();
+        self.popup_sni_slots.clear();
+
+        let mut icons_x = x;
+        self.arrow_region = None;
+        if !collapsed.is_empty() {
+            self.arrow_region = Some((x, x + arrow_width as i16));
+            icons_x += arrow_width as i16;
+        } else {
+            self.popup_open = false;
+        }
+
+        let y_tray = (panel_height.saturating_sub(icon_size) / 2) as i32;
+        for (i, &idx) in visible.iter().enumerate() {
+            let icon_x = icons_x + i as i16 * slot + (spacing / 2) as i16;
+            self.place(conn, idx, panel, icon_x as i32, y_tray, size)?;
+        }
+
+        let pad = (spacing / 2) as i32;
+        for (i, &idx) in collapsed.iter().enumerate() {
+            self.place(conn, idx, self.popup, pad + i as i32 * slot as i32, pad, size)?;
+        }
+
+        if self.popup_open {
+            let popup_w = collapsed.len() as u32 * slot as u32;
+            let popup_h = slot as u32;
+            let popup_x = (x as i32).min(self.screen_width as i32 - popup_w as i32).max(0);
+            conn.configure_window(self.popup, &ConfigureWindowAux::new()
+                .x(popup_x).y(panel_y as i32 - popup_h as i32)
+                .width(popup_w).height(popup_h)
+                .stack_mode(StackMode::ABOVE))?;
+            conn.map_window(self.popup)?;
+        } else {
+            conn.unmap_window(self.popup)?;
+        }
+        Ok(())
+    }
+
+    fn place(&mut self, conn: &RustConnection, idx: usize, parent: Window, x: i32, y: i32, size: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let IconKind::Xembed { win, parent: current, placed } = &mut self.icons[idx].kind else {
+            let slots = if parent == self.popup { &mut self.popup_sni_slots } else { &mut self.sni_slots };
+            slots.push((x as i16, y as i16, idx));
+            return Ok(());
+        };
+        if *current != parent {
+            conn.reparent_window(*win, parent, x as i16, y as i16)?;
+            *current = parent;
+            *placed = None;
+        }
+        if *placed != Some((x, y, size)) {
+            conn.configure_window(*win, &ConfigureWindowAux::new().x(x).y(y).width(size).height(size))?;
+            *placed = Some((x, y, size));
+        }
+        conn.map_window(*win)?;
+        Ok(())
+    }
+}
+
+// Returns (visible, collapsed) icon indices; hidden icons are in neither.
+fn classify(icons: &[TrayIcon], cfg: &TrayConfig) -> (Vec<usize>, Vec<usize>) {
+    let mut ranked: Vec<(usize, usize)> = icons.iter().enumerate()
+        .filter(|(_, icon)| icon.matches(&cfg.hidden).is_none())
+        .filter(|(_, icon)| !matches!(icon.kind, IconKind::Sni { passive: true, .. }))
+        .map(|(idx, icon)| (icon.matches(&cfg.order).unwrap_or(cfg.order.len()), idx))
+        .collect();
+    ranked.sort_by_key(|&(rank, _)| rank);
+
+    if !cfg.overflow {
+        return (ranked.into_iter().map(|(_, idx)| idx).collect(), Vec::new());
+    }
+    let (visible, collapsed): (Vec<_>, Vec<_>) = ranked.into_iter().partition(|&(rank, _)| rank < cfg.order.len());
+    (visible.into_iter().map(|(_, idx)| idx).collect(), collapsed.into_iter().map(|(_, idx)| idx).collect())
+}
+
+fn read_wm_class(conn: &RustConnection, win: Window) -> (String, String) {
+    let reply = conn.get_property(false, win, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 256).ok()
+        .and_then(|cookie| cookie.reply().ok());
+    let Some(reply) = reply else { return (String::new(), String::new()) };
+
+    let mut parts = reply.value.split(|&b| b == 0).map(|s| String::from_utf8_lossy(s).to_string());
+    let instance = parts.next().unwrap_or_default();
+    let class = parts.next().unwrap_or_default();
+    (instance, class)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xembed(instance: &str, class: &str) -> TrayIcon {
+        TrayIcon { kind: IconKind::Xembed { win: 0, parent: 0, placed: None }, instance: instance.to_string(), class: class.to_string() }
+    }
+
+    fn sni(id: &str, passive: bool) -> TrayIcon {
+        TrayIcon { kind: IconKind::Sni { key: id.to_string(), icon: None, passive }, instance: id.to_string(), class: String::new() }
+    }
+
+    fn config(order: &[&str], hidden: &[&str], overflow: bool) -> TrayConfig {
+        TrayConfig {
+            order: order.iter().map(|s| s.to_string()).collect(),
+            hidden: hidden.iter().map(|s| s.to_string()).collect(),
+            overflow,
+            ..TrayConfig::default()
+        }
+    }
+
+    fn icons() -> Vec<TrayIcon> {
+        vec![xembed("nm-applet", "Nm-applet"), sni("discord", false), xembed("pasystray", "Pasystray"), sni("steam", true), xembed("blueman", "Blueman-applet")]
+    }
+
+    #[test]
+    fn arrival_order() {
+        assert_eq!(classify(&icons(), &config(&[], &[], false)), (vec![0, 1, 2, 4], vec![]));
+        assert_eq!(classify(&[], &config(&["x"], &[], true)), (vec![], vec![]));
+    }
+
+    #[test]
+    fn ordered_first() {
+        // By instance or class, case-insensitively; the rest keep arrival order after them.
+        let cfg = config(&["blueman-applet", "NM-APPLET"], &[], false);
+        assert_eq!(classify(&icons(), &cfg), (vec![4, 0, 1, 2], vec![]));
+    }
+
+    #[test]
+    fn hidden_and_passive() {
+        let cfg = config(&["pasystray"], &["Discord", "pasystray"], false);
+        assert_eq!(classify(&icons(), &cfg), (vec![0, 4], vec![]));
+    }
+
+    #[test]
+    fn overflow() {
+        let cfg = config(&["pasystray", "nm-applet"], &["blueman"], true);
+        assert_eq!(classify(&icons(), &cfg), (vec![2, 0], vec![1]));
+        // Nothing ordered: everything goes behind the arrow.
+        assert_eq!(classify(&icons(), &config(&[], &[], true)), (vec![], vec![0, 1, 2, 4]));
+    }
+}
@@ -0,0 +1,461 @@
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+// An IANA time zone read from the system's TZif files (zoneinfo), so that offsets and abbreviations follow
+// the tz database without bundling it.
+pub struct Zone {
+    pub name: String,
+    // UTC seconds at which `types[index]` starts to apply, ascending.
+    transitions: Vec<(i64, usize)>,
+    types: Vec<LocalType>,
+    // For times after the last transition; slim zoneinfo files stop listing transitions early and rely on it.
+    rule: Option<Rule>,
+}
+
+#[derive(Clone)]
+struct LocalType {
+    // Seconds east of UTC.
+    offset: i32,
+    abbr: String,
+}
+
+// A POSIX TZ string such as "CET-1CEST,M3.5.0,M10.5.0/3".
+struct Rule {
+    std: LocalType,
+    dst: Option<(LocalType, Boundary, Boundary)>,
+}
+
+#[derive(Clone, Copy)]
+enum Date {
+    // Jn: day 1 to 365, February 29th never counted.
+    Julian(u16),
+    // n: day 0 to 365, counting February 29th.
+    Ordinal(u16),
+    // Mm.w.d: weekday d (0 is Sunday) of week w (5 is the last) of month m.
+    Month(u8, u8, u8),
+}
+
+// A rule date with the local time of day it happens at, in seconds (may be negative or past 24h).
+#[derive(Clone, Copy)]
+struct Boundary {
+    date: Date,
+    time: i32,
+}
+
+impl Zone {
+    // `name` is an IANA name like "Asia/Tokyo" looked up in $TZDIR or /usr/share/zoneinfo, or a path.
+    pub fn load(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = if name.starts_with('/') {
+            name.into()
+        } else {
+            if name.split('/').any(|part| part == "..") { return Err(format!("invalid time zone \"{}\"", name).into()); }
+            let dir = std::env::var_os("TZDIR").unwrap_or_else(|| "/usr/share/zoneinfo".into());
+            std::path::PathBuf::from(dir).join(name)
+        };
+        let data = std::fs::read(&path).map_err(|e| format!("time zone \"{}\": {}", name, e))?;
+        let mut zone = parse(&data).ok_or_else(|| format!("time zone \"{}\": not a TZif file", name))?;
+        zone.name = name.to_string();
+        Ok(zone)
+    }
+
+    // The zone the C library would use: $TZ when it names a zone or holds a rule, else /etc/localtime.
+    pub fn local() -> Option<Self> {
+        match std::env::var("TZ") {
+            Ok(tz) if !tz.is_empty() => {
+                let name = tz.strip_prefix(':').unwrap_or(&tz);
+                Self::load(name).ok().or_else(|| Self::from_rule(name))
+            }
+            _ => Self::load("/etc/localtime").ok(),
+        }
+    }
+
+    // A zone following a POSIX TZ rule like "CET-1CEST,M3.5.0,M10.5.0/3" alone.
+    pub fn from_rule(rule: &str) -> Option<Self> {
+        let parsed = parse_rule(rule)?;
+        Some(Self { name: rule.to_string(), transitions: Vec::new(), types: vec![parsed.std.clone()], rule: Some(parsed) })
+    }
+
+    // The offset from UTC and the abbreviation in effect at `utc`.
+    pub fn at(&self, utc: i64) -> (i32, &str) {
+        let last = self.transitions.last().map(|&(t, _)| t);
+        if let (Some(rule), true) = (&self.rule, last.is_none_or(|t| utc >= t)) {
+            let t = rule.at(utc);
+            return (t.offset, &t.abbr);
+        }
+        let index = match self.transitions.partition_point(|&(t, _)| t <= utc) {
+            0 => 0,
+            i => self.transitions[i - 1].1,
+        };
+        let t = &self.types[index];
+        (t.offset, &t.abbr)
+    }
+
+    pub fn to_local(&self, utc: DateTime<Utc>) -> (DateTime<FixedOffset>, &str) {
+        let (offset, abbr) = self.at(utc.timestamp());
+        let offset = FixedOffset::east_opt(offset).unwrap_or(FixedOffset::east_opt(0).unwrap());
+        (utc.with_timezone(&offset), abbr)
+    }
+}
+
+// The wall clock time in `zone` at `utc`, or in chrono's idea of local time without a zone.
+pub fn localize(zone: Option<&Zone>, utc: DateTime<Utc>) -> DateTime<FixedOffset> {
+    match zone {
+        Some(zone) => zone.to_local(utc).0,
+        None => utc.with_timezone(&Local).fixed_offset(),
+    }
+}
+
+// The instant a wall clock time in `zone` stands for. Times skipped by a DST change are taken with the offset
+// from before it, repeated ones at their first occurrence.
+pub fn resolve(zone: Option<&Zone>, local: NaiveDateTime) -> DateTime<Utc> {
+    let Some(zone) = zone else {
+        return Local.from_local_datetime(&local).earliest().map_or_else(|| local.and_utc(), |t| t.with_timezone(&Utc));
+    };
+    let guess = local.and_utc().timestamp();
+    let before = zone.at(guess - 86400).0 as i64;
+    let after = zone.at(guess + 86400).0 as i64;
+    // The earlier instant of the two readings, if it maps back to the same wall time.
+    let t = [guess - before.max(after), guess - before.min(after)].into_iter()
+        .find(|&t| t + zone.at(t).0 as i64 == guess)
+        .unwrap_or(guess - before);
+    DateTime::from_timestamp(t, 0).unwrap_or_default()
+}
+
+impl Rule {
+    fn at(&self, utc: i64) -> &LocalType {
+        let Some((dst, start, end)) = &self.dst else { return &self.std };
+        let year = DateTime::from_timestamp(utc + self.std.offset as i64, 0).map_or(1970, |d| d.year());
+        // Start is given in standard time, end in daylight time.
+        let start = start.local_seconds(year) - self.std.offset as i64;
+        let end = end.local_seconds(year) - dst.offset as i64;
+        let in_dst = if start < end { utc >= start && utc < end } else { utc < end || utc >= start };
+        if in_dst { dst } else { &self.std }
+    }
+}
+
+impl Boundary {
+    // Seconds since the epoch of the boundary in `year`, as if local time were UTC.
+    fn local_seconds(&self, year: i32) -> i64 {
+        let jan1 = NaiveDate::from_ymd_opt(year, 1, 1).unwrap_or_default();
+        let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+        let day = match self.date {
+            Date::Julian(n) => {
+                let n = n.clamp(1, 365) as i64 - 1;
+                if leap && n >= 59 { n + 1 } else { n }
+            }
+            Date::Ordinal(n) => n.min(365) as i64,
+            Date::Month(m, w, d) => {
+                let first = NaiveDate::from_ymd_opt(year, m as u32, 1).unwrap_or(jan1);
+                let days_in_month = first.checked_add_months(chrono::Months::new(1)).map_or(31, |next| (next - first).num_days());
+                let mut mday = 1 + (d as i64 - first.weekday().num_days_from_sunday() as i64).rem_euclid(7) + (w as i64 - 1) * 7;
+                while mday > days_in_month { mday -= 7; }
+                first.ordinal0() as i64 + mday - 1
+            }
+        };
+        (jan1.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp()) + day * 86400 + self.time as i64
+    }
+}
+
+// TZif versions 1 to 4 (RFC 8536); the 64-bit data of version 2+ is used when present.
+fn parse(data: &[u8]) -> Option<Zone> {
+    let header = |data: &[u8]| -> Option<[usize; 6]> {
+        if data.get(..4)? != b"TZif" { return None; }
+        let mut counts = [0usize; 6];
+        for (i, count) in counts.iter_mut().enumerate() {
+            *count = u32::from_be_bytes(data.get(20 + i * 4..24 + i * 4)?.try_into().ok()?) as usize;
+        }
+        Some(counts)
+    };
+    let block_len = |c: [usize; 6], time_size: usize| {
+        let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = c;
+        timecnt * time_size + timecnt + typecnt * 6 + charcnt + leapcnt * (time_size + 4) + isstdcnt + isutcnt
+    };
+
+    let counts = header(data)?;
+    let version = data[4];
+    let (mut data, mut counts, mut time_size) = (&data[44..], counts, 4);
+    let mut footer = None;
+    if version >= b'2' && data.len() >= block_len(counts, 4) {
+        let rest = &data[block_len(counts, 4)..];
+        counts = header(rest)?;
+        data = &rest[44..];
+        time_size = 8;
+        let after = data.get(block_len(counts, 8)..)?;
+        footer = std::str::from_utf8(after).ok().and_then(|s| s.trim_matches('\n').lines().next().map(str::to_string));
+    }
+    let [_, _, _, timecnt, typecnt, charcnt] = counts;
+    if typecnt == 0 || data.len() < block_len(counts, time_size) { return None; }
+
+    let times = &data[..timecnt * time_size];
+    let indices = &data[timecnt * time_size..timecnt * (time_size + 1)];
+    let infos = &data[timecnt * (time_size + 1)..timecnt * (time_size + 1) + typecnt * 6];
+    let chars = &data[timecnt * (time_size + 1) + typecnt * 6..][..charcnt];
+
+    let types = infos.chunks_exact(6).map(|info| {
+        let offset = i32::from_be_bytes(info[..4].try_into().unwrap());
+        let abbr = chars.get(info[5] as usize..).unwrap_or_default();
+        let end = abbr.iter().position(|&b| b == 0).unwrap_or(abbr.len());
+        LocalType { offset, abbr: String::from_utf8_lossy(&abbr[..end]).into_owned() }
+    }).collect::<Vec<_>>();
+    let transitions = times.chunks_exact(time_size).zip(indices).map(|(t, &i)| {
+        let t = if time_size == 8 { i64::from_be_bytes(t.try_into().unwrap()) } else { i32::from_be_bytes(t.try_into().unwrap()) as i64 };
+        (t, (i as usize).min(typecnt - 1))
+    }).collect();
+
+    Some(Zone { name: String::new(), transitions, types, rule: footer.as_deref().and_then(parse_rule) })
+}
+
+// "std offset [dst [offset] [,start[/time],end[/time]]]"; offsets count hours west of UTC.
+fn parse_rule(s: &str) -> Option<Rule> {
+    let mut p = Cursor(s.as_bytes());
+    let std_abbr = p.name()?;
+    let std = LocalType { offset: -p.offset(24)?, abbr: std_abbr };
+    if p.0.is_empty() { return Some(Rule { std, dst: None }); }
+
+    let dst_abbr = p.name()?;
+    let dst_offset = if p.peek().is_some_and(|c| c != b',') { -p.offset(24)? } else { std.offset + 3600 };
+    let dst = LocalType { offset: dst_offset, abbr: dst_abbr };
+    // Without dates POSIX leaves them implementation-defined; glibc uses the US rules.
+    let (start, end) = if p.eat(b',') {
+        let start = p.boundary()?;
+        if !p.eat(b',') { return None; }
+        (start, p.boundary()?)
+    } else {
+        (Boundary { date: Date::Month(3, 2, 0), time: 7200 }, Boundary { date: Date::Month(11, 1, 0), time: 7200 })
+    };
+    Some(Rule { std, dst: Some((dst, start, end)) })
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.0.first().copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        let found = self.peek() == Some(c);
+        if found { self.0 = &self.0[1..]; }
+        found
+    }
+
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &'a [u8] {
+        let n = self.0.iter().position(|&c| !f(c)).unwrap_or(self.0.len());
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        taken
+    }
+
+    // "CET" or a quoted "<+09>".
+    fn name(&mut self) -> Option<String> {
+        let name = if self.eat(b'<') {
+            let name = self.take_while(|c| c != b'>');
+            if !self.eat(b'>') { return None; }
+            name
+        } else {
+            self.take_while(|c| c.is_ascii_alphabetic())
+        };
+        (name.len() >= 3).then(|| String::from_utf8_lossy(name).into_owned())
+    }
+
+    fn number(&mut self) -> Option<i32> {
+        std::str::from_utf8(self.take_while(|c| c.is_ascii_digit())).ok()?.parse().ok()
+    }
+
+    // [+-]hh[:mm[:ss]] in seconds, with at most `max_hours`: 24 for UTC offsets, 167 for the transition times
+    // RFC 8536 allows. None on anything larger rather than an overflowed offset.
+    fn offset(&mut self, max_hours: i32) -> Option<i32> {
+        let sign = if self.eat(b'-') { -1 } else { self.eat(b'+'); 1 };
+        let mut seconds = self.number().filter(|h| *h <= max_hours)?.checked_mul(3600)?;
+        for unit in [60, 1] {
+            if !self.eat(b':') { break; }
+            seconds = seconds.checked_add(self.number().filter(|n| *n < 60)?.checked_mul(unit)?)?;
+        }
+        Some(sign * seconds)
+    }
+
+    fn boundary(&mut self) -> Option<Boundary> {
+        let date = if self.eat(b'J') {
+            Date::Julian(self.number()? as u16)
+        } else if self.eat(b'M') {
+            let m = self.number()?;
+            if !self.eat(b'.') { return None; }
+            let w = self.number()?;
+            if !self.eat(b'.') { return None; }
+            let d = self.number()?;
+            if !(1..=12).contains(&m) || !(1..=5).contains(&w) || d > 6 { return None; }
+            Date::Month(m as u8, w as u8, d as u8)
+        } else {
+            Date::Ordinal(self.number()? as u16)
+        };
+        let time = if self.eat(b'/') { self.offset(167)? } else { 7200 };
+        Some(Boundary { date, time })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A TZif file with `types` of (offset, abbreviation), and for version 2 a copy with 64-bit times and `footer`.
+    fn tzif(version: u8, transitions: &[(i64, u8)], types: &[(i32, &str)], footer: &str) -> Vec<u8> {
+        let mut chars = Vec::new();
+        let mut infos = Vec::new();
+        for (offset, abbr) in types {
+            infos.extend(offset.to_be_bytes());
+            infos.extend([0, chars.len() as u8]);
+            chars.extend(abbr.bytes().chain([0]));
+        }
+        let block = |time_size: usize| {
+            let mut out = b"TZif".to_vec();
+            out.push(version);
+            out.extend([0; 15]);
+            for count in [0, 0, 0, transitions.len(), types.len(), chars.len()] { out.extend((count as u32).to_be_bytes()); }
+            for &(t, _) in transitions {
+                if time_size == 8 { out.extend(t.to_be_bytes()) } else { out.extend((t as i32).to_be_bytes()) }
+            }
+            out.extend(transitions.iter().map(|&(_, i)| i));
+            out.extend(&infos);
+            out.extend(&chars);
+            out
+        };
+        let mut data = block(4);
+        if version >= b'2' {
+            data.extend(block(8));
+            data.extend(format!("\n{}\n", footer).bytes());
+        }
+        data
+    }
+
+    fn utc(s: &str) -> i64 {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap().and_utc().timestamp()
+    }
+
+    fn rule_zone(rule: &str) -> Zone {
+        Zone::from_rule(rule).unwrap()
+    }
+
+    // The offsets just before and at `instant`.
+    fn around(zone: &Zone, instant: &str) -> (i32, i32) {
+        let t = utc(instant);
+        (zone.at(t - 1).0, zone.at(t).0)
+    }
+
+    #[test]
+    fn version_1() {
+        let zone = parse(&tzif(0, &[(1000, 1)], &[(0, "LMT"), (3600, "AAA")], "")).unwrap();
+        assert_eq!(zone.at(0), (0, "LMT"));
+        assert_eq!(zone.at(999), (0, "LMT"));
+        assert_eq!(zone.at(1000), (3600, "AAA"));
+        assert!(zone.rule.is_none());
+    }
+
+    #[test]
+    fn version_2_uses_64_bit_data_and_footer() {
+        let transitions = [(-5_000_000_000, 1), (utc("2020-01-01 00:00:00"), 2)];
+        let types = [(0, "LMT"), (1800, "OLD"), (3600, "CET"), (7200, "CEST")];
+        let zone = parse(&tzif(b'2', &transitions, &types, "CET-1CEST,M3.5.0,M10.5.0/3")).unwrap();
+        // Before 1901, out of reach of the 32-bit times.
+        assert_eq!(zone.at(-5_000_000_001), (0, "LMT"));
+        assert_eq!(zone.at(-5_000_000_000), (1800, "OLD"));
+        // After the last transition the footer rule takes over.
+        assert_eq!(zone.at(utc("2030-01-15 00:00:00")), (3600, "CET"));
+        assert_eq!(zone.at(utc("2030-07-15 00:00:00")), (7200, "CEST"));
+    }
+
+    #[test]
+    fn not_tzif() {
+        assert!(parse(b"TZxf").is_none());
+        assert!(parse(&tzif(b'2', &[], &[], "")).is_none());
+        assert!(Zone::load("../etc/passwd").is_err());
+    }
+
+    #[test]
+    fn month_week_day_rules() {
+        let zone = rule_zone("CET-1CEST,M3.5.0,M10.5.0/3");
+        assert_eq!(around(&zone, "2024-03-31 01:00:00"), (3600, 7200));
+        assert_eq!(around(&zone, "2024-10-27 01:00:00"), (7200, 3600));
+        assert_eq!(zone.at(utc("2024-07-01 00:00:00")).1, "CEST");
+        // The last Sunday when the month has five.
+        assert_eq!(around(&zone, "2026-03-29 01:00:00"), (3600, 7200));
+    }
+
+    #[test]
+    fn default_us_rules() {
+        let zone = rule_zone("EST5EDT");
+        assert_eq!(around(&zone, "2024-03-10 07:00:00"), (-18000, -14400));
+        assert_eq!(around(&zone, "2024-11-03 06:00:00"), (-14400, -18000));
+    }
+
+    #[test]
+    fn southern_hemisphere() {
+        let zone = rule_zone("AEST-10AEDT,M10.1.0,M4.1.0/3");
+        assert_eq!(zone.at(utc("2024-01-15 00:00:00")), (39600, "AEDT"));
+        assert_eq!(zone.at(utc("2024-06-15 00:00:00")), (36000, "AEST"));
+        assert_eq!(around(&zone, "2024-04-06 16:00:00"), (39600, 36000));
+        assert_eq!(around(&zone, "2024-10-05 16:00:00"), (36000, 39600));
+    }
+
+    #[test]
+    fn julian_days_skip_february_29() {
+        // J60 is March 1st and J300 October 27th, leap year or not.
+        let zone = rule_zone("XXX0YYY,J60/0,J300/0");
+        assert_eq!(around(&zone, "2024-03-01 00:00:00"), (0, 3600));
+        assert_eq!(around(&zone, "2023-03-01 00:00:00"), (0, 3600));
+        assert_eq!(around(&zone, "2024-10-26 23:00:00"), (3600, 0));
+    }
+
+    #[test]
+    fn ordinal_days_count_february_29() {
+        let zone = rule_zone("XXX0YYY,59/0,300/0");
+        assert_eq!(around(&zone, "2024-02-29 00:00:00"), (0, 3600));
+        assert_eq!(around(&zone, "2023-03-01 00:00:00"), (0, 3600));
+    }
+
+    #[test]
+    fn offsets() {
+        let rule = parse_rule("<+0530>-5:30").unwrap();
+        assert_eq!((rule.std.offset, rule.std.abbr.as_str()), (19800, "+0530"));
+        assert!(rule.dst.is_none());
+        assert_eq!(parse_rule("<-03>3").unwrap().std.offset, -10800);
+        assert_eq!(parse_rule("XXX-1:02:03").unwrap().std.offset, 3723);
+        // An explicit daylight offset, and a boundary time past midnight.
+        let zone = rule_zone("XXX3YYY2,M3.2.0/26,M11.1.0/-1");
+        assert_eq!(around(&zone, "2024-03-11 05:00:00"), (-10800, -7200));
+        assert_eq!(around(&zone, "2024-11-03 01:00:00"), (-7200, -10800));
+        assert!(parse_rule("XX0").is_none());
+        assert!(parse_rule("XXX0YYY,M13.1.0,M10.5.0").is_none());
+        assert!(parse_rule("XXX0YYY,M3.5.0").is_none());
+        // Out of range or overflowing numbers are rejected instead of wrapping.
+        assert!(parse_rule("XXX25").is_none());
+        assert!(parse_rule("XXX1:60").is_none());
+        assert!(parse_rule("XXX999999999").is_none());
+        assert!(parse_rule("XXX0YYY,M3.2.0/168,M11.1.0").is_none());
+        assert!(parse_rule("XXX0YYY,M3.2.0/167,M11.1.0/-167").is_some());
+    }
+
+    #[test]
+    fn resolve_gaps_and_overlaps() {
+        let zone = rule_zone("CET-1CEST,M3.5.0,M10.5.0/3");
+        let local = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        let resolve = |s: &str| resolve(Some(&zone), local(s)).timestamp();
+        assert_eq!(resolve("2024-07-01 12:00:00"), utc("2024-07-01 10:00:00"));
+        assert_eq!(resolve("2024-01-01 12:00:00"), utc("2024-01-01 11:00:00"));
+        // 02:30 never happens on March 31st: taken in winter time, which is 03:30 summer time.
+        assert_eq!(resolve("2024-03-31 02:30:00"), utc("2024-03-31 01:30:00"));
+        // It happens twice on October 27th: the first, in summer time.
+        assert_eq!(resolve("2024-10-27 02:30:00"), utc("2024-10-27 00:30:00"));
+        assert_eq!(resolve("2024-10-27 03:00:00"), utc("2024-10-27 02:00:00"));
+        let back = localize(Some(&zone), DateTime::from_timestamp(resolve("2024-10-27 02:30:00"), 0).unwrap());
+        assert_eq!(back.naive_local(), local("2024-10-27 02:30:00"));
+    }
+
+    #[test]
+    fn system_zones() {
+        let (Ok(berlin), Ok(new_york)) = (Zone::load("Europe/Berlin"), Zone::load("America/New_York")) else { return };
+        assert_eq!(berlin.at(utc("2024-03-31 00:59:59")), (3600, "CET"));
+        assert_eq!(berlin.at(utc("2024-03-31 01:00:00")), (7200, "CEST"));
+        assert_eq!(new_york.at(utc("2024-11-03 06:00:00")), (-18000, "EST"));
+        assert_eq!(new_york.at(utc("2090-07-01 00:00:00")), (-14400, "EDT"));
+        assert_eq!(berlin.at(utc("1880-01-01 00:00:00")).1, "LMT");
+    }
+}
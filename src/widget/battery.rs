@@ -0,0 +1,363 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use x11rb::protocol::xproto::Rectangle;
+
+use super::{Change, Ctx, Sampler, Widget};
+use crate::color::Fill;
+use crate::config::expand_home;
+use crate::command;
+use crate::layout::Region;
+use crate::text::calculate_text_width;
+
+// The battery icon's body, at 96 DPI.
+const ICON_WIDTH: u16 = 18;
+const ICON_HEIGHT: u16 = 10;
+
+// Charge of the laptop's batteries, combined, with a small battery drawn filled to the level and the estimated
+// time left. Takes no room on machines without a battery.
+pub struct Battery {
+    root: PathBuf,
+    sampler: Sampler,
+    readings: Vec<Reading>,
+    summary: Option<Summary>,
+    // Set once the critical command ran, until the battery charges or gets above the critical level again.
+    critical_ran: bool,
+    width: u16,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Charging,
+    Discharging,
+    Full,
+    NotCharging,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Charging => "charging",
+            Status::Discharging => "discharging",
+            Status::Full => "full",
+            Status::NotCharging => "not charging",
+        }
+    }
+}
+
+// One power supply; energies in Wh and power in W, when the driver reports them.
+struct Reading {
+    name: String,
+    percent: f64,
+    status: Status,
+    energy: Option<(f64, f64)>,
+    power: Option<f64>,
+}
+
+struct Summary {
+    percent: u8,
+    status: Status,
+    // Until empty while discharging, until full while charging.
+    time: Option<Duration>,
+}
+
+impl Battery {
+    pub fn new(ctx: &Ctx) -> Result<Self, Box<dyn std::error::Error>> {
+        let cfg = &ctx.config.battery;
+        if !cfg.interval.is_finite() || cfg.interval <= 0.0 { return Err("[battery] interval must be above 0".into()); }
+        if cfg.critical > cfg.warning { return Err("[battery] critical is above warning".into()); }
+        let root = PathBuf::from(expand_home(&cfg.root));
+        let sampler = Sampler::new(Duration::from_secs_f64(cfg.interval));
+        let mut battery = Self { root, sampler, readings: Vec::new(), summary: None, critical_ran: false, width: 0 };
+        battery.sample(ctx);
+        Ok(battery)
+    }
+
+    fn sample(&mut self, ctx: &Ctx) {
+        let cfg = &ctx.config.battery;
+        self.readings = read_all(&self.root, &cfg.batteries);
+        self.summary = summarize(&self.readings);
+        if !self.reached_critical(cfg.critical) { return; }
+        if let (Some(cmd), Some(summary)) = (&cfg.critical_command, &self.summary) {
+            command::spawn(cmd, &[&summary.percent.to_string()]);
+        }
+    }
+
+    // Whether the charge just dropped to `level` while discharging: once per drop, until the battery charges or
+    // gets above it again.
+    fn reached_critical(&mut self, level: u8) -> bool {
+        let Some(summary) = &self.summary else { return false };
+        let critical = summary.status == Status::Discharging && summary.percent <= level;
+        let reached = critical && !self.critical_ran;
+        self.critical_ran = critical;
+        reached
+    }
+
+    fn text(&self, ctx: &Ctx) -> Option<String> {
+        let summary = self.summary.as_ref()?;
+        let mut text = format!("{}%", summary.percent);
+        if let Some(time) = summary.time.filter(|_| ctx.config.battery.show_time) {
+            text += &format!(" {}", hours_minutes(time));
+        }
+        Some(text)
+    }
+
+    fn text_width(&self, ctx: &Ctx) -> u16 {
+        let Some(text) = self.text(ctx) else { return 0 };
+        let m = ctx.metrics;
+        // The icon with its nub, a gap, the text and the padding.
+        m.px(ICON_WIDTH + 2 + 4) + calculate_text_width(ctx.fonts, m.font_main, &text) as u16 + m.px(8)
+    }
+}
+
+// "2:07".
+fn hours_minutes(time: Duration) -> String {
+    let minutes = time.as_secs() / 60;
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+// The batteries named, or else every one of the system's (not those of mice and the like).
+fn read_all(root: &Path, names: &[String]) -> Vec<Reading> {
+    let mut dirs: Vec<PathBuf> = match std::fs::read_dir(root) {
+        Ok(dir) => dir.flatten().map(|e| e.path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    dirs.sort();
+    dirs.into_iter()
+        .filter(|dir| {
+            let name = dir.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            if !names.is_empty() { return names.contains(&name); }
+            value(dir, "type").as_deref() == Some("Battery") && value(dir, "scope").as_deref() != Some("Device")
+        })
+        .filter_map(|dir| read(&dir))
+        .collect()
+}
+
+fn value(dir: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(dir.join(name)).ok().map(|s| s.trim().to_string())
+}
+
+fn number(dir: &Path, name: &str) -> Option<f64> {
+    value(dir, name)?.parse().ok()
+}
+
+// Drivers report either energy (µWh, µW) or charge (µAh, µA), which the voltage turns into energy.
+fn read(dir: &Path) -> Option<Reading> {
+    if value(dir, "present").as_deref() == Some("0") { return None; }
+    let name = dir.file_name()?.to_string_lossy().into_owned();
+    let (energy, power) = match (number(dir, "energy_now"), number(dir, "energy_full")) {
+        (Some(now), Some(full)) => (Some((now / 1e6, full / 1e6)), number(dir, "power_now").map(|p| p.abs() / 1e6)),
+        _ => {
+            let volts = number(dir, "voltage_now").or_else(|| number(dir, "voltage_min_design")).map_or(1.0, |v| v / 1e6);
+            let energy = number(dir, "charge_now").zip(number(dir, "charge_full")).map(|(now, full)| (now / 1e6 * volts, full / 1e6 * volts));
+            (energy, number(dir, "current_now").map(|c| c.abs() / 1e6 * volts))
+        }
+    };
+    let percent = number(dir, "capacity")
+        .or_else(|| energy.filter(|(_, full)| *full > 0.0).map(|(now, full)| now / full * 100.0))?;
+    let status = match value(dir, "status").as_deref() {
+        Some("Charging") => Status::Charging,
+        Some("Discharging") => Status::Discharging,
+        Some("Full") => Status::Full,
+        _ => Status::NotCharging,
+    };
+    Some(Reading { name, percent: percent.clamp(0.0, 100.0), status, energy, power: power.filter(|p| *p > 0.0) })
+}
+
+// All batteries as one: charge weighted by capacity when the energies are known, charging while any charges.
+fn summarize(readings: &[Reading]) -> Option<Summary> {
+    if readings.is_empty() { return None; }
+    let status = if readings.iter().any(|r| r.status == Status::Charging) {
+        Status::Charging
+    } else if readings.iter().any(|r| r.status == Status::Discharging) {
+        Status::Discharging
+    } else if readings.iter().all(|r| r.status == Status::Full) {
+        Status::Full
+    } else {
+        Status::NotCharging
+    };
+    let energies: Option<Vec<(f64, f64)>> = readings.iter().map(|r| r.energy).collect();
+    let (now, full) = energies.as_ref().map_or((0.0, 0.0), |e| e.iter().fold((0.0, 0.0), |(n, f), (en, ef)| (n + en, f + ef)));
+    let percent = if full > 0.0 { now / full * 100.0 } else { readings.iter().map(|r| r.percent).sum::<f64>() / readings.len() as f64 };
+    let power: f64 = readings.iter().filter_map(|r| r.power).sum();
+    let hours = match status {
+        _ if full <= 0.0 || power <= 0.0 => None,
+        Status::Discharging => Some(now / power),
+        Status::Charging => Some((full - now).max(0.0) / power),
+        _ => None,
+    };
+    // Nonsense from a power reading just after plugging in or out.
+    let time = hours.filter(|h| *h < 100.0).map(|h| Duration::from_secs((h * 3600.0) as u64));
+    Some(Summary { percent: percent.round() as u8, status, time })
+}
+
+impl Widget for Battery {
+    fn measure(&mut self, ctx: &Ctx, _available: u16) -> u16 {
+        self.width = self.text_width(ctx);
+        self.width
+    }
+
+    fn render(&mut self, ctx: &mut Ctx, region: Region, _full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>> {
+        let (colors, m, cfg) = (ctx.config.colors, ctx.metrics, &ctx.config.battery);
+        ctx.renderer.fill_background(ctx.conn, region.rect(m.panel_height), colors.background)?;
+        let (Some(summary), Some(text)) = (&self.summary, self.text(ctx)) else { return Ok(()) };
+        let discharging = summary.status == Status::Discharging;
+        let color = match summary.percent {
+            p if discharging && p <= cfg.critical => cfg.critical_color,
+            p if discharging && p <= cfg.warning => cfg.warning_color,
+            _ => colors.text,
+        };
+        // Charging shows in the fill.
+        let fill = if summary.status == Status::Charging { colors.underline } else { color };
+
+        let (w, h) = (m.px(ICON_WIDTH), m.px(ICON_HEIGHT));
+        let (x, y) = (region.x + m.px(4) as i16, (m.panel_height.saturating_sub(h) / 2) as i16);
+        let line = m.px(1).max(1);
+        ctx.renderer.stroke_rounded_rect(ctx.conn, Rectangle { x, y, width: w, height: h }, m.px(2), line, color)?;
+        let nub = Rectangle { x: x + w as i16, y: y + (h / 4) as i16, width: m.px(2), height: h - h / 4 * 2 };
+        ctx.renderer.fill_rect(ctx.conn, nub, color)?;
+        let inset = 2 * line;
+        let level = ((w - 2 * inset) as f64 * summary.percent as f64 / 100.0).round() as u16;
+        if level > 0 {
+            let rect = Rectangle { x: x + inset as i16, y: y + inset as i16, width: level, height: h - 2 * inset };
+            ctx.renderer.fill_rounded_rect(ctx.conn, rect, 0, Fill::Solid(fill))?;
+        }
+
+        let text_x = x + m.px(ICON_WIDTH + 2 + 4) as i16;
+        ctx.renderer.draw_text(ctx.conn, ctx.fonts, &text, m.font_main, text_x, m.text_y, color)?;
+        damage.push(region.rect(m.panel_height));
+        Ok(())
+    }
+
+    // Every battery, then the time left for all of them.
+    fn tooltip(&mut self, _ctx: &Ctx, _region: Region, _x: i16) -> Option<Vec<String>> {
+        let summary = self.summary.as_ref()?;
+        let mut lines: Vec<String> = self.readings.iter().map(|r| {
+            let mut line = format!("{}  {:.0}%  {}", r.name, r.percent, r.status.name());
+            if let Some(power) = r.power.filter(|_| r.status != Status::Full) { line += &format!("  {:.1} W", power); }
+            line
+        }).collect();
+        match (summary.time, summary.status) {
+            (Some(time), Status::Discharging) => lines.push(format!("{} until empty", hours_minutes(time))),
+            (Some(time), Status::Charging) => lines.push(format!("{} until full", hours_minutes(time))),
+            _ => {}
+        }
+        Some(lines)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        Some(self.sampler.next())
+    }
+
+    fn tick(&mut self, ctx: &mut Ctx, now: Instant) -> Result<Change, Box<dyn std::error::Error>> {
+        if !self.sampler.due(now) { return Ok(Change::None); }
+        let before = self.text(ctx);
+        let status = self.summary.as_ref().map(|s| s.status);
+        self.sample(ctx);
+        if self.text_width(ctx) != self.width { return Ok(Change::Relayout); }
+        let changed = before != self.text(ctx) || status != self.summary.as_ref().map(|s| s.status);
+        Ok(if changed { Change::Redraw } else { Change::None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::widget::test_util::TempDir;
+
+    // An empty power_supply directory of the test's own.
+    fn root(test: &str) -> TempDir {
+        TempDir::new("battery", test)
+    }
+
+    fn supply(root: &TempDir, name: &str, files: &[(&str, &str)]) {
+        for (file, value) in files { root.write(&format!("{}/{}", name, file), &format!("{}\n", value)); }
+    }
+
+    fn battery(summary: Option<Summary>) -> Battery {
+        Battery {
+            root: PathBuf::new(), sampler: Sampler::new(Duration::from_secs(1)), readings: Vec::new(),
+            summary, critical_ran: false, width: 0,
+        }
+    }
+
+    #[test]
+    fn energy() {
+        let root = root("energy");
+        supply(&root, "BAT0", &[
+            ("type", "Battery"), ("status", "Discharging"), ("capacity", "50"),
+            ("energy_now", "30000000"), ("energy_full", "60000000"), ("power_now", "15000000"),
+        ]);
+        let readings = read_all(&root, &[]);
+        assert_eq!(readings.len(), 1);
+        assert_eq!((readings[0].name.as_str(), readings[0].percent), ("BAT0", 50.0));
+        assert_eq!((readings[0].energy, readings[0].power), (Some((30.0, 60.0)), Some(15.0)));
+        let summary = summarize(&readings).unwrap();
+        assert_eq!((summary.percent, summary.status.name()), (50, "discharging"));
+        assert_eq!(summary.time, Some(Duration::from_secs(2 * 3600)));
+    }
+
+    #[test]
+    fn charge() {
+        let root = root("charge");
+        // µAh and µA at 12 V, without a capacity file.
+        supply(&root, "BAT1", &[
+            ("type", "Battery"), ("status", "Charging"),
+            ("charge_now", "2000000"), ("charge_full", "4000000"), ("voltage_now", "12000000"), ("current_now", "-1000000"),
+        ]);
+        let readings = read_all(&root, &[]);
+        assert_eq!(readings[0].percent, 50.0);
+        assert_eq!((readings[0].energy, readings[0].power), (Some((24.0, 48.0)), Some(12.0)));
+        let summary = summarize(&readings).unwrap();
+        assert_eq!((summary.percent, summary.status.name()), (50, "charging"));
+        assert_eq!(summary.time.map(hours_minutes).as_deref(), Some("2:00"));
+    }
+
+    #[test]
+    fn combined() {
+        let root = root("combined");
+        supply(&root, "BAT0", &[("type", "Battery"), ("status", "Discharging"), ("energy_now", "30000000"), ("energy_full", "60000000"), ("power_now", "10000000")]);
+        supply(&root, "BAT1", &[("type", "Battery"), ("status", "Full"), ("energy_now", "10000000"), ("energy_full", "40000000")]);
+        // Neither the charger nor a mouse counts.
+        supply(&root, "AC", &[("type", "Mains"), ("online", "0")]);
+        supply(&root, "hidpp_battery_0", &[("type", "Battery"), ("scope", "Device"), ("capacity", "5"), ("status", "Discharging")]);
+        let readings = read_all(&root, &[]);
+        assert_eq!(readings.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(), ["BAT0", "BAT1"]);
+        // Weighted by capacity, not 50% and 25% averaged.
+        let summary = summarize(&readings).unwrap();
+        assert_eq!((summary.percent, summary.status.name()), (40, "discharging"));
+        assert_eq!(summary.time, Some(Duration::from_secs(4 * 3600)));
+        assert_eq!(read_all(&root, &["BAT1".to_string()]).len(), 1);
+    }
+
+    #[test]
+    fn missing_files() {
+        let root = root("missing");
+        supply(&root, "BAT0", &[("type", "Battery"), ("capacity", "70")]);
+        supply(&root, "BAT1", &[("type", "Battery"), ("present", "0"), ("capacity", "30")]);
+        supply(&root, "BAT2", &[("type", "Battery"), ("status", "Discharging")]);
+        let readings = read_all(&root, &[]);
+        assert_eq!(readings.len(), 1);
+        assert_eq!((readings[0].energy, readings[0].power, readings[0].status.name()), (None, None, "not charging"));
+        let summary = summarize(&readings).unwrap();
+        assert_eq!((summary.percent, summary.time), (70, None));
+        assert!(read_all(&root.join("nowhere"), &[]).is_empty());
+        assert!(summarize(&[]).is_none());
+    }
+
+    #[test]
+    fn critical_once_per_drop() {
+        let mut battery = battery(None);
+        let mut at = |percent, status| {
+            battery.summary = Some(Summary { percent, status, time: None });
+            battery.reached_critical(10)
+        };
+        assert!(!at(15, Status::Discharging));
+        assert!(at(10, Status::Discharging));
+        assert!(!at(9, Status::Discharging));
+        assert!(!at(9, Status::Charging));
+        assert!(at(8, Status::Discharging));
+        assert!(!at(11, Status::Discharging));
+        assert!(at(10, Status::Discharging));
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDate, Utc};
+use x11rb::protocol::xproto::{ButtonPressEvent, Rectangle};
+use x11rb::protocol::Event;
+
+use super::{next_boundary, wall_deadline, Change, Ctx, Widget};
+use crate::calendar::{Calendar, CalendarAction};
+use crate::ics::{self, Events};
+use crate::layout::Region;
+use crate::locale::{self, Names};
+use crate::text::calculate_text_width;
+use crate::tz::{self, Zone};
+
+// Summaries in the reminder are cut to this many characters.
+const REMINDER_CHARS: usize = 24;
+
+// Time over date, in the configured formats and zone, redrawn when the text can have changed. With events
+// configured, an upcoming one is announced to the left.
+pub struct Clock {
+    zone: Option<Zone>,
+    names: Option<Names>,
+    // Every second when a format shows seconds, else every minute.
+    period: Duration,
+    // The wall clock time at which the text shown goes out of date.
+    shown_until: SystemTime,
+    width: u16,
+    calendar: Option<Calendar>,
+    // When focus loss last closed the calendar: a click on the clock that took the focus shouldn't reopen it.
+    calendar_closed: Option<Instant>,
+    events: Option<Events>,
+    // What is shown to the left, worked out once per tick since following the recurrence rules is costly.
+    reminder: Option<String>,
+}
+
+impl Clock {
+    pub fn new(ctx: &Ctx) -> Result<Self, Box<dyn std::error::Error>> {
+        let cfg = &ctx.config.clock;
+        locale::validate(&cfg.format)?;
+        locale::validate(&cfg.date_format)?;
+        let zone = match &cfg.timezone {
+            Some(name) => Some(Zone::load(name)?),
+            None => Zone::local(),
+        };
+        let names = Names::configured(cfg.locale.as_deref())?;
+        let seconds = locale::has_seconds(&cfg.format, names.as_ref())
+            || (!cfg.single_line && locale::has_seconds(&cfg.date_format, names.as_ref()));
+        let period = Duration::from_secs(if seconds { 1 } else { 60 });
+        let events = if cfg.events.is_empty() { None } else { Some(Events::load(&cfg.events)?) };
+        let mut clock = Self {
+            zone, names, period, shown_until: SystemTime::now(), width: 0, calendar: None, calendar_closed: None,
+            events, reminder: None,
+        };
+        clock.reminder = clock.next_reminder(ctx);
+        Ok(clock)
+    }
+
+    fn now(&self) -> (DateTime<FixedOffset>, Option<&str>) {
+        match &self.zone {
+            Some(zone) => {
+                let (time, abbr) = zone.to_local(Utc::now());
+                (time, Some(abbr))
+            }
+            None => (Local::now().fixed_offset(), None),
+        }
+    }
+
+    // "Standup in 10 min" for the next timed event starting within the configured minutes.
+    fn next_reminder(&self, ctx: &Ctx) -> Option<String> {
+        let (events, minutes) = (self.events.as_ref()?, ctx.config.clock.reminder);
+        if minutes == 0 { return None; }
+        let now = Utc::now();
+        let next = events.occurrences(now, now + chrono::Duration::minutes(minutes as i64), self.zone.as_ref())
+            .into_iter().find(|o| !o.all_day && o.start > now)?;
+        let left = ((next.start - now).num_seconds() + 59) / 60;
+        let summary: String = if next.summary.chars().count() > REMINDER_CHARS {
+            next.summary.chars().take(REMINDER_CHARS - 1).chain(['…']).collect()
+        } else {
+            next.summary
+        };
+        Some(format!("{} in {} min", summary, left))
+    }
+
+    // A heading with the date of `day`, then its events or that there are none.
+    fn agenda(&self, day: NaiveDate) -> Vec<String> {
+        let local = self.zone.as_ref();
+        let heading = tz::localize(local, tz::resolve(local, day.and_hms_opt(12, 0, 0).unwrap_or_default()));
+        let mut lines = vec![locale::format("%A %-d %B %Y", &heading, None, self.names.as_ref())];
+        let Some(events) = &self.events else { return lines };
+        let (from, to) = day_range(local, day);
+        let time = |t: DateTime<Utc>| {
+            let t = tz::localize(local, t);
+            if t.date_naive() == day { t.format("%H:%M").to_string() } else { "…".to_string() }
+        };
+        for o in events.occurrences(from, to, local) {
+            if o.all_day {
+                lines.push(format!("All day  {}", o.summary));
+            } else if o.end == o.start {
+                lines.push(format!("{}  {}", time(o.start), o.summary));
+            } else {
+                lines.push(format!("{}–{}  {}", time(o.start), time(o.end), o.summary));
+            }
+        }
+        if lines.len() == 1 { lines.push("No events".to_string()); }
+        lines
+    }
+
+    // Marks the days with events in the month shown and refreshes the agenda, then draws.
+    fn draw_calendar(&mut self, ctx: &mut Ctx) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(calendar) = &self.calendar else { return Ok(()) };
+        let (month, selected) = (calendar.month(), calendar.selected());
+        let local = self.zone.as_ref();
+        let marks: HashSet<NaiveDate> = match &self.events {
+            Some(events) => {
+                let next = month.checked_add_months(chrono::Months::new(1)).unwrap_or(month);
+                let (from, to) = (day_range(local, month).0, day_range(local, next).0);
+                events.occurrences(from, to, local).iter()
+                    .flat_map(|o| ics::days(o, local))
+                    .filter(|d| d.month() == month.month() && d.year() == month.year())
+                    .collect()
+            }
+            None => HashSet::new(),
+        };
+        let agenda = selected.map(|day| self.agenda(day)).unwrap_or_default();
+        let Some(calendar) = &mut self.calendar else { return Ok(()) };
+        calendar.set_marks(marks);
+        calendar.set_agenda(ctx.conn, ctx.fonts, agenda)?;
+        calendar.draw(ctx.conn, ctx.fonts, self.names.as_ref(), ctx.render_buffer)
+    }
+
+    fn close_calendar(&mut self, ctx: &Ctx, restore_focus: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(calendar) = self.calendar.take() { calendar.close(ctx.conn, restore_focus)?; }
+        Ok(())
+    }
+
+    // The lower line is empty in single-line mode.
+    fn strings(&self, ctx: &Ctx) -> (String, String) {
+        let cfg = &ctx.config.clock;
+        let (now, abbr) = self.now();
+        let time = locale::format(&cfg.format, &now, abbr, self.names.as_ref());
+        let date = if cfg.single_line { String::new() } else { locale::format(&cfg.date_format, &now, abbr, self.names.as_ref()) };
+        (time, date)
+    }
+
+    fn text_width(&self, ctx: &Ctx) -> u16 {
+        let (time_str, date_str) = self.strings(ctx);
+        let time_width = calculate_text_width(ctx.fonts, ctx.metrics.font_main, &time_str);
+        let date_width = calculate_text_width(ctx.fonts, ctx.metrics.font_date, &date_str);
+        time_width.max(date_width) as u16 + ctx.metrics.px(8) + self.reminder_width(ctx)
+    }
+
+    // The reminder with the gap after it.
+    fn reminder_width(&self, ctx: &Ctx) -> u16 {
+        self.reminder.as_deref().map_or(0, |r| calculate_text_width(ctx.fonts, ctx.metrics.font_main, r) as u16 + ctx.metrics.px(8))
+    }
+}
+
+// The instants `day` starts and ends at in `local`.
+fn day_range(local: Option<&Zone>, day: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = |d: NaiveDate| tz::resolve(local, d.and_hms_opt(0, 0, 0).unwrap_or_default());
+    (start(day), start(day.succ_opt().unwrap_or(day)))
+}
+
+impl Widget for Clock {
+    fn measure(&mut self, ctx: &Ctx, _available: u16) -> u16 {
+        self.width = self.text_width(ctx);
+        self.width
+    }
+
+    fn render(&mut self, ctx: &mut Ctx, region: Region, _full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>> {
+        let (colors, m) = (ctx.config.colors, ctx.metrics);
+        ctx.renderer.fill_background(ctx.conn, region.rect(m.panel_height), colors.background)?;
+
+        let (time_str, date_str) = self.strings(ctx);
+        self.shown_until = next_boundary(SystemTime::now(), self.period);
+        let (text_color, date_color) = match self.reminder {
+            Some(_) => {
+                let color = ctx.config.clock.reminder_color.unwrap_or(colors.underline);
+                (color, color)
+            }
+            None => (colors.text, colors.date),
+        };
+        if let Some(reminder) = &self.reminder {
+            ctx.renderer.draw_text(ctx.conn, ctx.fonts, reminder, m.font_main, region.x, m.text_y, text_color)?;
+        }
+        let x = region.x + self.reminder_width(ctx) as i16;
+        let time_width = calculate_text_width(ctx.fonts, m.font_main, &time_str);
+        let date_width = calculate_text_width(ctx.fonts, m.font_date, &date_str);
+        let max_text_width = if time_width > date_width { time_width } else { date_width };
+
+        let time_x_offset = if time_width < max_text_width { (max_text_width - time_width) / 2.0 } else { 0.0 };
+        let time_y = if ctx.config.clock.single_line { m.text_y } else { m.px(2) as i16 };
+        ctx.renderer.draw_text(ctx.conn, ctx.fonts, &time_str, m.font_main, x + time_x_offset as i16, time_y, text_color)?;
+
+        if !date_str.is_empty() {
+            let date_x_offset = if date_width < max_text_width { (max_text_width - date_width) / 2.0 } else { 0.0 };
+            ctx.renderer.draw_text(ctx.conn, ctx.fonts, &date_str, m.font_date, x + date_x_offset as i16, m.px(20) as i16, date_color)?;
+        }
+
+        damage.push(region.rect(m.panel_height));
+        Ok(())
+    }
+
+    // The left button opens the calendar above the clock, or closes it.
+    fn click(&mut self, ctx: &mut Ctx, region: Region, e: &ButtonPressEvent) -> Result<Change, Box<dyn std::error::Error>> {
+        if e.detail != 1 { return Ok(Change::None); }
+        if self.calendar.is_some() {
+            self.close_calendar(ctx, true)?;
+        } else if self.calendar_closed.is_none_or(|at| at.elapsed() > Duration::from_millis(200)) {
+            let cfg = &ctx.config.clock;
+            let today = self.now().0.date_naive();
+            let anchor_x = region.x + (region.width / 2) as i16;
+            let calendar = Calendar::open(
+                ctx.conn, ctx.screen_num, ctx.visual, ctx.config.colors, ctx.metrics,
+                today, cfg.first_weekday.weekday(), cfg.week_numbers, anchor_x, ctx.panel_y,
+            )?;
+            self.calendar = Some(calendar);
+        }
+        Ok(Change::None)
+    }
+
+    fn event(&mut self, ctx: &mut Ctx, event: &Event) -> Result<Change, Box<dyn std::error::Error>> {
+        let Some(calendar) = &mut self.calendar else { return Ok(Change::None) };
+        let win = calendar.window();
+        let action = match event {
+            Event::Expose(e) if e.window == win && e.count == 0 => CalendarAction::Redraw,
+            Event::ButtonPress(e) if e.event == win => calendar.handle_click(e),
+            Event::KeyPress(e) if e.event == win => calendar.handle_key(e),
+            Event::FocusOut(e) if e.event == win => {
+                let action = calendar.handle_focus_out(e);
+                if matches!(action, CalendarAction::Close) { self.calendar_closed = Some(Instant::now()); }
+                action
+            }
+            _ => CalendarAction::None,
+        };
+        match action {
+            CalendarAction::None => {}
+            CalendarAction::Redraw => self.draw_calendar(ctx)?,
+            // The same day again hides its agenda.
+            CalendarAction::Day(day) if self.events.is_some() => {
+                calendar.select((calendar.selected() != Some(day)).then_some(day));
+                self.draw_calendar(ctx)?;
+            }
+            CalendarAction::Day(_) => {}
+            CalendarAction::Close => {
+                let by_key = matches!(event, Event::KeyPress(_));
+                self.close_calendar(ctx, by_key)?;
+            }
+        }
+        Ok(Change::None)
+    }
+
+    // With events configured, today's date and agenda.
+    fn tooltip(&mut self, _ctx: &Ctx, _region: Region, _x: i16) -> Option<Vec<String>> {
+        self.events.as_ref()?;
+        Some(self.agenda(self.now().0.date_naive()))
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        Some(wall_deadline(self.shown_until))
+    }
+
+    // The width changes with the text, e.g. at a new day with a proportional font. Ticking every second, the
+    // clock only grows, so that narrower digits don't move everything else around each second.
+    fn tick(&mut self, ctx: &mut Ctx, _now: Instant) -> Result<Change, Box<dyn std::error::Error>> {
+        // Past midnight the highlight moves on.
+        let today = self.now().0.date_naive();
+        let new_day = self.calendar.as_mut().is_some_and(|c| c.set_today(today));
+        let reloaded = self.events.as_mut().is_some_and(|e| e.refresh());
+        if new_day || reloaded { self.draw_calendar(ctx)?; }
+        // A reminder coming or going always resizes.
+        let reminder = self.next_reminder(ctx);
+        let toggled = reminder.is_some() != self.reminder.is_some();
+        self.reminder = reminder;
+        let width = self.text_width(ctx);
+        if toggled || width > self.width || (width < self.width && self.period >= Duration::from_secs(60)) {
+            self.width = width;
+            return Ok(Change::Relayout);
+        }
+        Ok(Change::Redraw)
+    }
+
+    fn rescale(&mut self, ctx: &mut Ctx) -> Result<(), Box<dyn std::error::Error>> {
+        self.close_calendar(ctx, true)
+    }
+
+    fn has_popup(&self) -> bool {
+        self.calendar.is_some()
+    }
+}
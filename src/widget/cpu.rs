@@ -0,0 +1,260 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use x11rb::protocol::xproto::Rectangle;
+
+use super::graph::{self, History};
+use super::{Change, Ctx, Sampler, Widget};
+use crate::config::expand_home;
+use crate::layout::Region;
+use crate::text::calculate_text_width;
+
+// Space between the graph, the core bars and the text, and the width of a core's bar, at 96 DPI.
+const GAP: u16 = 4;
+const CORE_BAR: u16 = 3;
+
+// CPU usage from /proc/stat between two samples, overall and per core, as text and/or a graph of the last
+// samples; the tooltip has the load averages.
+pub struct Cpu {
+    root: PathBuf,
+    sampler: Sampler,
+    // (busy, total) jiffies at the last sample, overall first and then per core.
+    counters: Vec<(u64, u64)>,
+    // Busy fraction since the sample before, in the same order; empty until there are two samples.
+    usage: Vec<f32>,
+    history: History,
+    width: u16,
+}
+
+impl Cpu {
+    pub fn new(ctx: &Ctx) -> Result<Self, Box<dyn std::error::Error>> {
+        let cfg = &ctx.config.cpu;
+        if !cfg.interval.is_finite() || cfg.interval <= 0.0 { return Err("[cpu] interval must be above 0".into()); }
+        let root = PathBuf::from(expand_home(&cfg.root));
+        let counters = read_stat(&root).map_err(|e| format!("{}: {}", root.join("stat").display(), e))?;
+        if counters.is_empty() { return Err(format!("{}: no cpu lines", root.join("stat").display()).into()); }
+        let sampler = Sampler::new(Duration::from_secs_f64(cfg.interval));
+        Ok(Self { root, sampler, counters, usage: Vec::new(), history: History::new(cfg.history), width: 0 })
+    }
+
+    fn sample(&mut self) {
+        let Ok(counters) = read_stat(&self.root) else { return };
+        if counters.len() == self.counters.len() {
+            self.usage = counters.iter().zip(&self.counters).map(|(&(busy, total), &(last_busy, last_total))| {
+                let total = total.saturating_sub(last_total);
+                if total == 0 { 0.0 } else { busy.saturating_sub(last_busy) as f32 / total as f32 }
+            }).collect();
+            if let Some(&total) = self.usage.first() { self.history.push(total); }
+        } else {
+            // Cores came or went.
+            self.usage.clear();
+        }
+        self.counters = counters;
+    }
+
+    fn cores(&self) -> usize {
+        self.counters.len().saturating_sub(1)
+    }
+
+    // "23%", or each core's "12 5 80 3"; with `widest` as wide as it gets.
+    fn value(&self, ctx: &Ctx, widest: bool) -> String {
+        let percent = |i: usize| if widest { 100 } else { self.usage.get(i).map_or(0, |u| (u * 100.0).round() as u32) };
+        if ctx.config.cpu.per_core {
+            (1..=self.cores()).map(|i| percent(i).to_string()).collect::<Vec<_>>().join(" ")
+        } else {
+            format!("{}%", percent(0))
+        }
+    }
+
+    // Widths of the graph, the core bars and the text, each 0 when not shown.
+    fn parts(&self, ctx: &Ctx) -> (u16, u16, u16) {
+        let (cfg, m) = (&ctx.config.cpu, ctx.metrics);
+        let graph = if cfg.display.graph() || cfg.display.bar() { m.px(cfg.graph_width) } else { 0 };
+        let bars = if graph > 0 && cfg.per_core { self.cores() as u16 * m.px(CORE_BAR + 1) } else { 0 };
+        let text = if cfg.display.text() {
+            let template = format!("CPU {}", self.value(ctx, true)).replace(|c: char| c.is_ascii_digit(), "0");
+            calculate_text_width(ctx.fonts, m.font_main, &template) as u16
+        } else {
+            0
+        };
+        (graph, bars, text)
+    }
+}
+
+// (busy, total) jiffies of the "cpu" line and the "cpuN" lines after it. Waiting for I/O counts as idle, and
+// guest time is already part of user time.
+fn read_stat(root: &Path) -> std::io::Result<Vec<(u64, u64)>> {
+    let text = std::fs::read_to_string(root.join("stat"))?;
+    Ok(text.lines().filter(|l| l.starts_with("cpu")).map(|line| {
+        let fields: Vec<u64> = line.split_whitespace().skip(1).take(8).map(|f| f.parse().unwrap_or(0)).collect();
+        let total: u64 = fields.iter().sum();
+        let idle = fields.get(3).copied().unwrap_or(0) + fields.get(4).copied().unwrap_or(0);
+        (total - idle, total)
+    }).collect())
+}
+
+impl Widget for Cpu {
+    fn measure(&mut self, ctx: &Ctx, _available: u16) -> u16 {
+        let m = ctx.metrics;
+        let (graph, bars, text) = self.parts(ctx);
+        let shown = [graph, bars, text].iter().filter(|w| **w > 0).count() as u16;
+        self.width = graph + bars + text + m.px(GAP) * shown.saturating_sub(1) + m.px(8);
+        self.width
+    }
+
+    fn render(&mut self, ctx: &mut Ctx, region: Region, _full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>> {
+        let (colors, m) = (ctx.config.colors, ctx.metrics);
+        let cfg = &ctx.config.cpu;
+        let (display, high, high_color) = (cfg.display, cfg.high, cfg.high_color);
+        let color = cfg.color.unwrap_or(colors.underline);
+        let backdrop = cfg.graph_background;
+        ctx.renderer.fill_background(ctx.conn, region.rect(m.panel_height), colors.background)?;
+        let (graph_w, bars_w, _) = self.parts(ctx);
+        let pad = m.px(8);
+        let area = |x: i16, width: u16| Rectangle { x, y: pad as i16, width, height: m.panel_height.saturating_sub(2 * pad) };
+
+        let mut x = region.x + m.px(4) as i16;
+        if graph_w > 0 {
+            let rect = area(x, graph_w);
+            if let Some(bg) = backdrop { ctx.renderer.fill_rect(ctx.conn, rect, bg)?; }
+            if display.bar() {
+                graph::meter(ctx, rect, self.usage.first().copied().unwrap_or(0.0), color)?;
+            } else {
+                graph::area(ctx, rect, &self.history, 1.0, color)?;
+            }
+            x += (graph_w + m.px(GAP)) as i16;
+        }
+        if bars_w > 0 {
+            for core in 0..self.cores() {
+                let rect = area(x + (core as u16 * m.px(CORE_BAR + 1)) as i16, m.px(CORE_BAR));
+                if let Some(bg) = backdrop { ctx.renderer.fill_rect(ctx.conn, rect, bg)?; }
+                graph::bar(ctx, rect, self.usage.get(core + 1).copied().unwrap_or(0.0), color)?;
+            }
+            x += (bars_w + m.px(GAP)) as i16;
+        }
+        if display.text() {
+            let label = "CPU ";
+            ctx.renderer.draw_text(ctx.conn, ctx.fonts, label, m.font_main, x, m.text_y, colors.date)?;
+            x += calculate_text_width(ctx.fonts, m.font_main, label) as i16;
+            let busy = self.usage.first().is_some_and(|u| u * 100.0 >= high as f32);
+            let fg = if busy { high_color } else { colors.text };
+            ctx.renderer.draw_text(ctx.conn, ctx.fonts, &self.value(ctx, false), m.font_main, x, m.text_y, fg)?;
+        }
+        damage.push(region.rect(m.panel_height));
+        Ok(())
+    }
+
+    // Load averages and runnable tasks from /proc/loadavg, then the usage per core.
+    fn tooltip(&mut self, _ctx: &Ctx, _region: Region, _x: i16) -> Option<Vec<String>> {
+        let mut lines = Vec::new();
+        if let Ok(loadavg) = std::fs::read_to_string(self.root.join("loadavg")) {
+            let fields: Vec<&str> = loadavg.split_whitespace().collect();
+            if fields.len() >= 4 {
+                lines.push(format!("Load average  {}  {}  {}", fields[0], fields[1], fields[2]));
+                if let Some((running, total)) = fields[3].split_once('/') {
+                    lines.push(format!("Tasks  {} running of {}", running, total));
+                }
+            }
+        }
+        if let Some(total) = self.usage.first() {
+            lines.push(format!("Usage  {:.0}%", total * 100.0));
+            let cores: Vec<String> = self.usage[1..].iter().enumerate().map(|(i, u)| format!("cpu{} {:.0}%", i, u * 100.0)).collect();
+            lines.extend(cores.chunks(4).map(|c| c.join("  ")));
+        }
+        Some(lines)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        Some(self.sampler.next())
+    }
+
+    fn tick(&mut self, _ctx: &mut Ctx, now: Instant) -> Result<Change, Box<dyn std::error::Error>> {
+        if !self.sampler.due(now) { return Ok(Change::None); }
+        let cores = self.cores();
+        self.sample();
+        Ok(if self.cores() != cores { Change::Relayout } else { Change::Redraw })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::widget::test_util::TempDir;
+
+    // A /proc of the test's own, holding just `stat`.
+    fn root(test: &str) -> TempDir {
+        TempDir::new("cpu", test)
+    }
+
+    fn write_stat(root: &TempDir, lines: &[&str]) {
+        root.write("stat", &lines.iter().map(|l| format!("{}\n", l)).collect::<String>());
+    }
+
+    fn cpu(root: &Path) -> Cpu {
+        let root = root.to_path_buf();
+        let counters = read_stat(&root).unwrap();
+        Cpu { root, sampler: Sampler::new(Duration::from_secs(1)), counters, usage: Vec::new(), history: History::new(4), width: 0 }
+    }
+
+    #[test]
+    fn busy_and_total() {
+        let root = root("parse");
+        // user nice system idle iowait irq softirq steal guest guest_nice
+        write_stat(&root, &[
+            "cpu  100 10 90 700 100 0 0 0 500 0",
+            "cpu0 60 10 30 300 100 0 0 0 500 0",
+            "cpu1 40 0 60 400 0 0 0 0 0 0",
+            "intr 12345 1 2",
+            "ctxt 6789",
+            "btime 1700000000",
+        ]);
+        // Waiting for I/O is idle and guest time isn't counted twice.
+        assert_eq!(read_stat(&root).unwrap(), [(200, 1000), (100, 500), (100, 500)]);
+        write_stat(&root, &["intr 1"]);
+        assert!(read_stat(&root).unwrap().is_empty());
+        assert!(read_stat(&root.join("nowhere")).is_err());
+    }
+
+    #[test]
+    fn usage_between_samples() {
+        let root = root("usage");
+        write_stat(&root, &["cpu  100 0 100 700 100 0 0 0", "cpu0 50 0 50 350 50 0 0 0", "cpu1 50 0 50 350 50 0 0 0"]);
+        let mut cpu = cpu(&root);
+        assert_eq!(cpu.cores(), 2);
+        assert!(cpu.usage.is_empty());
+        write_stat(&root, &["cpu  200 0 200 1500 100 0 0 0", "cpu0 150 0 50 350 50 0 0 0", "cpu1 50 0 150 1050 50 0 0 0"]);
+        cpu.sample();
+        assert_eq!(cpu.usage, [0.2, 1.0, 0.125]);
+        assert_eq!(cpu.history.max(), 0.2);
+        // Counters that went backwards count as idle rather than wrapping.
+        write_stat(&root, &["cpu  100 0 100 1600 100 0 0 0", "cpu0 150 0 50 350 50 0 0 0", "cpu1 50 0 150 1050 50 0 0 0"]);
+        cpu.sample();
+        assert_eq!(cpu.usage, [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn cores_coming_and_going() {
+        let root = root("cores");
+        write_stat(&root, &["cpu  100 0 100 700 100 0 0 0", "cpu0 50 0 50 350 50 0 0 0", "cpu1 50 0 50 350 50 0 0 0"]);
+        let mut cpu = cpu(&root);
+        // A core went offline: nothing to compare against until the next sample.
+        write_stat(&root, &["cpu  150 0 150 800 100 0 0 0", "cpu0 100 0 100 400 50 0 0 0"]);
+        cpu.sample();
+        assert_eq!(cpu.cores(), 1);
+        assert!(cpu.usage.is_empty());
+        write_stat(&root, &["cpu  200 0 150 850 100 0 0 0", "cpu0 150 0 100 450 50 0 0 0"]);
+        cpu.sample();
+        assert_eq!(cpu.usage, [0.5, 0.5]);
+        // And came back.
+        write_stat(&root, &["cpu  300 0 150 950 100 0 0 0", "cpu0 200 0 100 500 50 0 0 0", "cpu1 50 0 50 350 50 0 0 0"]);
+        cpu.sample();
+        assert_eq!(cpu.cores(), 2);
+        assert!(cpu.usage.is_empty());
+        // A file without cpu lines leaves nothing to show, without panicking.
+        write_stat(&root, &["intr 1"]);
+        cpu.sample();
+        cpu.sample();
+        assert!(cpu.usage.is_empty());
+        assert_eq!(cpu.cores(), 0);
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+
+use x11rb::protocol::xproto::Rectangle;
+
+use super::Ctx;
+use crate::color::Color;
+
+// The last samples of something, oldest first, for the small graphs of the polling widgets.
+pub struct History {
+    samples: VecDeque<f32>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2);
+        Self { samples: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        if self.samples.len() == self.capacity { self.samples.pop_front(); }
+        self.samples.push_back(sample);
+    }
+
+    pub fn max(&self) -> f32 {
+        self.samples.iter().copied().fold(0.0, f32::max)
+    }
+
+    // The x range of sample `i` when `capacity` samples span `rect`, the newest at the right edge.
+    fn column(&self, rect: Rectangle, i: usize) -> (i16, u16) {
+        let slot = self.capacity - self.samples.len() + i;
+        let left = rect.x + (slot * rect.width as usize / self.capacity) as i16;
+        let right = rect.x + ((slot + 1) * rect.width as usize / self.capacity) as i16;
+        (left, (right - left).max(1) as u16)
+    }
+}
+
+// The height in `rect` for `sample` out of `max`.
+fn height(rect: Rectangle, sample: f32, max: f32) -> u16 {
+    if max <= 0.0 { return 0; }
+    (rect.height as f32 * (sample / max).clamp(0.0, 1.0)).round() as u16
+}
+
+// Fills `rect` up to each sample's height, as an area chart.
+pub fn area(ctx: &mut Ctx, rect: Rectangle, history: &History, max: f32, color: Color) -> Result<(), Box<dyn std::error::Error>> {
+    for (i, sample) in history.samples.iter().enumerate() {
+        let h = height(rect, *sample, max);
+        if h == 0 { continue; }
+        let (x, width) = history.column(rect, i);
+        ctx.renderer.fill_rect(ctx.conn, Rectangle { x, y: rect.y + (rect.height - h) as i16, width, height: h }, color)?;
+    }
+    Ok(())
+}
+
+// A line through the samples: each column covers the heights between its sample and the one before.
+pub fn line(ctx: &mut Ctx, rect: Rectangle, history: &History, max: f32, color: Color) -> Result<(), Box<dyn std::error::Error>> {
+    let thickness = ctx.metrics.px(1);
+    let mut previous = None;
+    for (i, sample) in history.samples.iter().enumerate() {
+        let h = height(rect, *sample, max);
+        let (low, high) = previous.map_or((h, h), |p: u16| (h.min(p), h.max(p)));
+        previous = Some(h);
+        let (x, width) = history.column(rect, i);
+        let span = (high - low + thickness).min(rect.height);
+        let y = (rect.y + (rect.height - high) as i16 - (thickness / 2) as i16).clamp(rect.y, rect.y + (rect.height - span) as i16);
+        ctx.renderer.fill_rect(ctx.conn, Rectangle { x, y, width, height: span }, color)?;
+    }
+    Ok(())
+}
+
+// A bar filled from the left to `fraction` of `rect`.
+pub fn meter(ctx: &mut Ctx, rect: Rectangle, fraction: f32, color: Color) -> Result<(), Box<dyn std::error::Error>> {
+    let w = (rect.width as f32 * fraction.clamp(0.0, 1.0)).round() as u16;
+    if w == 0 { return Ok(()); }
+    ctx.renderer.fill_rect(ctx.conn, Rectangle { width: w, ..rect }, color)
+}
+
+// A vertical bar filled from the bottom to `fraction` of `rect`.
+pub fn bar(ctx: &mut Ctx, rect: Rectangle, fraction: f32, color: Color) -> Result<(), Box<dyn std::error::Error>> {
+    let h = height(rect, fraction, 1.0);
+    if h == 0 { return Ok(()); }
+    ctx.renderer.fill_rect(ctx.conn, Rectangle { y: rect.y + (rect.height - h) as i16, height: h, ..rect }, color)
+}
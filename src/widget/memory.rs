@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use x11rb::protocol::xproto::Rectangle;
+
+use super::graph::{self, History};
+use super::{format_bytes, Change, Ctx, Sampler, Widget};
+use crate::color::Color;
+use crate::config::{expand_home, MemoryConfig};
+use crate::layout::Region;
+use crate::text::calculate_text_width;
+
+// Space between the graph or bar and the text, at 96 DPI.
+const GAP: u16 = 4;
+
+// Memory in use from /proc/meminfo, what the kernel couldn't hand out without swapping (MemTotal minus
+// MemAvailable), and swap, as text, a bar or a graph; the tooltip breaks it down.
+pub struct Memory {
+    root: PathBuf,
+    sampler: Sampler,
+    info: Info,
+    history: History,
+    swap_history: History,
+    width: u16,
+}
+
+// In bytes.
+struct Info {
+    total: u64,
+    available: u64,
+    free: u64,
+    buffers: u64,
+    // The page cache and reclaimable slab.
+    cache: u64,
+    shared: u64,
+    swap_total: u64,
+    swap_free: u64,
+}
+
+impl Info {
+    fn used(&self) -> u64 {
+        self.total.saturating_sub(self.available)
+    }
+
+    fn swap_used(&self) -> u64 {
+        self.swap_total.saturating_sub(self.swap_free)
+    }
+
+    fn fraction(&self) -> f32 {
+        if self.total == 0 { 0.0 } else { self.used() as f32 / self.total as f32 }
+    }
+
+    fn swap_fraction(&self) -> f32 {
+        if self.swap_total == 0 { 0.0 } else { self.swap_used() as f32 / self.swap_total as f32 }
+    }
+}
+
+fn read_meminfo(root: &Path) -> std::io::Result<Info> {
+    let text = std::fs::read_to_string(root.join("meminfo"))?;
+    // "MemTotal:       16318412 kB"
+    let fields: HashMap<&str, u64> = text.lines().filter_map(|line| {
+        let (name, rest) = line.split_once(':')?;
+        let kb: u64 = rest.split_whitespace().next()?.parse().ok()?;
+        Some((name, kb * 1024))
+    }).collect();
+    let get = |name: &str| fields.get(name).copied().unwrap_or(0);
+    let cache = get("Cached") + get("SReclaimable");
+    Ok(Info {
+        total: get("MemTotal"),
+        // Kernels before 3.14 don't estimate it.
+        available: fields.get("MemAvailable").copied().unwrap_or(get("MemFree") + get("Buffers") + cache),
+        free: get("MemFree"),
+        buffers: get("Buffers"),
+        cache,
+        shared: get("Shmem"),
+        swap_total: get("SwapTotal"),
+        swap_free: get("SwapFree"),
+    })
+}
+
+fn check(cfg: &MemoryConfig) -> Result<(), Box<dyn std::error::Error>> {
+    if !cfg.interval.is_finite() || cfg.interval <= 0.0 { return Err("[memory] interval must be above 0".into()); }
+    if cfg.critical < cfg.warning { return Err("[memory] critical is below warning".into()); }
+    Ok(())
+}
+
+// The warning or critical color once that much of memory is in use, None below both.
+fn level(cfg: &MemoryConfig, fraction: f32) -> Option<Color> {
+    let percent = fraction * 100.0;
+    if percent >= cfg.critical as f32 {
+        Some(cfg.critical_color)
+    } else if percent >= cfg.warning as f32 {
+        Some(cfg.warning_color)
+    } else {
+        None
+    }
+}
+
+impl Memory {
+    pub fn new(ctx: &Ctx) -> Result<Self, Box<dyn std::error::Error>> {
+        let cfg = &ctx.config.memory;
+        check(cfg)?;
+        let root = PathBuf::from(expand_home(&cfg.root));
+        let info = read_meminfo(&root).map_err(|e| format!("{}: {}", root.join("meminfo").display(), e))?;
+        let sampler = Sampler::new(Duration::from_secs_f64(cfg.interval));
+        let mut memory = Self {
+            root, sampler, info,
+            history: History::new(cfg.history), swap_history: History::new(cfg.history), width: 0,
+        };
+        memory.record();
+        Ok(memory)
+    }
+
+    fn record(&mut self) {
+        self.history.push(self.info.fraction());
+        self.swap_history.push(self.info.swap_fraction());
+    }
+
+    fn show_swap(&self, ctx: &Ctx) -> bool {
+        ctx.config.memory.swap && self.info.swap_total > 0
+    }
+
+    // "MEM 42%  SWP 3%", with `widest` as wide as it gets.
+    fn text(&self, ctx: &Ctx, widest: bool) -> Vec<(&'static str, String)> {
+        let percent = |f: f32| if widest { 100 } else { (f * 100.0).round() as u32 };
+        let mut parts = vec![("MEM ", format!("{}%", percent(self.info.fraction())))];
+        if self.show_swap(ctx) { parts.push(("  SWP ", format!("{}%", percent(self.info.swap_fraction())))); }
+        parts
+    }
+
+    // Widths of the graph or bar and of the text, 0 when not shown.
+    fn parts(&self, ctx: &Ctx) -> (u16, u16) {
+        let (cfg, m) = (&ctx.config.memory, ctx.metrics);
+        let graph = if cfg.display.graph() || cfg.display.bar() { m.px(cfg.graph_width) } else { 0 };
+        let text = if cfg.display.text() {
+            let template: String = self.text(ctx, true).iter().map(|(label, value)| format!("{}{}", label, value)).collect();
+            calculate_text_width(ctx.fonts, m.font_main, &template.replace(|c: char| c.is_ascii_digit(), "0")) as u16
+        } else {
+            0
+        };
+        (graph, text)
+    }
+}
+
+impl Widget for Memory {
+    fn measure(&mut self, ctx: &Ctx, _available: u16) -> u16 {
+        let m = ctx.metrics;
+        let (graph, text) = self.parts(ctx);
+        let gap = if graph > 0 && text > 0 { m.px(GAP) } else { 0 };
+        self.width = graph + gap + text + m.px(8);
+        self.width
+    }
+
+    fn render(&mut self, ctx: &mut Ctx, region: Region, _full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>> {
+        let (colors, m) = (ctx.config.colors, ctx.metrics);
+        let cfg = &ctx.config.memory;
+        let display = cfg.display;
+        let level = level(cfg, self.info.fraction());
+        let color = level.unwrap_or(cfg.color.unwrap_or(colors.underline));
+        let swap_color = cfg.swap_color.unwrap_or(colors.date);
+        let backdrop = cfg.graph_background;
+        let swap = self.show_swap(ctx);
+        ctx.renderer.fill_background(ctx.conn, region.rect(m.panel_height), colors.background)?;
+        let (graph_w, _) = self.parts(ctx);
+
+        let mut x = region.x + m.px(4) as i16;
+        if graph_w > 0 {
+            let pad = m.px(8);
+            let rect = Rectangle { x, y: pad as i16, width: graph_w, height: m.panel_height.saturating_sub(2 * pad) };
+            if let Some(bg) = backdrop { ctx.renderer.fill_rect(ctx.conn, rect, bg)?; }
+            if display.bar() && swap {
+                // Memory above swap.
+                let half = (rect.height - m.px(2)) / 2;
+                graph::meter(ctx, Rectangle { height: half, ..rect }, self.info.fraction(), color)?;
+                let lower = Rectangle { y: rect.y + (rect.height - half) as i16, height: half, ..rect };
+                graph::meter(ctx, lower, self.info.swap_fraction(), swap_color)?;
+            } else if display.bar() {
+                graph::meter(ctx, rect, self.info.fraction(), color)?;
+            } else {
+                graph::area(ctx, rect, &self.history, 1.0, color)?;
+                if swap { graph::line(ctx, rect, &self.swap_history, 1.0, swap_color)?; }
+            }
+            x += (graph_w + m.px(GAP)) as i16;
+        }
+        if display.text() {
+            for (i, (label, value)) in self.text(ctx, false).into_iter().enumerate() {
+                ctx.renderer.draw_text(ctx.conn, ctx.fonts, label, m.font_main, x, m.text_y, colors.date)?;
+                x += calculate_text_width(ctx.fonts, m.font_main, label) as i16;
+                let fg = if i == 0 { level.unwrap_or(colors.text) } else { colors.text };
+                ctx.renderer.draw_text(ctx.conn, ctx.fonts, &value, m.font_main, x, m.text_y, fg)?;
+                x += calculate_text_width(ctx.fonts, m.font_main, &value) as i16;
+            }
+        }
+        damage.push(region.rect(m.panel_height));
+        Ok(())
+    }
+
+    fn tooltip(&mut self, _ctx: &Ctx, _region: Region, _x: i16) -> Option<Vec<String>> {
+        let info = &self.info;
+        let mut lines = vec![
+            format!("Memory  {} of {} ({:.0}%)", format_bytes(info.used() as f64), format_bytes(info.total as f64), info.fraction() * 100.0),
+            format!("Available  {}", format_bytes(info.available as f64)),
+            format!("Free  {}", format_bytes(info.free as f64)),
+            format!("Buffers  {}", format_bytes(info.buffers as f64)),
+            format!("Cache  {}", format_bytes(info.cache as f64)),
+            format!("Shared  {}", format_bytes(info.shared as f64)),
+        ];
+        if info.swap_total > 0 {
+            lines.push(format!("Swap  {} of {} ({:.0}%)", format_bytes(info.swap_used() as f64), format_bytes(info.swap_total as f64), info.swap_fraction() * 100.0));
+        }
+        Some(lines)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        Some(self.sampler.next())
+    }
+
+    fn tick(&mut self, ctx: &mut Ctx, now: Instant) -> Result<Change, Box<dyn std::error::Error>> {
+        if !self.sampler.due(now) { return Ok(Change::None); }
+        let Ok(info) = read_meminfo(&self.root) else { return Ok(Change::None) };
+        let swap = self.show_swap(ctx);
+        self.info = info;
+        self.record();
+        // Swap turned on or off.
+        Ok(if self.show_swap(ctx) != swap { Change::Relayout } else { Change::Redraw })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::widget::test_util::TempDir;
+
+    // A /proc of the test's own, holding just `meminfo` with (field, kB).
+    fn root(test: &str, fields: &[(&str, u64)]) -> TempDir {
+        let root = TempDir::new("memory", test);
+        root.write("meminfo", &fields.iter().map(|(name, kb)| format!("{}:{:>16} kB\n", name, kb)).collect::<String>());
+        root
+    }
+
+    #[test]
+    fn meminfo() {
+        let root = root("meminfo", &[
+            ("MemTotal", 16000), ("MemFree", 2000), ("MemAvailable", 10000), ("Buffers", 500), ("Cached", 6000),
+            ("SwapCached", 100), ("Shmem", 300), ("SReclaimable", 700), ("SwapTotal", 4000), ("SwapFree", 3000),
+        ]);
+        let info = read_meminfo(&root).unwrap();
+        assert_eq!((info.total, info.available, info.free), (16000 * 1024, 10000 * 1024, 2000 * 1024));
+        // SwapCached isn't the page cache.
+        assert_eq!((info.buffers, info.cache, info.shared), (500 * 1024, 6700 * 1024, 300 * 1024));
+        assert_eq!(info.used(), 6000 * 1024);
+        assert_eq!(info.fraction(), 0.375);
+        assert_eq!((info.swap_used(), info.swap_fraction()), (1000 * 1024, 0.25));
+        assert!(read_meminfo(&root.join("nowhere")).is_err());
+    }
+
+    #[test]
+    fn available_without_estimate() {
+        // Before Linux 3.14, and without swap.
+        let old = root("old", &[("MemTotal", 8000), ("MemFree", 1000), ("Buffers", 500), ("Cached", 2000), ("SReclaimable", 500)]);
+        let info = read_meminfo(&old).unwrap();
+        assert_eq!(info.available, 4000 * 1024);
+        assert_eq!(info.fraction(), 0.5);
+        assert_eq!((info.swap_total, info.swap_used(), info.swap_fraction()), (0, 0, 0.0));
+        // An empty file reads as nothing in use rather than dividing by zero.
+        let empty = root("empty", &[]);
+        assert_eq!(read_meminfo(&empty).unwrap().fraction(), 0.0);
+    }
+
+    #[test]
+    fn thresholds() {
+        let cfg = MemoryConfig { warning: 80, critical: 90, ..MemoryConfig::default() };
+        assert!(level(&cfg, 0.79).is_none());
+        assert!(level(&cfg, 0.8) == Some(cfg.warning_color));
+        assert!(level(&cfg, 0.89) == Some(cfg.warning_color));
+        assert!(level(&cfg, 0.9) == Some(cfg.critical_color));
+        assert!(level(&cfg, 1.0) == Some(cfg.critical_color));
+        // Equal thresholds skip the warning.
+        let cfg = MemoryConfig { warning: 90, critical: 90, ..MemoryConfig::default() };
+        assert!(check(&cfg).is_ok());
+        assert!(level(&cfg, 0.9) == Some(cfg.critical_color));
+    }
+
+    #[test]
+    fn rejected_config() {
+        assert!(check(&MemoryConfig::default()).is_ok());
+        assert!(check(&MemoryConfig { warning: 90, critical: 80, ..MemoryConfig::default() }).is_err());
+        for interval in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(check(&MemoryConfig { interval, ..MemoryConfig::default() }).is_err());
+        }
+    }
+}
@@ -0,0 +1,217 @@
+pub mod battery;
+pub mod clock;
+pub mod cpu;
+pub mod graph;
+pub mod memory;
+pub mod network;
+pub mod taskbar;
+#[cfg(test)]
+mod test_util;
+pub mod timer;
+pub mod tray;
+pub mod world_clock;
+
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{ButtonPressEvent, Gcontext, Rectangle, Window};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use crate::config::{Config, Theme};
+use crate::layout::Region;
+use crate::metrics::Metrics;
+use crate::render::Renderer;
+use crate::text::Fonts;
+use crate::{Atoms, VisualInfo};
+
+// What widgets draw with and talk to; borrowed from the app state for the length of one call.
+pub struct Ctx<'a> {
+    pub conn: &'a RustConnection,
+    pub atoms: &'a Atoms,
+    pub screen_num: usize,
+    // The panel window and a GC for it.
+    pub win: Window,
+    pub gc: Gcontext,
+    pub visual: VisualInfo,
+    // Where the panel is when shown, for popups that open above it.
+    pub panel_y: i16,
+    pub metrics: Metrics,
+    pub config: &'a Config,
+    pub theme: &'a Theme,
+    pub fonts: &'a Fonts,
+    pub renderer: &'a mut dyn Renderer,
+    pub render_buffer: &'a mut Vec<u8>,
+}
+
+impl Ctx<'_> {
+    pub fn root(&self) -> Window {
+        self.conn.setup().roots[self.screen_num].root
+    }
+}
+
+// What a widget needs after handling something.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    None,
+    // Render again at the same place.
+    Redraw,
+    // The width changed, so everything is placed again.
+    Relayout,
+}
+
+impl Change {
+    // The bigger of two changes, for handlers that do several things.
+    pub fn max(self, other: Change) -> Change {
+        match (self, other) {
+            (Change::Relayout, _) | (_, Change::Relayout) => Change::Relayout,
+            (Change::Redraw, _) | (_, Change::Redraw) => Change::Redraw,
+            _ => Change::None,
+        }
+    }
+}
+
+// Something that lives in a section of the panel. Coordinates in events are the panel's; `region` says where the
+// widget was placed by the last layout.
+pub trait Widget {
+    // The width the widget wants. Flexible widgets are measured last with what the others left, and may take less.
+    fn measure(&mut self, ctx: &Ctx, available: u16) -> u16;
+
+    fn flexible(&self) -> bool {
+        false
+    }
+
+    // Draws into the back buffer inside `region` and adds what it drew to `damage`. The background under the
+    // region has been restored when `full` is set; otherwise the widget restores whatever it redraws.
+    fn render(&mut self, ctx: &mut Ctx, region: Region, full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>>;
+
+    // Any button but the scroll wheel.
+    fn click(&mut self, _ctx: &mut Ctx, _region: Region, _e: &ButtonPressEvent) -> Result<Change, Box<dyn std::error::Error>> {
+        Ok(Change::None)
+    }
+
+    // Vertical scroll wheel steps: -1 up, 1 down.
+    fn scroll(&mut self, _ctx: &mut Ctx, _region: Region, _e: &ButtonPressEvent, _delta: i32) -> Result<Change, Box<dyn std::error::Error>> {
+        Ok(Change::None)
+    }
+
+    // The pointer moved over the widget, or left it (None).
+    fn hover(&mut self, _ctx: &mut Ctx, _region: Region, _x: Option<i16>) -> Change {
+        Change::None
+    }
+
+    // Lines describing what is at `x`, asked for once the pointer has rested on the widget, and again while shown
+    // whenever the pointer moves or the widget re-renders.
+    fn tooltip(&mut self, _ctx: &Ctx, _region: Region, _x: i16) -> Option<Vec<String>> {
+        None
+    }
+
+    // Every X event the panel receives, for widgets that watch windows or own popups.
+    fn event(&mut self, _ctx: &mut Ctx, _event: &Event) -> Result<Change, Box<dyn std::error::Error>> {
+        Ok(Change::None)
+    }
+
+    // When `tick` should be called next; the event loop sleeps until the earliest one.
+    fn next_tick(&self) -> Option<Instant> {
+        None
+    }
+
+    fn tick(&mut self, _ctx: &mut Ctx, _now: Instant) -> Result<Change, Box<dyn std::error::Error>> {
+        Ok(Change::None)
+    }
+
+    // A descriptor the event loop polls for the widget; `fd_ready` is called when it is readable.
+    fn fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn fd_ready(&mut self, _ctx: &mut Ctx) -> Result<Change, Box<dyn std::error::Error>> {
+        Ok(Change::None)
+    }
+
+    // The scale factor changed; `ctx.metrics` has the new sizes and a relayout follows.
+    fn rescale(&mut self, _ctx: &mut Ctx) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    // Brings an autohidden panel back, e.g. for an urgent window.
+    fn wants_attention(&self) -> bool {
+        false
+    }
+
+    // A popup or menu of the widget is open, so the panel should stay shown.
+    fn has_popup(&self) -> bool {
+        false
+    }
+}
+
+pub fn create(name: &str, ctx: &mut Ctx) -> Result<Box<dyn Widget>, Box<dyn std::error::Error>> {
+    Ok(match name {
+        "battery" => Box::new(battery::Battery::new(ctx)?),
+        "clock" => Box::new(clock::Clock::new(ctx)?),
+        "cpu" => Box::new(cpu::Cpu::new(ctx)?),
+        "memory" => Box::new(memory::Memory::new(ctx)?),
+        "network" => Box::new(network::Network::new(ctx)?),
+        "taskbar" => Box::new(taskbar::Taskbar::new(ctx)?),
+        "timer" => Box::new(timer::Timer::new(ctx)?),
+        "tray" => Box::new(tray::TrayWidget::new(ctx)?),
+        "world_clock" => Box::new(world_clock::WorldClock::new(ctx)?),
+        _ => return Err(format!("unknown widget \"{}\" in [layout]", name).into()),
+    })
+}
+
+// The next wall clock time after `now` that is a whole multiple of `period` since the epoch (in UTC, which is
+// what matters for minutes and seconds).
+pub fn next_boundary(now: SystemTime, period: Duration) -> SystemTime {
+    let since = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let period_ms = period.as_millis().max(1);
+    let next = (since.as_millis() / period_ms + 1) * period_ms;
+    UNIX_EPOCH + Duration::from_millis(next as u64)
+}
+
+// The monotonic instant matching wall clock `at`; already due when the wall clock has passed it (after a suspend,
+// say, during which monotonic time stood still).
+pub fn wall_deadline(at: SystemTime) -> Instant {
+    Instant::now() + at.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+// "512 KiB", "1.5 GiB": binary units, with a decimal below 10.
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes.max(0.0);
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit > 0 && value < 10.0 { format!("{:.1} {}", value, UNITS[unit]) } else { format!("{:.0} {}", value, UNITS[unit]) }
+}
+
+// When a widget that polls something (a sysfs or procfs file, say) reads it next. Due times advance by whole
+// periods from the first, so sampling keeps its rate however late the event loop wakes, and skips what was missed
+// after a suspend instead of catching up.
+pub struct Sampler {
+    period: Duration,
+    next: Instant,
+}
+
+impl Sampler {
+    // The first sample is due a period from now; widgets take one as they are created.
+    pub fn new(period: Duration) -> Self {
+        let period = period.max(Duration::from_millis(100));
+        Self { period, next: Instant::now() + period }
+    }
+
+    pub fn next(&self) -> Instant {
+        self.next
+    }
+
+    // Whether a sample is due at `now`; if so the next one is scheduled.
+    pub fn due(&mut self, now: Instant) -> bool {
+        if now < self.next { return false; }
+        let missed = (now - self.next).as_nanos() / self.period.as_nanos();
+        self.next += self.period * (missed.min(u32::MAX as u128 - 1) as u32 + 1);
+        true
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use x11rb::protocol::xproto::Rectangle;
+
+use super::graph::{self, History};
+use super::{format_bytes, Change, Ctx, Sampler, Widget};
+use crate::config::expand_home;
+use crate::layout::Region;
+use crate::text::calculate_text_width;
+
+// Space between the graph or bars and the text, at 96 DPI.
+const GAP: u16 = 4;
+// The graph and bars never scale below this many bytes per second, so an idle link stays flat.
+const FLOOR: f32 = 1024.0;
+
+// Download and upload rates over the interfaces named, or those with a default route, from the byte counters
+// in /proc/net/dev between two samples; as text and/or a graph of both. The tooltip lists each interface with
+// its link state and addresses.
+pub struct Network {
+    root: PathBuf,
+    sys_root: PathBuf,
+    sampler: Sampler,
+    interfaces: Vec<Interface>,
+    // When the counters were read.
+    taken: Instant,
+    down: History,
+    up: History,
+    width: u16,
+}
+
+struct Interface {
+    name: String,
+    // From operstate: "up", "down", "dormant"..., None when the interface is gone.
+    state: Option<String>,
+    // Bytes received and sent so far.
+    bytes: Option<(u64, u64)>,
+    // Bytes per second received and sent since the sample before.
+    rate: (f64, f64),
+}
+
+impl Interface {
+    // Tunnels and PPP links don't track their state and say "unknown".
+    fn up(&self) -> bool {
+        matches!(self.state.as_deref(), Some("up" | "unknown"))
+    }
+}
+
+impl Network {
+    pub fn new(ctx: &Ctx) -> Result<Self, Box<dyn std::error::Error>> {
+        let cfg = &ctx.config.network;
+        if !cfg.interval.is_finite() || cfg.interval <= 0.0 { return Err("[network] interval must be above 0".into()); }
+        let root = PathBuf::from(expand_home(&cfg.root));
+        let sys_root = PathBuf::from(expand_home(&cfg.sys_root));
+        let sampler = Sampler::new(Duration::from_secs_f64(cfg.interval));
+        let mut network = Self {
+            root, sys_root, sampler, interfaces: Vec::new(), taken: Instant::now(),
+            down: History::new(cfg.history), up: History::new(cfg.history), width: 0,
+        };
+        network.sample(&cfg.interfaces, Instant::now());
+        Ok(network)
+    }
+
+    // The interfaces `configured`, or those detected.
+    fn sample(&mut self, configured: &[String], now: Instant) {
+        let names = if configured.is_empty() { detect(&self.root, &self.sys_root) } else { configured.to_vec() };
+        let counters = read_counters(&self.root, &self.sys_root, &names);
+        let elapsed = now.saturating_duration_since(self.taken).as_secs_f64();
+        let previous: HashMap<&str, (u64, u64)> = self.interfaces.iter().filter_map(|i| Some((i.name.as_str(), i.bytes?))).collect();
+        let interfaces: Vec<Interface> = names.into_iter().map(|name| {
+            let bytes = counters.get(&name).copied();
+            let rate = match (bytes, previous.get(name.as_str())) {
+                (Some((rx, tx)), Some(&(last_rx, last_tx))) if elapsed > 0.0 => {
+                    (delta(rx, last_rx) as f64 / elapsed, delta(tx, last_tx) as f64 / elapsed)
+                }
+                _ => (0.0, 0.0),
+            };
+            let state = std::fs::read_to_string(self.sys_root.join(&name).join("operstate")).ok().map(|s| s.trim().to_string());
+            Interface { name, state, bytes, rate }
+        }).collect();
+        self.interfaces = interfaces;
+        self.taken = now;
+        let (down, up) = self.rates();
+        self.down.push(down as f32);
+        self.up.push(up as f32);
+    }
+
+    // Bytes per second received and sent over all interfaces.
+    fn rates(&self) -> (f64, f64) {
+        self.interfaces.iter().fold((0.0, 0.0), |(d, u), i| (d + i.rate.0, u + i.rate.1))
+    }
+
+    fn online(&self) -> bool {
+        self.interfaces.iter().any(Interface::up)
+    }
+
+    // What the graph and bars are scaled to: the highest rate they show.
+    fn scale(&self) -> f32 {
+        self.down.max().max(self.up.max()).max(FLOOR)
+    }
+
+    // Widths of the graph or bars and of the text, 0 when not shown.
+    fn parts(&self, ctx: &Ctx) -> (u16, u16) {
+        let (cfg, m) = (&ctx.config.network, ctx.metrics);
+        let graph = if cfg.display.graph() || cfg.display.bar() { m.px(cfg.graph_width) } else { 0 };
+        let text = if cfg.display.text() {
+            let widest = rate(1023.0 * 1024.0 * 1024.0).replace(|c: char| c.is_ascii_digit(), "0");
+            calculate_text_width(ctx.fonts, m.font_main, &format!("↓ {}  ↑ {}", widest, widest)) as u16
+        } else {
+            0
+        };
+        (graph, text)
+    }
+}
+
+// Bytes counted since `last`. The counters of 32-bit kernels wrap at 4 GiB; one that went back otherwise was
+// reset, the interface having been brought down and up.
+fn delta(now: u64, last: u64) -> u64 {
+    const WRAP: u64 = 1 << 32;
+    match now.checked_sub(last) {
+        Some(bytes) => bytes,
+        None if last < WRAP && now + WRAP - last < WRAP / 2 => now + WRAP - last,
+        None => 0,
+    }
+}
+
+// "1.2 MiB/s".
+fn rate(bytes: f64) -> String {
+    format!("{}/s", format_bytes(bytes))
+}
+
+// Interfaces with an IPv4 or IPv6 default route, or else every one but loopback that is up.
+fn detect(root: &Path, sys_root: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+    // "eth0	00000000	010200C0	0003 ..." with the destination second.
+    if let Ok(text) = std::fs::read_to_string(root.join("net/route")) {
+        names.extend(text.lines().skip(1).filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            (fields.get(1) == Some(&"00000000")).then(|| fields[0].to_string())
+        }));
+    }
+    // Destination, prefix length, ..., with the interface last.
+    if let Ok(text) = std::fs::read_to_string(root.join("net/ipv6_route")) {
+        names.extend(text.lines().filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let default = fields.len() == 10 && fields[0].bytes().all(|b| b == b'0') && fields[1] == "00";
+            (default && fields[9] != "lo").then(|| fields[9].to_string())
+        }));
+    }
+    if names.is_empty() {
+        if let Ok(dir) = std::fs::read_dir(sys_root) {
+            names.extend(dir.flatten().map(|e| e.file_name().to_string_lossy().into_owned()).filter(|name| {
+                let state = std::fs::read_to_string(sys_root.join(name).join("operstate")).unwrap_or_default();
+                name != "lo" && state.trim() == "up"
+            }));
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+// Bytes received and sent by each of `names`, from /proc/net/dev, or from sysfs without it.
+fn read_counters(root: &Path, sys_root: &Path, names: &[String]) -> HashMap<String, (u64, u64)> {
+    if let Ok(text) = std::fs::read_to_string(root.join("net/dev")) {
+        // "  eth0: 1234 56 0 0 0 0 0 0 7890 12 ..." with 8 receive fields before the transmit ones.
+        return text.lines().skip(2).filter_map(|line| {
+            let (name, rest) = line.split_once(':')?;
+            let name = name.trim();
+            if !names.iter().any(|n| n == name) { return None; }
+            let fields: Vec<u64> = rest.split_whitespace().map(|f| f.parse().unwrap_or(0)).collect();
+            Some((name.to_string(), (*fields.first()?, *fields.get(8)?)))
+        }).collect();
+    }
+    names.iter().filter_map(|name| {
+        let stat = |file: &str| -> Option<u64> {
+            std::fs::read_to_string(sys_root.join(name).join("statistics").join(file)).ok()?.trim().parse().ok()
+        };
+        Some((name.clone(), (stat("rx_bytes")?, stat("tx_bytes")?)))
+    }).collect()
+}
+
+// Every address with its prefix length, by interface name, from getifaddrs(3).
+fn addresses() -> Vec<(String, IpAddr, u32)> {
+    let mut list = Vec::new();
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut head) } != 0 { return list; }
+    let mut entry = head;
+    while !entry.is_null() {
+        let ifa = unsafe { &*entry };
+        entry = ifa.ifa_next;
+        let Some(addr) = ip(ifa.ifa_addr) else { continue };
+        let prefix = match ip(ifa.ifa_netmask) {
+            Some(IpAddr::V4(mask)) => u32::from(mask).count_ones(),
+            Some(IpAddr::V6(mask)) => u128::from(mask).count_ones(),
+            None => if addr.is_ipv4() { 32 } else { 128 },
+        };
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }.to_string_lossy().into_owned();
+        list.push((name, addr, prefix));
+    }
+    unsafe { libc::freeifaddrs(head) };
+    list
+}
+
+fn ip(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    if addr.is_null() { return None; }
+    match unsafe { (*addr).sa_family } as i32 {
+        libc::AF_INET => {
+            let sin = unsafe { &*(addr as *const libc::sockaddr_in) };
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr))))
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(addr as *const libc::sockaddr_in6) };
+            Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+        }
+        _ => None,
+    }
+}
+
+impl Widget for Network {
+    fn measure(&mut self, ctx: &Ctx, _available: u16) -> u16 {
+        let m = ctx.metrics;
+        let (graph, text) = self.parts(ctx);
+        let gap = if graph > 0 && text > 0 { m.px(GAP) } else { 0 };
+        self.width = graph + gap + text + m.px(8);
+        self.width
+    }
+
+    fn render(&mut self, ctx: &mut Ctx, region: Region, _full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>> {
+        let (colors, m) = (ctx.config.colors, ctx.metrics);
+        let cfg = &ctx.config.network;
+        let display = cfg.display;
+        let down_color = cfg.download_color.unwrap_or(colors.underline);
+        let up_color = cfg.upload_color.unwrap_or(colors.date);
+        let backdrop = cfg.graph_background;
+        ctx.renderer.fill_background(ctx.conn, region.rect(m.panel_height), colors.background)?;
+        let (graph_w, _) = self.parts(ctx);
+        let (down, up) = self.rates();
+        let scale = self.scale();
+
+        let mut x = region.x + m.px(4) as i16;
+        if graph_w > 0 {
+            let pad = m.px(8);
+            let rect = Rectangle { x, y: pad as i16, width: graph_w, height: m.panel_height.saturating_sub(2 * pad) };
+            if let Some(bg) = backdrop { ctx.renderer.fill_rect(ctx.conn, rect, bg)?; }
+            if display.bar() {
+                // Download above upload.
+                let half = (rect.height - m.px(2)) / 2;
+                graph::meter(ctx, Rectangle { height: half, ..rect }, down as f32 / scale, down_color)?;
+                let lower = Rectangle { y: rect.y + (rect.height - half) as i16, height: half, ..rect };
+                graph::meter(ctx, lower, up as f32 / scale, up_color)?;
+            } else {
+                graph::line(ctx, rect, &self.up, scale, up_color)?;
+                graph::line(ctx, rect, &self.down, scale, down_color)?;
+            }
+            x += (graph_w + m.px(GAP)) as i16;
+        }
+        if display.text() {
+            if self.online() {
+                for (arrow, value, color) in [("↓ ", down, down_color), ("  ↑ ", up, up_color)] {
+                    ctx.renderer.draw_text(ctx.conn, ctx.fonts, arrow, m.font_main, x, m.text_y, color)?;
+                    x += calculate_text_width(ctx.fonts, m.font_main, arrow) as i16;
+                    let value = rate(value);
+                    ctx.renderer.draw_text(ctx.conn, ctx.fonts, &value, m.font_main, x, m.text_y, colors.text)?;
+                    x += calculate_text_width(ctx.fonts, m.font_main, &value) as i16;
+                }
+            } else {
+                ctx.renderer.draw_text(ctx.conn, ctx.fonts, "offline", m.font_main, x, m.text_y, colors.date)?;
+            }
+        }
+        damage.push(region.rect(m.panel_height));
+        Ok(())
+    }
+
+    // Each interface with its state, rates and addresses.
+    fn tooltip(&mut self, _ctx: &Ctx, _region: Region, _x: i16) -> Option<Vec<String>> {
+        if self.interfaces.is_empty() { return Some(vec!["No network interface".to_string()]); }
+        let addresses = addresses();
+        let mut lines = Vec::new();
+        for interface in &self.interfaces {
+            let state = interface.state.as_deref().unwrap_or("absent");
+            let mut line = format!("{}  {}", interface.name, state);
+            if interface.up() { line += &format!("  ↓ {}  ↑ {}", rate(interface.rate.0), rate(interface.rate.1)); }
+            lines.push(line);
+            for (_, addr, prefix) in addresses.iter().filter(|(name, _, _)| *name == interface.name) {
+                lines.push(format!("  {}/{}", addr, prefix));
+            }
+            if let Some((rx, tx)) = interface.bytes {
+                lines.push(format!("  Received {}  Sent {}", format_bytes(rx as f64), format_bytes(tx as f64)));
+            }
+        }
+        Some(lines)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        Some(self.sampler.next())
+    }
+
+    fn tick(&mut self, ctx: &mut Ctx, now: Instant) -> Result<Change, Box<dyn std::error::Error>> {
+        if !self.sampler.due(now) { return Ok(Change::None); }
+        self.sample(&ctx.config.network.interfaces, now);
+        Ok(Change::Redraw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::widget::test_util::TempDir;
+
+    // A /proc and a /sys/class/net of the test's own.
+    fn roots(test: &str) -> (TempDir, TempDir) {
+        (TempDir::new("network", &format!("{}-proc", test)), TempDir::new("network", &format!("{}-net", test)))
+    }
+
+    fn interface(sys_root: &TempDir, name: &str, state: &str) {
+        sys_root.write(&format!("{}/operstate", name), &format!("{}\n", state));
+    }
+
+    // /proc/net/dev with (name, received, sent).
+    fn net_dev(root: &TempDir, counters: &[(&str, u64, u64)]) {
+        let mut text = "Inter-|   Receive                                                |  Transmit\n".to_string();
+        text += " face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n";
+        for (name, rx, tx) in counters {
+            text += &format!("{:>6}: {} 10 0 0 0 0 0 0 {} 20 0 0 0 0 0 0\n", name, rx, tx);
+        }
+        root.write("net/dev", &text);
+    }
+
+    fn network(root: PathBuf, sys_root: PathBuf) -> Network {
+        Network {
+            root, sys_root, sampler: Sampler::new(Duration::from_secs(1)), interfaces: Vec::new(), taken: Instant::now(),
+            down: History::new(4), up: History::new(4), width: 0,
+        }
+    }
+
+    #[test]
+    fn default_routes() {
+        let (root, sys_root) = roots("routes");
+        for (name, state) in [("lo", "unknown"), ("eth0", "up"), ("wlan0", "up"), ("wg0", "unknown")] { interface(&sys_root, name, state); }
+        root.write("net/route", concat!(
+            "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n",
+            "wlan0\t00000000\t0102A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n",
+            "wlan0\t0002A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0\n",
+            "eth0\t0000000A\t00000000\t0001\t0\t0\t100\t000000FF\t0\t0\t0\n",
+        ));
+        root.write("net/ipv6_route", concat!(
+            "fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001    wlan0\n",
+            "00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003      wg0\n",
+            "00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo\n",
+        ));
+        assert_eq!(detect(&root, &sys_root), ["wg0", "wlan0"]);
+        // Without a default route, whatever is up but loopback.
+        root.write("net/route", "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n");
+        std::fs::remove_file(root.join("net/ipv6_route")).unwrap();
+        interface(&sys_root, "wlan0", "dormant");
+        assert_eq!(detect(&root, &sys_root), ["eth0"]);
+    }
+
+    #[test]
+    fn counters() {
+        let (root, sys_root) = roots("counters");
+        net_dev(&root, &[("lo", 5, 5), ("eth0", 1000, 2000), ("wlan0", 3000, 4000)]);
+        let names = ["eth0".to_string(), "wlan1".to_string()];
+        assert_eq!(read_counters(&root, &sys_root, &names), HashMap::from([("eth0".to_string(), (1000, 2000))]));
+        // From sysfs without /proc.
+        std::fs::remove_file(root.join("net/dev")).unwrap();
+        sys_root.write("eth0/statistics/rx_bytes", "1500\n");
+        sys_root.write("eth0/statistics/tx_bytes", "2500\n");
+        assert_eq!(read_counters(&root, &sys_root, &names), HashMap::from([("eth0".to_string(), (1500, 2500))]));
+    }
+
+    #[test]
+    fn wraparound() {
+        assert_eq!(delta(1500, 1000), 500);
+        // A 32-bit counter passing 4 GiB.
+        assert_eq!(delta(100, u32::MAX as u64 - 99), 200);
+        // A reset, not a wrap.
+        assert_eq!(delta(100, 1_000_000_000), 0);
+        assert_eq!(delta(100, 10_000_000_000), 0);
+    }
+
+    #[test]
+    fn rates_and_state() {
+        let (root, sys_root) = roots("rates");
+        interface(&sys_root, "eth0", "up");
+        interface(&sys_root, "tun0", "unknown");
+        interface(&sys_root, "wlan0", "down");
+        let names = ["eth0".to_string(), "tun0".to_string(), "wlan0".to_string(), "usb0".to_string()];
+        net_dev(&root, &[("eth0", 1000, 2000), ("tun0", u32::MAX as u64 - 999, 0), ("wlan0", 0, 0)]);
+        let mut network = network(root.to_path_buf(), sys_root.to_path_buf());
+        let start = Instant::now();
+        network.sample(&names, start);
+        assert_eq!(network.rates(), (0.0, 0.0));
+        net_dev(&root, &[("eth0", 5000, 3000), ("tun0", 1000, 0), ("wlan0", 0, 0)]);
+        network.sample(&names, start + Duration::from_secs(2));
+        let rates: Vec<(f64, f64)> = network.interfaces.iter().map(|i| i.rate).collect();
+        assert_eq!(rates, [(2000.0, 500.0), (1000.0, 0.0), (0.0, 0.0), (0.0, 0.0)]);
+        assert_eq!(network.rates(), (3000.0, 500.0));
+        assert_eq!(network.down.max(), 3000.0);
+        let up: Vec<bool> = network.interfaces.iter().map(Interface::up).collect();
+        assert_eq!(up, [true, true, false, false]);
+        assert_eq!(network.interfaces[3].state, None);
+        assert!(network.online());
+        // Everything down.
+        interface(&sys_root, "eth0", "down");
+        interface(&sys_root, "tun0", "down");
+        network.sample(&names, start + Duration::from_secs(4));
+        assert!(!network.online());
+    }
+
+    #[test]
+    fn loopback_address() {
+        assert!(addresses().iter().any(|(_, addr, prefix)| addr.is_loopback() && (*prefix == 8 || *prefix == 128)));
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::hash_map::{Entry, HashMap};
+
+use image::{imageops::FilterType, ImageBuffer, RgbaImage};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::*;
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::CURRENT_TIME;
+
+use super::{Change, Ctx, Widget};
+use crate::color::Fill;
+use crate::config::{ButtonState, Indicator};
+use crate::layout::Region;
+use crate::render::IconKey;
+use crate::text::{calculate_text_width, shorten_text_to_fit};
+use crate::Atoms;
+
+struct CachedWindowData {
+    title: String,
+    icon_buffer: Option<Vec<u8>>,
+    icon_width: u16,
+    icon_height: u16,
+    // From _NET_WM_STATE and the WM_HINTS urgency flag.
+    urgent: bool,
+    minimized: bool,
+}
+
+// `region.x` is relative to the taskbar.
+#[derive(Clone, Copy)]
+struct TaskButton {
+    win: Window,
+    region: Region,
+}
+
+// A button per client window, squeezed to equal widths when they don't all fit.
+pub struct Taskbar {
+    window_cache: HashMap<Window, CachedWindowData>,
+    // Kept up to date from PropertyNotify on the root window instead of being refetched on every redraw
+    clients: Vec<Window>,
+    active_window: Window,
+    hovered_window: Option<Window>,
+    buttons: Vec<TaskButton>,
+    // Titles are shortened whenever the buttons had to be squeezed to fit.
+    compressed: bool,
+}
+
+impl Taskbar {
+    pub fn new(ctx: &mut Ctx) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut taskbar = Self {
+            window_cache: HashMap::new(),
+            clients: Vec::with_capacity(64),
+            active_window: x11rb::NONE,
+            hovered_window: None,
+            buttons: Vec::new(),
+            compressed: false,
+        };
+        taskbar.update_clients(ctx)?;
+        taskbar.update_active_window(ctx)?;
+        Ok(taskbar)
+    }
+
+    fn button_at(&self, x: i16) -> Option<Window> {
+        self.buttons.iter().find(|b| b.region.contains(x)).map(|b| b.win)
+    }
+
+    fn mark_button(&mut self, win: Option<Window>) {
+        if let Some(button) = self.buttons.iter_mut().find(|b| Some(b.win) == win) {
+            button.region.dirty = true;
+        }
+    }
+
+    // Re-reads the client list and starts caching windows that are new in it.
+    fn update_clients(&mut self, ctx: &Ctx) -> Result<(), Box<dyn std::error::Error>> {
+        let (conn, atoms, root) = (ctx.conn, ctx.atoms, ctx.root());
+        self.clients.clear();
+        if let Ok(reply) = conn.get_property(false, root, atoms._net_client_list, AtomEnum::ANY, 0, 1024)?.reply() {
+            if let Some(list) = reply.value32() {
+                self.clients.extend(list);
+            }
+        }
+        // Fallback for windows without _NET_CLIENT_LIST
+        if self.clients.is_empty() {
+            if let Ok(tree) = conn.query_tree(root)?.reply() {
+                for w in tree.children {
+                     let state = conn.get_property(false, w, atoms.wm_state, AtomEnum::ANY, 0, 1)?.reply();
+                     if let Ok(r) = state { if r.value_len > 0 { self.clients.push(w); } }
+                }
+            }
+        }
+
+        for &w in &self.clients {
+            if let Entry::Vacant(entry) = self.window_cache.entry(w) {
+                let type_cookie = conn.get_property(false, w, atoms._net_wm_window_type, AtomEnum::ATOM, 0, 1024).ok();
+                let mut is_dock = false;
+                if let Some(cookie) = type_cookie {
+                    if let Ok(reply) = cookie.reply() {
+                        if let Some(mut atoms_iter) = reply.value32() {
+                            if atoms_iter.any(|a|
+                                a == atoms._net_wm_window_type_dock ||
+                                a == atoms._net_wm_window_type_desktop ||
+                                a == atoms._net_wm_window_type_splash
+                            ) { is_dock = true; }
+                        }
+                    }
+                }
+                if !is_dock {
+                    conn.change_window_attributes(w, &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY)).ok();
+                    entry.insert(fetch_window_data(conn, atoms, w, ctx.metrics.icon_size));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Moves the active highlight; button widths don't depend on it, so no relayout.
+    fn update_active_window(&mut self, ctx: &Ctx) -> Result<Change, Box<dyn std::error::Error>> {
+        let active = ctx.conn.get_property(false, ctx.root(), ctx.atoms._net_active_window, AtomEnum::ANY, 0, 1)?.reply().ok()
+            .and_then(|r| r.value32().and_then(|mut i| i.next()))
+            .unwrap_or(0);
+        if active == self.active_window { return Ok(Change::None); }
+        self.mark_button(Some(self.active_window));
+        self.mark_button(Some(active));
+        self.active_window = active;
+        Ok(Change::Redraw)
+    }
+
+    fn draw_button(&mut self, ctx: &mut Ctx, origin: i16, index: usize) -> Result<Rectangle, Box<dyn std::error::Error>> {
+        let (colors, m) = (ctx.config.colors, ctx.metrics);
+        let TaskButton { win, region } = self.buttons[index];
+        let current_x = origin + region.x;
+        self.buttons[index].region.dirty = false;
+        let rect = Rectangle { x: current_x, y: 0, width: region.width, height: m.panel_height };
+        ctx.renderer.fill_background(ctx.conn, rect, colors.background)?;
+
+        let Some(data) = self.window_cache.get(&win) else { return Ok(rect) };
+        let state = if win == self.active_window {
+            ButtonState::Active
+        } else if data.urgent {
+            ButtonState::Urgent
+        } else if Some(win) == self.hovered_window {
+            ButtonState::Hover
+        } else if data.minimized {
+            ButtonState::Minimized
+        } else {
+            ButtonState::Normal
+        };
+        let style = *ctx.theme.style(state);
+        let (radius, padding) = (m.px(style.radius), m.px(style.padding));
+
+        let inset = m.px(2);
+        let frame = Rectangle { x: current_x, y: inset as i16, width: region.width, height: m.panel_height.saturating_sub(2 * inset) };
+        if let Some(background) = style.background {
+            ctx.renderer.fill_rounded_rect(ctx.conn, frame, radius, background)?;
+        }
+        if style.border_width > 0 {
+            ctx.renderer.stroke_rounded_rect(ctx.conn, frame, radius, m.px(style.border_width), style.border_color)?;
+        }
+        // Bars along an edge stop short of rounded corners.
+        let size = m.px(style.indicator_size);
+        let along = radius.min(frame.width / 2);
+        let indicator = match style.indicator {
+            Indicator::None => None,
+            Indicator::Top => Some(Rectangle { x: frame.x + along as i16, y: frame.y, width: frame.width - 2 * along, height: size }),
+            Indicator::Bottom => Some(Rectangle {
+                x: frame.x + along as i16, y: frame.y + (frame.height - size.min(frame.height)) as i16,
+                width: frame.width - 2 * along, height: size,
+            }),
+            Indicator::Left => {
+                let along = radius.min(frame.height / 2);
+                Some(Rectangle { x: frame.x, y: frame.y + along as i16, width: size, height: frame.height - 2 * along })
+            }
+        };
+        if let Some(rect) = indicator {
+            ctx.renderer.fill_rounded_rect(ctx.conn, rect, 0, Fill::Solid(style.indicator_color))?;
+        }
+
+        let icon_x = padding as i16;
+        if region.width as i32 >= m.icon_size as i32 + padding as i32 {
+             if let Some(ref pixels) = data.icon_buffer {
+                 ctx.renderer.draw_icon(ctx.conn, &IconKey::Window(win), pixels, data.icon_width, data.icon_height, current_x + icon_x, m.icon_y)?;
+             }
+        }
+
+        // Wide paddings can leave no room at all, so this is worked out in i32 and the text skipped when too narrow.
+        let text_area_w = (region.width as i32).saturating_sub(2 * padding as i32 + m.icon_size as i32 + m.px(6) as i32);
+        if text_area_w > m.px(10) as i32 {
+            let display_text = if self.compressed || calculate_text_width(ctx.fonts, m.font_main, &data.title) > text_area_w as f32 {
+                shorten_text_to_fit(ctx.fonts, m.font_main, &data.title, text_area_w as f32)
+            } else {
+                data.title.clone()
+            };
+
+            let text_x = current_x + (padding as i32 + m.icon_size as i32 + m.px(8) as i32) as i16;
+            ctx.renderer.draw_text(ctx.conn, ctx.fonts, &display_text, m.font_main, text_x, m.text_y, style.text)?;
+        }
+        Ok(rect)
+    }
+}
+
+impl Widget for Taskbar {
+    // Lays the buttons out from the cached client list and window data; no round trips.
+    fn measure(&mut self, ctx: &Ctx, available: u16) -> u16 {
+        let m = ctx.metrics;
+        let available_width_for_windows = available as f32;
+
+        let mut visible_windows: Vec<(Window, f32)> = Vec::new();
+        let mut total_ideal_width: f32 = 0.0;
+
+        for &w in &self.clients {
+            if w == ctx.win { continue; }
+
+            if let Some(data) = self.window_cache.get(&w) {
+                let class_str = data.title.to_lowercase();
+                if class_str.contains("conky") || class_str.contains("tint2") || class_str.contains("plank") { continue; }
+
+                let text_width = calculate_text_width(ctx.fonts, m.font_main, &data.title);
+                let max_window_width = m.px(250) as f32;
+                let padding = m.px(ctx.theme.style(ButtonState::Normal).padding);
+                let calc_width = (2.0 * padding as f32 + (m.icon_size + m.px(12)) as f32 + text_width).min(max_window_width);
+
+                total_ideal_width += calc_width;
+                visible_windows.push((w, calc_width));
+            }
+        }
+
+        let use_compression = total_ideal_width > available_width_for_windows;
+        let fixed_width_per_window = if use_compression {
+            (available_width_for_windows / visible_windows.len() as f32).floor()
+        } else { 0.0 };
+
+        let mut current_x: i16 = 0;
+        self.buttons.clear();
+        for (win, ideal_width) in visible_windows {
+            let actual_width = if use_compression { fixed_width_per_window as i16 } else { ideal_width as i16 };
+            self.buttons.push(TaskButton { win, region: Region { x: current_x, width: actual_width as u16, dirty: true } });
+            current_x += actual_width;
+        }
+        self.compressed = use_compression;
+        current_x.max(0) as u16
+    }
+
+    fn flexible(&self) -> bool {
+        true
+    }
+
+    fn render(&mut self, ctx: &mut Ctx, region: Region, full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>> {
+        for i in 0..self.buttons.len() {
+            if full || self.buttons[i].region.dirty {
+                damage.push(self.draw_button(ctx, region.x, i)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn click(&mut self, ctx: &mut Ctx, region: Region, e: &ButtonPressEvent) -> Result<Change, Box<dyn std::error::Error>> {
+        let Some(win) = self.button_at(e.event_x - region.x) else { return Ok(Change::None) };
+        let root = ctx.root();
+        if e.detail == 1 {
+            let event = ClientMessageEvent {
+                response_type: CLIENT_MESSAGE_EVENT, format: 32, sequence: 0, window: win,
+                type_: ctx.atoms._net_active_window, data: [2, CURRENT_TIME, 0, 0, 0].into(),
+            };
+            ctx.conn.send_event(false, root, EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY, event)?;
+            ctx.conn.set_input_focus(InputFocus::POINTER_ROOT, win, CURRENT_TIME)?;
+        } else if e.detail == 3 {
+            let event = ClientMessageEvent {
+                response_type: CLIENT_MESSAGE_EVENT, format: 32, sequence: 0, window: win,
+                type_: ctx.atoms._net_close_window, data: [CURRENT_TIME, 2, 0, 0, 0].into(),
+            };
+            ctx.conn.send_event(false, root, EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY, event)?;
+        }
+        ctx.conn.flush()?;
+        Ok(Change::None)
+    }
+
+    // Only the buttons losing and gaining the hover highlight are re-rendered
+    fn hover(&mut self, _ctx: &mut Ctx, region: Region, x: Option<i16>) -> Change {
+        let hovered = x.and_then(|x| self.button_at(x - region.x));
+        if hovered == self.hovered_window { return Change::None; }
+        self.mark_button(self.hovered_window);
+        self.mark_button(hovered);
+        self.hovered_window = hovered;
+        Change::Redraw
+    }
+
+    fn event(&mut self, ctx: &mut Ctx, event: &Event) -> Result<Change, Box<dyn std::error::Error>> {
+        let (conn, atoms) = (ctx.conn, ctx.atoms);
+        match event {
+            Event::PropertyNotify(e) if e.window == ctx.root() => {
+                if e.atom == atoms._net_client_list {
+                    self.update_clients(ctx)?;
+                    return Ok(Change::Relayout);
+                } else if e.atom == atoms._net_active_window {
+                    return self.update_active_window(ctx);
+                }
+            }
+            Event::PropertyNotify(e) if self.window_cache.contains_key(&e.window) => {
+                if e.atom == atoms._net_wm_name || e.atom == AtomEnum::WM_NAME.into() {
+                    let data = fetch_window_data(conn, atoms, e.window, ctx.metrics.icon_size);
+                    self.window_cache.insert(e.window, data);
+                    // The title decides the button width.
+                    return Ok(Change::Relayout);
+                } else if e.atom == atoms._net_wm_icon {
+                    let data = fetch_window_data(conn, atoms, e.window, ctx.metrics.icon_size);
+                    self.window_cache.insert(e.window, data);
+                    ctx.renderer.forget_icon(conn, &IconKey::Window(e.window))?;
+                    self.mark_button(Some(e.window));
+                    return Ok(Change::Redraw);
+                } else if e.atom == atoms._net_wm_state || e.atom == AtomEnum::WM_HINTS.into() {
+                    let (urgent, minimized) = fetch_window_state(conn, atoms, e.window);
+                    if let Some(data) = self.window_cache.get_mut(&e.window) {
+                        if (data.urgent, data.minimized) != (urgent, minimized) {
+                            data.urgent = urgent;
+                            data.minimized = minimized;
+                            self.mark_button(Some(e.window));
+                            return Ok(Change::Redraw);
+                        }
+                    }
+                }
+            }
+            Event::DestroyNotify(e) if self.window_cache.remove(&e.window).is_some() => {
+                ctx.renderer.forget_icon(conn, &IconKey::Window(e.window))?;
+                return Ok(Change::Relayout);
+            }
+            _ => {}
+        }
+        Ok(Change::None)
+    }
+
+    // Icons are fetched again at the new size.
+    fn rescale(&mut self, ctx: &mut Ctx) -> Result<(), Box<dyn std::error::Error>> {
+        let windows: Vec<Window> = self.window_cache.keys().copied().collect();
+        for win in windows {
+            ctx.renderer.forget_icon(ctx.conn, &IconKey::Window(win))?;
+            let data = fetch_window_data(ctx.conn, ctx.atoms, win, ctx.metrics.icon_size);
+            self.window_cache.insert(win, data);
+        }
+        Ok(())
+    }
+
+    fn wants_attention(&self) -> bool {
+        self.clients.iter().any(|w| self.window_cache.get(w).is_some_and(|d| d.urgent))
+    }
+}
+
+// The icon is scaled to `icon_size` from the smallest _NET_WM_ICON image at least that big, or else the biggest.
+fn fetch_window_data(conn: &RustConnection, atoms: &Atoms, win: Window, icon_size: u16) -> CachedWindowData {
+    let utf_cookie = conn.get_property(false, win, atoms._net_wm_name, atoms.utf8_string, 0, 1024).ok();
+    let str_cookie = conn.get_property(false, win, AtomEnum::WM_NAME, AtomEnum::STRING, 0, 1024).ok();
+
+    let mut title = String::new();
+    if let Some(cookie) = utf_cookie {
+        if let Ok(r) = cookie.reply() {
+            if r.value_len > 0 { title = String::from_utf8_lossy(&r.value).to_string(); }
+        }
+    }
+    if title.is_empty() {
+        if let Some(cookie) = str_cookie {
+            if let Ok(r) = cookie.reply() { title = String::from_utf8_lossy(&r.value).to_string(); }
+        }
+    }
+
+    // Bidi marks and isolates are kept: the shaper needs them to order mixed-direction titles.
+    let sanitized_title: String = title.chars().filter(|c| !c.is_control()).collect();
+
+    let mut icon_buffer = None;
+    let mut icon_w = 0;
+    let mut icon_h = 0;
+
+    if let Ok(cookie) = conn.get_property(false, win, atoms._net_wm_icon, atoms.cardinal, 0, 100000) {
+        if let Ok(reply) = cookie.reply() {
+            if reply.value_len > 0 {
+                if let Some(data_iter) = reply.value32() {
+                    let data: Vec<u32> = data_iter.collect();
+                    let mut best_start = 0;
+                    let mut best_w = 0;
+                    let mut max_w = 0;
+                    let mut max_start = 0;
+                    let mut idx = 0;
+                    while idx + 2 < data.len() {
+                        let w = data[idx] as usize;
+                        let h = data[idx+1] as usize;
+                        let size = w * h;
+                        if idx + 2 + size > data.len() { break; }
+                        if w > max_w { max_w = w; max_start = idx + 2; }
+                        if w >= icon_size as usize && (best_w == 0 || w < best_w) { best_w = w; best_start = idx + 2; }
+                        idx += 2 + size;
+                    }
+                    if best_w == 0 && max_w > 0 { best_w = max_w; best_start = max_start; }
+
+                    if best_w > 0 {
+                        let src_w = best_w;
+                        let src_h = data[best_start - 1] as usize;
+                        let pixels = &data[best_start..best_start + (src_w * src_h)];
+                        let mut img_buf: RgbaImage = ImageBuffer::new(src_w as u32, src_h as u32);
+                        for (i, &px) in pixels.iter().enumerate() {
+                            let x_px = (i % src_w) as u32;
+                            let y_px = (i / src_w) as u32;
+                            img_buf.put_pixel(x_px, y_px, image::Rgba([
+                                ((px >> 16) & 0xFF) as u8,
+                                ((px >> 8) & 0xFF) as u8,
+                                (px & 0xFF) as u8,
+                                ((px >> 24) & 0xFF) as u8,
+                            ]));
+                        }
+                        let resized = image::imageops::resize(&img_buf, icon_size as u32, icon_size as u32, FilterType::Lanczos3);
+                        icon_buffer = Some(resized.into_raw());
+                        icon_w = icon_size;
+                        icon_h = icon_size;
+                    }
+                }
+            }
+        }
+    }
+
+    let (urgent, minimized) = fetch_window_state(conn, atoms, win);
+    CachedWindowData {
+        title: sanitized_title,
+        icon_buffer,
+        icon_width: icon_w,
+        icon_height: icon_h,
+        urgent,
+        minimized,
+    }
+}
+
+// Whether the window demands attention and whether it is minimized.
+fn fetch_window_state(conn: &RustConnection, atoms: &Atoms, win: Window) -> (bool, bool) {
+    let state_cookie = conn.get_property(false, win, atoms._net_wm_state, AtomEnum::ATOM, 0, 64).ok();
+    let hints_cookie = conn.get_property(false, win, AtomEnum::WM_HINTS, AtomEnum::WM_HINTS, 0, 9).ok();
+
+    let states: Vec<Atom> = state_cookie.and_then(|c| c.reply().ok())
+        .and_then(|r| r.value32().map(|i| i.collect()))
+        .unwrap_or_default();
+    // The first WM_HINTS field holds the flags; UrgencyHint is bit 8.
+    let urgency_hint = hints_cookie.and_then(|c| c.reply().ok())
+        .and_then(|r| r.value32().and_then(|mut i| i.next()))
+        .is_some_and(|flags| flags & (1 << 8) != 0);
+    (urgency_hint || states.contains(&atoms._net_wm_state_demands_attention), states.contains(&atoms._net_wm_state_hidden))
+}
+
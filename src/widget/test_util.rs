@@ -0,0 +1,37 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+// A directory of one test's own under the system temp dir, standing in for /proc or /sys; removed with
+// everything in it when dropped, also when the test fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    // Named after the widget and the test, so tests running in parallel never share one.
+    pub fn new(widget: &str, test: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("psa-xpanel-{}-{}-{}", widget, std::process::id(), test));
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    // Writes `text` to `name`, a path relative to the directory, creating what lies in between.
+    pub fn write(&self, name: &str, text: &str) {
+        let path = self.0.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
@@ -0,0 +1,235 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{NaiveTime, Utc};
+use x11rb::protocol::xproto::{ButtonPressEvent, Rectangle};
+
+use super::{wall_deadline, Change, Ctx, Widget};
+use crate::command;
+use crate::config::TimerMode;
+use crate::layout::Region;
+use crate::text::calculate_text_width;
+use crate::tz::{self, Zone};
+
+// The flashing alternates this often.
+const BLINK: Duration = Duration::from_millis(500);
+// Longest countdown scrolling sets.
+const MAX_COUNTDOWN: Duration = Duration::from_secs(24 * 3600);
+
+// A countdown set with the scroll wheel, or a stopwatch, started and paused by clicking; plus daily alarms. The
+// end of a countdown and alarms flash the widget and run the configured command. Deadlines are monotonic
+// instants the event loop sleeps until, so nothing drifts with how often it wakes.
+pub struct Timer {
+    mode: TimerMode,
+    state: State,
+    // When the widget started flashing, and for what.
+    flashing: Option<(Instant, String)>,
+    alarms: Vec<(NaiveTime, String)>,
+    zone: Option<Zone>,
+    // The next alarm's wall clock time and index.
+    next_alarm: Option<(SystemTime, usize)>,
+    width: u16,
+}
+
+enum State {
+    // Not running, showing what the countdown has left or the stopwatch has counted.
+    Stopped(Duration),
+    // The countdown's end, or when the stopwatch would have started to show what it shows.
+    Running(Instant),
+}
+
+impl Timer {
+    pub fn new(ctx: &Ctx) -> Result<Self, Box<dyn std::error::Error>> {
+        let cfg = &ctx.config.timer;
+        if cfg.step == 0 { return Err("[timer] step must be at least 1".into()); }
+        let alarms = cfg.alarms.iter()
+            .map(|a| {
+                let time = NaiveTime::parse_from_str(&a.time, "%H:%M").map_err(|_| format!("[timer] alarm time \"{}\" isn't HH:MM", a.time))?;
+                Ok((time, a.label.clone()))
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        let zone = match &ctx.config.clock.timezone {
+            Some(name) => Some(Zone::load(name)?),
+            None => Zone::local(),
+        };
+        let mut timer = Self { mode: cfg.mode, state: State::Stopped(Duration::ZERO), flashing: None, alarms, zone, next_alarm: None, width: 0 };
+        timer.schedule_alarm();
+        Ok(timer)
+    }
+
+    // The time shown: left on the countdown, rounded up, or counted by the stopwatch.
+    fn value(&self, now: Instant) -> Duration {
+        match (&self.state, self.mode) {
+            (State::Stopped(d), _) => *d,
+            (State::Running(end), TimerMode::Timer) => end.saturating_duration_since(now),
+            (State::Running(start), TimerMode::Stopwatch) => now.saturating_duration_since(*start),
+        }
+    }
+
+    fn text(&self, now: Instant) -> String {
+        let value = self.value(now);
+        let secs = match self.mode {
+            TimerMode::Timer => value.as_secs() + (value.subsec_nanos() > 0) as u64,
+            TimerMode::Stopwatch => value.as_secs(),
+        };
+        let icon = if self.mode == TimerMode::Timer { '⏲' } else { '⏱' };
+        match secs {
+            s if s >= 3600 => format!("{} {}:{:02}:{:02}", icon, s / 3600, s / 60 % 60, s % 60),
+            s => format!("{} {}:{:02}", icon, s / 60, s % 60),
+        }
+    }
+
+    // Measured with every digit as 0, so that the width only changes with the number of digits.
+    fn text_width(&self, ctx: &Ctx) -> u16 {
+        let text: String = self.text(Instant::now()).chars().map(|c| if c.is_ascii_digit() { '0' } else { c }).collect();
+        calculate_text_width(ctx.fonts, ctx.metrics.font_main, &text) as u16 + ctx.metrics.px(8)
+    }
+
+    fn resized(&mut self, ctx: &Ctx) -> Change {
+        let width = self.text_width(ctx);
+        if width == self.width { return Change::Redraw; }
+        self.width = width;
+        Change::Relayout
+    }
+
+    fn schedule_alarm(&mut self) {
+        let now = tz::localize(self.zone.as_ref(), Utc::now()).naive_local();
+        self.next_alarm = self.alarms.iter().enumerate().map(|(i, (time, _))| {
+            let today = now.date().and_time(*time);
+            let local = if today > now { today } else { today + chrono::Duration::days(1) };
+            let at = tz::resolve(self.zone.as_ref(), local);
+            (SystemTime::from(at), i)
+        }).min();
+    }
+
+    fn go_off(&mut self, ctx: &Ctx, now: Instant, label: String) {
+        if let Some(cmd) = &ctx.config.timer.command { command::spawn(cmd, &[&label]); }
+        self.flashing = Some((now, label));
+    }
+
+    fn running(&self) -> bool {
+        matches!(self.state, State::Running(_))
+    }
+}
+
+impl Widget for Timer {
+    fn measure(&mut self, ctx: &Ctx, _available: u16) -> u16 {
+        self.width = self.text_width(ctx);
+        self.width
+    }
+
+    fn render(&mut self, ctx: &mut Ctx, region: Region, _full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>> {
+        let (colors, m) = (ctx.config.colors, ctx.metrics);
+        let now = Instant::now();
+        let rect = region.rect(m.panel_height);
+        ctx.renderer.fill_background(ctx.conn, rect, colors.background)?;
+        // Every other half second while flashing the widget is filled with the underline color.
+        let lit = self.flashing.as_ref().is_some_and(|(since, _)| ((now - *since).as_millis() / BLINK.as_millis()).is_multiple_of(2));
+        if lit { ctx.renderer.fill_rect(ctx.conn, rect, colors.underline)?; }
+        let idle = !self.running() && self.value(now).is_zero() && self.flashing.is_none();
+        let fg = match (lit, idle) {
+            (true, _) => colors.background,
+            (false, true) => colors.date,
+            (false, false) => colors.text,
+        };
+        ctx.renderer.draw_text(ctx.conn, ctx.fonts, &self.text(now), m.font_main, region.x + m.px(4) as i16, m.text_y, fg)?;
+        damage.push(rect);
+        Ok(())
+    }
+
+    // Left starts or pauses, right resets, middle switches between countdown and stopwatch. Any click ends the
+    // flashing first.
+    fn click(&mut self, ctx: &mut Ctx, _region: Region, e: &ButtonPressEvent) -> Result<Change, Box<dyn std::error::Error>> {
+        if self.flashing.take().is_some() { return Ok(Change::Redraw); }
+        let now = Instant::now();
+        match e.detail {
+            1 => {
+                self.state = match (&self.state, self.mode) {
+                    (State::Stopped(d), TimerMode::Timer) if d.is_zero() => return Ok(Change::None),
+                    (State::Stopped(d), TimerMode::Timer) => State::Running(now + *d),
+                    (State::Stopped(d), TimerMode::Stopwatch) => State::Running(now.checked_sub(*d).unwrap_or(now)),
+                    (State::Running(_), _) => State::Stopped(self.value(now)),
+                };
+            }
+            2 => {
+                self.mode = if self.mode == TimerMode::Timer { TimerMode::Stopwatch } else { TimerMode::Timer };
+                self.state = State::Stopped(Duration::ZERO);
+            }
+            3 => self.state = State::Stopped(Duration::ZERO),
+            _ => return Ok(Change::None),
+        }
+        Ok(self.resized(ctx))
+    }
+
+    // Up adds a step to the countdown, down takes one off, also while it runs.
+    fn scroll(&mut self, ctx: &mut Ctx, _region: Region, _e: &ButtonPressEvent, delta: i32) -> Result<Change, Box<dyn std::error::Error>> {
+        if self.mode != TimerMode::Timer { return Ok(Change::None); }
+        self.flashing = None;
+        let now = Instant::now();
+        let step = Duration::from_secs(ctx.config.timer.step);
+        let value = self.value(now);
+        let value = if delta < 0 { (value + step).min(MAX_COUNTDOWN) } else { value.saturating_sub(step) };
+        self.state = match self.state {
+            State::Running(_) if !value.is_zero() => State::Running(now + value),
+            _ => State::Stopped(value),
+        };
+        Ok(self.resized(ctx))
+    }
+
+    fn tooltip(&mut self, _ctx: &Ctx, _region: Region, _x: i16) -> Option<Vec<String>> {
+        let mut lines = vec![match self.mode {
+            TimerMode::Timer => "Timer: scroll to set, click to start or pause".to_string(),
+            TimerMode::Stopwatch => "Stopwatch: click to start or pause".to_string(),
+        }];
+        lines.push("Right click resets, middle click switches mode".to_string());
+        if let Some((_, label)) = &self.flashing { lines.insert(0, format!("{} — click to dismiss", label)); }
+        let next = self.next_alarm.map(|(_, i)| i);
+        for (i, (time, label)) in self.alarms.iter().enumerate() {
+            let marker = if Some(i) == next { "  (next)" } else { "" };
+            lines.push(format!("{}  {}{}", time.format("%H:%M"), label, marker));
+        }
+        Some(lines)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        let now = Instant::now();
+        // When the text shown changes next.
+        let text = match self.state {
+            State::Running(end) if self.mode == TimerMode::Timer => {
+                let left = end.saturating_duration_since(now);
+                Some(end.checked_sub(Duration::from_secs(left.as_secs().saturating_sub((left.subsec_nanos() == 0) as u64))).unwrap_or(end))
+            }
+            State::Running(start) => Some(start + Duration::from_secs(now.saturating_duration_since(start).as_secs() + 1)),
+            State::Stopped(_) => None,
+        };
+        let blink = self.flashing.as_ref().map(|(since, _)| {
+            let blinks = (now - *since).as_millis() / BLINK.as_millis() + 1;
+            *since + BLINK * blinks as u32
+        });
+        let alarm = self.next_alarm.map(|(at, _)| wall_deadline(at));
+        [text, blink, alarm].into_iter().flatten().min()
+    }
+
+    fn tick(&mut self, ctx: &mut Ctx, now: Instant) -> Result<Change, Box<dyn std::error::Error>> {
+        if let State::Running(end) = self.state {
+            if self.mode == TimerMode::Timer && end <= now {
+                self.state = State::Stopped(Duration::ZERO);
+                self.go_off(ctx, now, "Timer".to_string());
+            }
+        }
+        if let Some((at, i)) = self.next_alarm {
+            if at <= SystemTime::now() {
+                let label = self.alarms[i].1.clone();
+                self.go_off(ctx, now, label);
+                self.schedule_alarm();
+            }
+        }
+        if self.flashing.as_ref().is_some_and(|(since, _)| now - *since >= Duration::from_secs(ctx.config.timer.flash)) {
+            self.flashing = None;
+        }
+        Ok(self.resized(ctx))
+    }
+
+    fn wants_attention(&self) -> bool {
+        self.flashing.is_some()
+    }
+}
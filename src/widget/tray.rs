@@ -0,0 +1,213 @@
+use std::os::unix::io::RawFd;
+
+use x11rb::protocol::xproto::*;
+use x11rb::protocol::Event;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::CURRENT_TIME;
+
+use super::{Change, Ctx, Widget};
+use crate::layout::Region;
+use crate::menu::{Menu, MenuAction};
+use crate::render::{self, IconKey};
+use crate::sni::{SniCommand, SniEvent, SniHost};
+use crate::tray::Tray;
+
+// The system tray selection owner, the StatusNotifier host and the menus of its items.
+pub struct TrayWidget {
+    tray: Tray,
+    sni: Option<SniHost>,
+    menu: Option<Menu>,
+    // What the last layout gave the tray, to tell a resize from a redraw.
+    width: u16,
+}
+
+impl TrayWidget {
+    pub fn new(ctx: &mut Ctx) -> Result<Self, Box<dyn std::error::Error>> {
+        let (conn, atoms, win, root) = (ctx.conn, ctx.atoms, ctx.win, ctx.root());
+        // Lets tray icons create windows with our visual instead of painting an opaque background.
+        conn.change_property32(PropMode::REPLACE, win, atoms._net_system_tray_visual, AtomEnum::VISUALID, &[ctx.visual.visual])?;
+        conn.set_selection_owner(win, atoms.net_system_tray_s0, CURRENT_TIME)?;
+        let tray_msg = ClientMessageEvent {
+            response_type: CLIENT_MESSAGE_EVENT,
+            format: 32, sequence: 0, window: root,
+            type_: atoms.manager,
+            data: [CURRENT_TIME, atoms.net_system_tray_s0, win, 0, 0].into(),
+        };
+        conn.send_event(false, root, EventMask::STRUCTURE_NOTIFY, tray_msg)?;
+
+        let tray = Tray::new(conn, ctx.screen_num, ctx.visual, ctx.config.colors.background, ctx.metrics)?;
+        // Without a session bus the panel still works, just with XEMBED icons only.
+        let sni = if ctx.config.tray.status_notifier {
+            SniHost::start(tray.icon_size(&ctx.config.tray) as u32, &ctx.config.tray.icon_theme).ok()
+        } else {
+            None
+        };
+        Ok(Self { tray, sni, menu: None, width: 0 })
+    }
+
+    fn resized(&self, ctx: &Ctx) -> Change {
+        if self.tray.width(&ctx.config.tray) != self.width { Change::Relayout } else { Change::Redraw }
+    }
+
+    fn close_menu(&mut self, ctx: &Ctx) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(menu) = self.menu.take() { menu.close(ctx.conn)?; }
+        Ok(())
+    }
+
+    // Menus open above the window that was clicked (the panel or the overflow popup).
+    fn send_click(&self, key: &str, e: &ButtonPressEvent) {
+        let Some(sni) = &self.sni else { return };
+        let key = key.to_string();
+        let (x, y) = (e.root_x, e.root_y);
+        let cmd = match e.detail {
+            1 => SniCommand::Activate { key, x, y },
+            2 => SniCommand::SecondaryActivate { key, x, y },
+            3 => SniCommand::ContextMenu { key, x, y: e.root_y - e.event_y },
+            4 | 5 => SniCommand::Scroll { key, delta: if e.detail == 4 { -1 } else { 1 }, horizontal: false },
+            6 | 7 => SniCommand::Scroll { key, delta: if e.detail == 6 { -1 } else { 1 }, horizontal: true },
+            _ => return,
+        };
+        sni.send(cmd);
+    }
+}
+
+impl Widget for TrayWidget {
+    fn measure(&mut self, ctx: &Ctx, _available: u16) -> u16 {
+        self.width = self.tray.width(&ctx.config.tray);
+        self.width
+    }
+
+    fn render(&mut self, ctx: &mut Ctx, region: Region, _full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>> {
+        let (colors, m) = (ctx.config.colors, ctx.metrics);
+        ctx.renderer.fill_background(ctx.conn, region.rect(m.panel_height), colors.background)?;
+
+        self.tray.layout(ctx.conn, &ctx.config.tray, ctx.win, region.x, ctx.panel_y, m.panel_height)?;
+        // SNI icons are square; after a scale change they keep their old size until the host has reloaded them.
+        let sni_size = |pixels: &[u8]| ((pixels.len() / 4) as f64).sqrt() as u16;
+        for (x, y, key, pixels) in self.tray.sni_icons(false) {
+            let size = sni_size(pixels);
+            ctx.renderer.draw_icon(ctx.conn, &IconKey::Sni(key.to_string()), pixels, size, size, x, y)?;
+        }
+        if self.tray.popup_open() {
+            for (x, y, _, pixels) in self.tray.sni_icons(true) {
+                let size = sni_size(pixels);
+                render::software::draw_icon_fast(ctx.conn, self.tray.popup(), ctx.gc, ctx.visual.depth, pixels, size, size, x, y, colors.background, ctx.render_buffer)?;
+            }
+        }
+        if let Some((arrow_start, arrow_end)) = self.tray.arrow_region() {
+            let cx = (arrow_start + arrow_end) / 2;
+            let cy = (m.panel_height / 2) as i16;
+            let (half, tip, base) = (m.px(5) as i16, m.px(3) as i16, m.px(2) as i16);
+            // Points up while collapsed, down while the popup is open.
+            let (tip_y, base_y) = if self.tray.popup_open() { (cy + tip, cy - base) } else { (cy - tip, cy + base) };
+            let arrow = [Point { x: cx - half, y: base_y }, Point { x: cx + half, y: base_y }, Point { x: cx, y: tip_y }];
+            ctx.renderer.fill_triangle(ctx.conn, arrow, colors.date)?;
+        }
+
+        damage.push(region.rect(m.panel_height));
+        Ok(())
+    }
+
+    fn click(&mut self, _ctx: &mut Ctx, _region: Region, e: &ButtonPressEvent) -> Result<Change, Box<dyn std::error::Error>> {
+        if let Some((start, end)) = self.tray.arrow_region() {
+            if e.event_x >= start && e.event_x <= end {
+                self.tray.toggle_popup();
+                return Ok(Change::Redraw);
+            }
+        }
+        if let Some(key) = self.tray.sni_at(false, e.event_x) {
+            self.send_click(key, e);
+        }
+        Ok(Change::None)
+    }
+
+    fn scroll(&mut self, _ctx: &mut Ctx, _region: Region, e: &ButtonPressEvent, _delta: i32) -> Result<Change, Box<dyn std::error::Error>> {
+        if let Some(key) = self.tray.sni_at(false, e.event_x) {
+            self.send_click(key, e);
+        }
+        Ok(Change::None)
+    }
+
+    fn event(&mut self, ctx: &mut Ctx, event: &Event) -> Result<Change, Box<dyn std::error::Error>> {
+        let menu_window = self.menu.as_ref().map(|m| m.window());
+        match event {
+            Event::Expose(e) if e.window == self.tray.popup() && e.count == 0 => return Ok(Change::Redraw),
+            Event::Expose(e) if Some(e.window) == menu_window && e.count == 0 => {
+                if let Some(menu) = &self.menu { menu.draw(ctx.conn, ctx.fonts, ctx.render_buffer)?; }
+            }
+            Event::ButtonPress(e) if Some(e.event) == menu_window => {
+                let menu = self.menu.as_mut().unwrap();
+                match menu.handle_click(ctx.conn, ctx.screen_num, ctx.fonts, e.event_x, e.event_y)? {
+                    MenuAction::None => menu.draw(ctx.conn, ctx.fonts, ctx.render_buffer)?,
+                    MenuAction::Close => self.close_menu(ctx)?,
+                    MenuAction::Clicked(id) => {
+                        let menu = self.menu.take().unwrap();
+                        if let Some(sni) = &self.sni { sni.send(SniCommand::MenuClicked { key: menu.key.clone(), id }); }
+                        menu.close(ctx.conn)?;
+                    }
+                }
+            }
+            Event::MotionNotify(e) if Some(e.event) == menu_window => {
+                let menu = self.menu.as_mut().unwrap();
+                if menu.handle_motion(e.event_x, e.event_y) {
+                    menu.draw(ctx.conn, ctx.fonts, ctx.render_buffer)?;
+                }
+            }
+            Event::ButtonPress(e) if e.event == self.tray.popup() => {
+                if let Some(key) = self.tray.sni_at(true, e.event_x) {
+                    self.send_click(key, e);
+                }
+            }
+            Event::ClientMessage(e) if e.type_ == ctx.atoms._net_system_tray_opcode => {
+                let data = e.data.as_data32();
+                if data[1] == 0 {
+                    self.tray.dock(ctx.conn, ctx.win, data[2])?;
+                    return Ok(self.resized(ctx));
+                }
+            }
+            Event::DestroyNotify(e) if self.tray.remove(e.window) => return Ok(self.resized(ctx)),
+            _ => {}
+        }
+        Ok(Change::None)
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        self.sni.as_ref().map(|s| s.fd())
+    }
+
+    fn fd_ready(&mut self, ctx: &mut Ctx) -> Result<Change, Box<dyn std::error::Error>> {
+        let mut change = Change::None;
+        let events = self.sni.as_ref().map(|s| s.poll_events()).unwrap_or_default();
+        for event in events {
+            match event {
+                SniEvent::Updated(item) => {
+                    ctx.renderer.forget_icon(ctx.conn, &IconKey::Sni(item.key.clone()))?;
+                    self.tray.update_sni(item);
+                    change = change.max(self.resized(ctx));
+                }
+                SniEvent::Removed(key) => {
+                    ctx.renderer.forget_icon(ctx.conn, &IconKey::Sni(key.clone()))?;
+                    self.tray.remove_sni(&key);
+                    if self.menu.as_ref().is_some_and(|m| m.key == key) { self.close_menu(ctx)?; }
+                    change = change.max(self.resized(ctx));
+                }
+                SniEvent::Menu { key, x, y, root } => {
+                    self.close_menu(ctx)?;
+                    let menu = Menu::open(ctx.conn, ctx.screen_num, ctx.visual, ctx.config.colors, ctx.metrics, ctx.fonts, key, root, x, y)?;
+                    self.menu = Some(menu);
+                }
+            }
+        }
+        Ok(change)
+    }
+
+    fn rescale(&mut self, ctx: &mut Ctx) -> Result<(), Box<dyn std::error::Error>> {
+        self.tray.set_metrics(ctx.metrics);
+        if let Some(sni) = &self.sni { sni.set_icon_size(self.tray.icon_size(&ctx.config.tray) as u32); }
+        self.close_menu(ctx)
+    }
+
+    fn has_popup(&self) -> bool {
+        self.menu.is_some() || self.tray.popup_open()
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{Local, Utc};
+use x11rb::protocol::xproto::Rectangle;
+
+use super::{next_boundary, wall_deadline, Change, Ctx, Widget};
+use crate::layout::Region;
+use crate::locale::{self, Names};
+use crate::text::calculate_text_width;
+use crate::tz::Zone;
+
+// Gap between two labelled clocks, at 96 DPI.
+const GAP: u16 = 12;
+
+// "NYC 09:14  TYO 22:14": a label and time per configured zone, on one line.
+pub struct WorldClock {
+    zones: Vec<(String, Zone)>,
+    // Where the panel is, for telling which zones are on another day.
+    local: Option<Zone>,
+    names: Option<Names>,
+    period: Duration,
+    shown_until: SystemTime,
+    width: u16,
+}
+
+impl WorldClock {
+    pub fn new(ctx: &Ctx) -> Result<Self, Box<dyn std::error::Error>> {
+        let cfg = &ctx.config.world_clock;
+        if cfg.zones.is_empty() { return Err("[world_clock] needs at least one entry in zones".into()); }
+        locale::validate(&cfg.format)?;
+        locale::validate(&cfg.tooltip_format)?;
+        let zones = cfg.zones.iter()
+            .map(|z| Ok((z.label.clone(), Zone::load(&z.timezone)?)))
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        let names = Names::configured(ctx.config.clock.locale.as_deref())?;
+        let period = Duration::from_secs(if locale::has_seconds(&cfg.format, names.as_ref()) { 1 } else { 60 });
+        Ok(Self { zones, local: Zone::local(), names, period, shown_until: SystemTime::now(), width: 0 })
+    }
+
+    // (label, time) per zone.
+    fn entries(&self, ctx: &Ctx) -> Vec<(&str, String)> {
+        let now = Utc::now();
+        self.zones.iter().map(|(label, zone)| {
+            let (time, abbr) = zone.to_local(now);
+            (label.as_str(), locale::format(&ctx.config.world_clock.format, &time, Some(abbr), self.names.as_ref()))
+        }).collect()
+    }
+
+    fn text_width(&self, ctx: &Ctx) -> u16 {
+        let m = ctx.metrics;
+        let entries = self.entries(ctx);
+        let text: f32 = entries.iter()
+            .map(|(label, time)| calculate_text_width(ctx.fonts, m.font_main, &format!("{} {}", label, time)))
+            .sum();
+        text as u16 + m.px(GAP) * entries.len().saturating_sub(1) as u16 + m.px(8)
+    }
+}
+
+// "UTC+9", "UTC−4", "UTC+5:30".
+fn utc_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '−' } else { '+' };
+    let (hours, minutes) = (seconds.abs() / 3600, seconds.abs() % 3600 / 60);
+    if minutes == 0 { format!("UTC{}{}", sign, hours) } else { format!("UTC{}{}:{:02}", sign, hours, minutes) }
+}
+
+impl Widget for WorldClock {
+    fn measure(&mut self, ctx: &Ctx, _available: u16) -> u16 {
+        self.width = self.text_width(ctx);
+        self.width
+    }
+
+    fn render(&mut self, ctx: &mut Ctx, region: Region, _full: bool, damage: &mut Vec<Rectangle>) -> Result<(), Box<dyn std::error::Error>> {
+        let (colors, m) = (ctx.config.colors, ctx.metrics);
+        ctx.renderer.fill_background(ctx.conn, region.rect(m.panel_height), colors.background)?;
+        self.shown_until = next_boundary(SystemTime::now(), self.period);
+
+        let mut x = region.x as f32 + m.px(4) as f32;
+        for (label, time) in self.entries(ctx) {
+            let label = format!("{} ", label);
+            ctx.renderer.draw_text(ctx.conn, ctx.fonts, &label, m.font_main, x as i16, m.text_y, colors.date)?;
+            x += calculate_text_width(ctx.fonts, m.font_main, &label);
+            ctx.renderer.draw_text(ctx.conn, ctx.fonts, &time, m.font_main, x as i16, m.text_y, colors.text)?;
+            x += calculate_text_width(ctx.fonts, m.font_main, &time) + m.px(GAP) as f32;
+        }
+
+        damage.push(region.rect(m.panel_height));
+        Ok(())
+    }
+
+    // Every zone with its date and time, UTC offset and abbreviation, and a marker when it is on another day
+    // than the panel's own zone.
+    fn tooltip(&mut self, ctx: &Ctx, _region: Region, _x: i16) -> Option<Vec<String>> {
+        let now = Utc::now();
+        let today = match &self.local {
+            Some(zone) => zone.to_local(now).0.date_naive(),
+            None => Local::now().date_naive(),
+        };
+        let lines = self.zones.iter().map(|(label, zone)| {
+            let (time, abbr) = zone.to_local(now);
+            let mut line = format!(
+                "{}  {}  {}",
+                label, locale::format(&ctx.config.world_clock.tooltip_format, &time, Some(abbr), self.names.as_ref()),
+                utc_offset(time.offset().local_minus_utc()),
+            );
+            // Zones without an abbreviation of their own have "+09" and the like, which the offset already says.
+            if !abbr.starts_with(['+', '-']) { line += &format!(" ({})", abbr); }
+            match (time.date_naive() - today).num_days() {
+                0 => {}
+                days => line += &format!("  {:+} day", days),
+            }
+            line
+        }).collect();
+        Some(lines)
+    }
+
+    fn next_tick(&self) -> Option<Instant> {
+        Some(wall_deadline(self.shown_until))
+    }
+
+    fn tick(&mut self, ctx: &mut Ctx, _now: Instant) -> Result<Change, Box<dyn std::error::Error>> {
+        let width = self.text_width(ctx);
+        if width > self.width || (width < self.width && self.period >= Duration::from_secs(60)) {
+            self.width = width;
+            return Ok(Change::Relayout);
+        }
+        Ok(Change::Redraw)
+    }
+}
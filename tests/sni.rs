@@ -0,0 +1,283 @@
+// The StatusNotifier watcher and host against a private bus: an item registers with the watcher the host serves,
+// the host reads its properties and reports it, and forwards clicks and menus back. Skipped where dbus-daemon
+// isn't installed.
+
+#[allow(dead_code)]
+#[path = "../src/icon_theme.rs"]
+mod icon_theme;
+#[allow(dead_code)]
+#[path = "../src/sni/mod.rs"]
+mod sni;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+
+const ICON_SIZE: u32 = 16;
+
+// Kills the bus when the test ends, passed or not.
+struct Bus(Child);
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+// A session bus of its own, and its address.
+fn start_bus() -> Option<(Bus, String)> {
+    let child = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::null())
+        .spawn().ok()?;
+    let mut bus = Bus(child);
+    let mut address = String::new();
+    BufReader::new(bus.0.stdout.take()?).read_line(&mut address).ok()?;
+    let address = address.trim().to_string();
+    (!address.is_empty()).then_some((bus, address))
+}
+
+#[derive(Default)]
+struct Item {
+    pixmaps: Vec<(i32, i32, Vec<u8>)>,
+    menu: Option<&'static str>,
+    // Methods called on the item, as "Name args".
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+#[zbus::interface(name = "org.kde.StatusNotifierItem")]
+impl Item {
+    fn activate(&self, x: i32, y: i32) {
+        self.calls.lock().unwrap().push(format!("Activate {} {}", x, y));
+    }
+
+    fn secondary_activate(&self, x: i32, y: i32) {
+        self.calls.lock().unwrap().push(format!("SecondaryActivate {} {}", x, y));
+    }
+
+    fn scroll(&self, delta: i32, orientation: &str) {
+        self.calls.lock().unwrap().push(format!("Scroll {} {}", delta, orientation));
+    }
+
+    fn context_menu(&self, x: i32, y: i32) {
+        self.calls.lock().unwrap().push(format!("ContextMenu {} {}", x, y));
+    }
+
+    #[zbus(property)]
+    fn id(&self) -> String {
+        "test-item".to_string()
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> String {
+        "Test item".to_string()
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> String {
+        "Passive".to_string()
+    }
+
+    #[zbus(property)]
+    fn icon_pixmap(&self) -> Vec<(i32, i32, Vec<u8>)> {
+        self.pixmaps.clone()
+    }
+
+    #[zbus(property)]
+    fn menu(&self) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(self.menu.unwrap_or("/")).unwrap()
+    }
+}
+
+// (id, properties, children), as dbusmenu.rs expects from GetLayout.
+type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+struct Menu;
+
+#[zbus::interface(name = "com.canonical.dbusmenu")]
+impl Menu {
+    fn get_layout(&self, _parent: i32, _depth: i32, _names: Vec<String>) -> (u32, Layout) {
+        let child = |id: i32, props: &[(&str, Value<'static>)]| {
+            let props: HashMap<String, Value> = props.iter().map(|(k, v)| (k.to_string(), v.clone())).collect();
+            OwnedValue::try_from(Value::from((id, props, Vec::<Value>::new()))).unwrap()
+        };
+        let children = vec![
+            child(1, &[("label", Value::from("_Open"))]),
+            child(2, &[("type", Value::from("separator"))]),
+            child(3, &[("label", Value::from("Hidden")), ("visible", Value::from(false))]),
+            child(4, &[("label", Value::from("Mute")), ("toggle-type", Value::from("checkmark")), ("toggle-state", Value::from(1i32))]),
+            child(5, &[("label", Value::from("Quit")), ("enabled", Value::from(false))]),
+        ];
+        (1, (0, HashMap::new(), children))
+    }
+}
+
+// Tests point DBUS_SESSION_BUS_ADDRESS at their own bus, so they take turns.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+struct Fixture {
+    host: sni::SniHost,
+    item: zbus::blocking::Connection,
+    key: String,
+    calls: Arc<Mutex<Vec<String>>>,
+    // Declared last so the connections close before the bus goes.
+    _bus: Bus,
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl Fixture {
+    // Serves `item` on a fresh bus, registers it and waits for the host's first report of it.
+    fn start(item: Item) -> Option<(Fixture, sni::SniItem)> {
+        let serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let Some((bus, address)) = start_bus() else {
+            eprintln!("dbus-daemon not available, skipping");
+            return None;
+        };
+        std::env::set_var("DBUS_SESSION_BUS_ADDRESS", &address);
+        let host = sni::SniHost::start(ICON_SIZE, "hicolor").expect("host starts");
+
+        let calls = item.calls.clone();
+        let conn = zbus::blocking::connection::Builder::address(address.as_str()).unwrap()
+            .serve_at("/StatusNotifierItem", item).unwrap()
+            .serve_at("/MenuBar", Menu).unwrap()
+            .build().unwrap();
+        let key = format!("{}/StatusNotifierItem", conn.unique_name().unwrap());
+        // As libappindicator does: just the path, the bus being the caller's.
+        conn.call_method(Some("org.kde.StatusNotifierWatcher"), "/StatusNotifierWatcher", Some("org.kde.StatusNotifierWatcher"), "RegisterStatusNotifierItem", &("/StatusNotifierItem",))
+            .expect("watcher accepts the item");
+
+        let fixture = Fixture { host, item: conn, key, calls, _bus: bus, _serial: serial };
+        let seen = fixture.wait_for("the host never reported the item", |event| match event {
+            sni::SniEvent::Updated(item) if item.key == fixture.key => Some(item),
+            _ => None,
+        });
+        Some((fixture, seen))
+    }
+
+    fn wait_for<T>(&self, what: &str, mut pick: impl FnMut(sni::SniEvent) -> Option<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(found) = self.host.poll_events().into_iter().find_map(&mut pick) { return found; }
+            assert!(Instant::now() < deadline, "{}", what);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    fn wait_for_calls(&self, count: usize) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let calls = self.calls.lock().unwrap().clone();
+            if calls.len() >= count { return calls; }
+            assert!(Instant::now() < deadline, "the item only saw {:?}", calls);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+// An ARGB32 pixmap of one colour.
+fn pixmap(size: i32, argb: [u8; 4]) -> (i32, i32, Vec<u8>) {
+    (size, size, argb.repeat((size * size) as usize))
+}
+
+#[test]
+fn host_sees_registered_item() {
+    let Some((fixture, seen)) = Fixture::start(Item::default()) else { return };
+    assert_eq!(seen.id, "test-item");
+    assert_eq!(seen.title, "Test item");
+    assert!(seen.passive);
+    assert!(seen.icon.is_none());
+
+    let reply = fixture.item.call_method(Some("org.kde.StatusNotifierWatcher"), "/StatusNotifierWatcher", Some("org.freedesktop.DBus.Properties"), "Get", &("org.kde.StatusNotifierWatcher", "RegisteredStatusNotifierItems"))
+        .unwrap();
+    let items: Vec<String> = reply.body().deserialize::<OwnedValue>().unwrap().try_into().unwrap();
+    assert_eq!(items, vec![fixture.key.clone()]);
+
+    // Leaving the bus unregisters it.
+    let Fixture { host, item, key, _bus, _serial, .. } = fixture;
+    drop(item);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if host.poll_events().iter().any(|e| matches!(e, sni::SniEvent::Removed(k) if *k == key)) { break; }
+        assert!(Instant::now() < deadline, "the host never dropped the item");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn commands_reach_the_item() {
+    let Some((fixture, _)) = Fixture::start(Item::default()) else { return };
+    let key = fixture.key.clone();
+    fixture.host.send(sni::SniCommand::Activate { key: key.clone(), x: 10, y: 20 });
+    fixture.host.send(sni::SniCommand::SecondaryActivate { key: key.clone(), x: 30, y: 40 });
+    fixture.host.send(sni::SniCommand::Scroll { key: key.clone(), delta: -120, horizontal: false });
+    fixture.host.send(sni::SniCommand::Scroll { key: key.clone(), delta: 1, horizontal: true });
+    // Without a Menu the item is asked to show its own.
+    fixture.host.send(sni::SniCommand::ContextMenu { key, x: 5, y: 6 });
+
+    assert_eq!(fixture.wait_for_calls(5), [
+        "Activate 10 20",
+        "SecondaryActivate 30 40",
+        "Scroll -120 vertical",
+        "Scroll 1 horizontal",
+        "ContextMenu 5 6",
+    ]);
+}
+
+#[test]
+fn pixmap_closest_to_icon_size() {
+    let item = Item {
+        pixmaps: vec![pixmap(8, [255, 255, 0, 0]), pixmap(32, [255, 0, 0, 255]), pixmap(16, [255, 0, 255, 0])],
+        ..Item::default()
+    };
+    let Some((_fixture, seen)) = Fixture::start(item) else { return };
+    let icon = seen.icon.expect("an icon from the pixmaps");
+    assert_eq!(icon.len(), (ICON_SIZE * ICON_SIZE * 4) as usize);
+    // The 16px green one, reordered from ARGB to RGBA.
+    for px in icon.chunks_exact(4) {
+        assert!(px[0] <= 1 && px[1] >= 254 && px[2] <= 1 && px[3] >= 254, "{:?}", px);
+    }
+}
+
+#[test]
+fn oversized_pixmaps_rejected() {
+    let item = Item {
+        pixmaps: vec![
+            (i32::MAX, i32::MAX, vec![255; 64]),
+            (65536, 65536, vec![255; 64]),
+            (4096, 1, vec![255; 4096 * 4]),
+            (-4, -4, vec![255; 64]),
+            // Shorter than its dimensions claim.
+            (4, 4, vec![255; 60]),
+        ],
+        ..Item::default()
+    };
+    let Some((_fixture, seen)) = Fixture::start(item) else { return };
+    assert_eq!(seen.id, "test-item");
+    assert!(seen.icon.is_none());
+}
+
+#[test]
+fn context_menu_fetches_layout() {
+    let item = Item { menu: Some("/MenuBar"), ..Item::default() };
+    let Some((fixture, _)) = Fixture::start(item) else { return };
+    fixture.host.send(sni::SniCommand::ContextMenu { key: fixture.key.clone(), x: 7, y: 8 });
+
+    let (x, y, root) = fixture.wait_for("the host never sent the menu", |event| match event {
+        sni::SniEvent::Menu { key, x, y, root } if key == fixture.key => Some((x, y, root)),
+        _ => None,
+    });
+    assert_eq!((x, y), (7, 8));
+    let children: Vec<_> = root.children.iter().map(|c| (c.id, c.label.as_str(), c.enabled, c.separator, c.toggle)).collect();
+    assert_eq!(children, [
+        (1, "Open", true, false, None),
+        (2, "", true, true, None),
+        (4, "Mute", true, false, Some(true)),
+        (5, "Quit", false, false, None),
+    ]);
+    assert!(fixture.calls.lock().unwrap().is_empty(), "the item was asked to show its own menu");
+}